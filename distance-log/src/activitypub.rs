@@ -0,0 +1,220 @@
+use crate::{
+    config::ActivityPubConfig,
+    domain::ChangelistEntry,
+    feed::{entry_summary_html, fetch_time},
+    http,
+    persistence::impls::file_json::write_file_atomically,
+};
+use anyhow::{Context, Error};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::{collections::HashSet, fs, path::Path};
+
+const ACTIVITY_STREAMS: &str = "https://www.w3.org/ns/activitystreams";
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+/// Writes the actor, its outbox of the latest changelist entries, a file for each of those notes,
+/// and the WebFinger response for the account. The files have no extension, so that their URLs
+/// match their IDs; they should be served as `application/activity+json`, and
+/// `.well-known/webfinger` from the root of the host, whatever the query. Notes no longer in the
+/// outbox are removed.
+///
+/// Remote servers can look the account up and read its outbox, but nothing is pushed to followers.
+pub fn write_outbox(
+    config: &ActivityPubConfig,
+    changelist: &[ChangelistEntry],
+) -> Result<(), Error> {
+    let actor = Actor::new(config)?;
+    let entries: Vec<_> = changelist.iter().rev().take(config.max_items).collect();
+    let public_key = match &config.public_key_path {
+        Some(path) => Some(fs::read_to_string(path).with_context(|| {
+            format!("Error reading ActivityPub public key '{}'", path.display())
+        })?),
+        None => None,
+    };
+
+    let directory = &config.directory;
+    let notes_directory = directory.join("notes");
+    fs::create_dir_all(&notes_directory)?;
+    fs::create_dir_all(directory.join(".well-known"))?;
+    for entry in &entries {
+        write_json(&notes_directory.join(entry.id()), &actor.note(entry))?;
+    }
+    write_json(&directory.join("outbox"), &actor.outbox(&entries))?;
+    write_json(&directory.join("actor"), &actor.actor(config, public_key.as_deref()))?;
+    write_json(&directory.join(".well-known").join("webfinger"), &actor.webfinger())?;
+
+    let listed: HashSet<_> = entries.iter().map(|entry| entry.id()).collect();
+    for file in fs::read_dir(&notes_directory)? {
+        let path = file?.path();
+        match path.file_name().and_then(|x| x.to_str()) {
+            Some(name) if listed.contains(name) => {}
+            _ => fs::remove_file(&path)
+                .with_context(|| format!("Error removing old note '{}'", path.display()))?,
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug)]
+struct Actor {
+    base_url: String,
+    id: String,
+    account: String,
+}
+
+impl Actor {
+    fn new(config: &ActivityPubConfig) -> Result<Self, Error> {
+        let base_url = config.base_url.trim_end_matches('/').to_owned();
+        let host = base_url
+            .split("://")
+            .nth(1)
+            .and_then(|x| x.split('/').next())
+            .filter(|x| !x.is_empty())
+            .with_context(|| format!("Invalid ActivityPub base URL '{}'", config.base_url))?;
+        let account = format!("{}@{}", config.username, host);
+
+        Ok(Actor { id: format!("{}/actor", base_url), base_url, account })
+    }
+
+    fn actor(&self, config: &ActivityPubConfig, public_key: Option<&str>) -> Value {
+        let mut actor = json!({
+            "@context": [ACTIVITY_STREAMS, "https://w3id.org/security/v1"],
+            "id": self.id,
+            "type": "Service",
+            "preferredUsername": config.username,
+            "name": config.name,
+            "summary": config.summary,
+            "inbox": format!("{}/inbox", self.base_url),
+            "outbox": format!("{}/outbox", self.base_url),
+        });
+        if let Some(public_key) = public_key {
+            actor["publicKey"] = json!({
+                "id": format!("{}#main-key", self.id),
+                "owner": self.id,
+                "publicKeyPem": public_key,
+            });
+        }
+
+        actor
+    }
+
+    fn outbox(&self, entries: &[&ChangelistEntry]) -> Value {
+        let activities: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                let note = self.note(entry);
+                json!({
+                    "id": format!("{}/activity", note["id"].as_str().unwrap_or_default()),
+                    "type": "Create",
+                    "actor": self.id,
+                    "published": note["published"],
+                    "to": [PUBLIC],
+                    "object": note,
+                })
+            })
+            .collect();
+
+        json!({
+            "@context": ACTIVITY_STREAMS,
+            "id": format!("{}/outbox", self.base_url),
+            "type": "OrderedCollection",
+            "totalItems": activities.len(),
+            "orderedItems": activities,
+        })
+    }
+
+    fn note(&self, entry: &ChangelistEntry) -> Value {
+        let published = fetch_time(entry).unwrap_or_else(Utc::now);
+        let attachments: Vec<_> = entry
+            .map_preview
+            .iter()
+            .map(|preview| {
+                json!({
+                    "type": "Image",
+                    "mediaType": http::image_media_type(preview),
+                    "url": preview,
+                    "name": entry.map_name,
+                })
+            })
+            .collect();
+
+        json!({
+            "@context": ACTIVITY_STREAMS,
+            "id": format!("{}/notes/{}", self.base_url, entry.id()),
+            "type": "Note",
+            "attributedTo": self.id,
+            "published": published.to_rfc3339(),
+            "url": entry.workshop_item_url(),
+            "to": [PUBLIC],
+            "content": entry_summary_html(entry),
+            "attachment": attachments,
+        })
+    }
+
+    fn webfinger(&self) -> Value {
+        json!({
+            "subject": format!("acct:{}", self.account),
+            "links": [{
+                "rel": "self",
+                "type": "application/activity+json",
+                "href": self.id,
+            }],
+        })
+    }
+}
+
+fn write_json(path: &Path, value: &Value) -> Result<(), Error> {
+    write_file_atomically(&serde_json::to_vec_pretty(value)?, path)
+        .with_context(|| format!("Error writing '{}'", path.display()))
+}
+
+#[test]
+fn test_outbox() {
+    use crate::test_util::{changelist_of, day, level_info, read_json};
+
+    let runs: Vec<_> = ["Broken Symmetry", "Lost Society", "Micro-Brew"]
+        .iter()
+        .enumerate()
+        .map(|(i, name)| vec![level_info(name, name, Some(("Seeker", 20000)), day(i as u32 + 1))])
+        .collect();
+    let mut changelist = changelist_of(&runs);
+    changelist[2].map_preview = Some("https://example.org/preview.png".to_owned());
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = ActivityPubConfig {
+        base_url: "https://example.org/distance-log/ap/".to_owned(),
+        username: "records".to_owned(),
+        name: "Distance world records".to_owned(),
+        summary: String::new(),
+        directory: dir.path().to_owned(),
+        max_items: 2,
+        public_key_path: None,
+    };
+    write_outbox(&config, &changelist).unwrap();
+
+    let read = |path: &str| read_json(dir.path().join(path));
+    let outbox = read("outbox");
+    assert_eq!(outbox["totalItems"], 2);
+    let note = &outbox["orderedItems"][0]["object"];
+    assert!(note["content"].as_str().unwrap().contains("Micro-Brew"));
+    assert_eq!(note["attachment"][0]["mediaType"], "image/png");
+    assert_eq!(note["published"], "2020-05-03T00:00:00+00:00");
+    assert_eq!(
+        note["id"],
+        format!("https://example.org/distance-log/ap/notes/{}", changelist[2].id())
+    );
+    assert_eq!(read(&format!("notes/{}", changelist[2].id()))["id"], note["id"]);
+
+    assert_eq!(read("actor")["outbox"], "https://example.org/distance-log/ap/outbox");
+    assert_eq!(read(".well-known/webfinger")["subject"], "acct:records@example.org");
+
+    // Notes that fall out of the outbox are removed
+    write_outbox(&ActivityPubConfig { max_items: 1, ..config }, &changelist).unwrap();
+    let notes: Vec<_> = fs::read_dir(dir.path().join("notes"))
+        .unwrap()
+        .map(|x| x.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(notes, [changelist[2].id()]);
+}
@@ -0,0 +1,65 @@
+use crate::persistence::{
+    impls::file_json::{load_file, write_file_atomically},
+    LoadError,
+};
+use anyhow::{Context, Error};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A directory of files named after the SHA-256 hash of their contents, so each distinct file is
+/// stored only once.
+#[derive(Debug, Clone)]
+pub struct ContentStore {
+    root: PathBuf,
+}
+
+impl ContentStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        ContentStore { root: root.into() }
+    }
+
+    /// Stores `data` if it isn't stored already, and returns its hash.
+    pub fn insert(&self, data: &[u8]) -> Result<String, Error> {
+        let hash = format!("{:x}", Sha256::digest(data));
+        let path = self.path(&hash);
+        if !path.exists() {
+            fs::create_dir_all(path.parent().unwrap())?;
+            write_file_atomically(data, &path)?;
+        }
+
+        Ok(hash)
+    }
+
+    pub fn path(&self, hash: &str) -> PathBuf {
+        self.root.join(&hash[..2]).join(hash)
+    }
+}
+
+/// Loads an archive index, treating a missing index as an empty one.
+pub fn load_index<T>(path: &Path) -> Result<Vec<T>, Error>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    match load_file(path) {
+        Ok(x) => Ok(x),
+        Err(LoadError::DoesNotExist) => Ok(Vec::new()),
+        Err(e) => {
+            Err(e).with_context(|| format!("Error loading archive index '{}'", path.display()))
+        }
+    }
+}
+
+#[test]
+fn test_content_store_deduplicates() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = ContentStore::new(dir.path());
+
+    let hash = store.insert(b"ghost").unwrap();
+    assert_eq!(store.insert(b"ghost").unwrap(), hash);
+    assert_ne!(store.insert(b"other ghost").unwrap(), hash);
+    assert_eq!(fs::read(store.path(&hash)).unwrap(), b"ghost");
+}
@@ -0,0 +1,56 @@
+use crate::{
+    archive, backend::impls::throttled::ThrottleStats, persistence::impls::file_json::save_file,
+};
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+/// How many runs' worth of stats are kept.
+const MAX_RUNS: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendStats {
+    pub backend: String,
+    pub requests: u64,
+    pub errors: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunHealth {
+    pub timestamp: DateTime<Utc>,
+    pub backends: Vec<BackendStats>,
+
+    /// The effective request limits during the run. `None` for runs without rate limiting, or
+    /// recorded before it existed.
+    #[serde(default)]
+    pub throttle: Option<ThrottleStats>,
+}
+
+/// Appends this run's stats to the health history at `path`, and returns each backend's error
+/// rate over the runs in the history.
+pub fn record_run(
+    path: &Path,
+    stats: Vec<BackendStats>,
+    throttle: Option<ThrottleStats>,
+) -> Result<Vec<(String, f64)>, Error> {
+    let mut history: Vec<RunHealth> = archive::load_index(path)?;
+    history.push(RunHealth { timestamp: Utc::now(), backends: stats, throttle });
+    if history.len() > MAX_RUNS {
+        history.drain(..history.len() - MAX_RUNS);
+    }
+    save_file(&history, path)?;
+
+    let mut totals: BTreeMap<&str, (u64, u64)> = BTreeMap::new();
+    for stats in history.iter().flat_map(|run| &run.backends) {
+        let total = totals.entry(&stats.backend).or_default();
+        total.0 += stats.requests;
+        total.1 += stats.errors;
+    }
+
+    Ok(totals
+        .into_iter()
+        .filter(|(_, (requests, _))| *requests > 0)
+        .map(|(backend, (requests, errors))| (backend.to_owned(), errors as f64 / requests as f64))
+        .collect())
+}
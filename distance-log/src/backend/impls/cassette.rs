@@ -0,0 +1,383 @@
+use crate::{
+    backend::{Backend, LeaderboardResponse, Unsupported, WorkshopResponse},
+    persistence::impls::file_json::{load_file, write_file_atomically},
+};
+use anyhow::{format_err, Context, Error};
+use chrono::{DateTime, Utc};
+use futures::{future::LocalBoxFuture, prelude::*, stream::LocalBoxStream};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    path::Path,
+    rc::Rc,
+};
+
+const NAME: &str = "cassette";
+
+/// Every leaderboard and workshop response received during a run, in a form that can be replayed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Cassette {
+    /// When the recorded run started. `None` in cassettes recorded before this was.
+    #[serde(default)]
+    started: Option<DateTime<Utc>>,
+
+    leaderboard_ranges: Vec<RecordedRange>,
+    workshop_levels: Vec<Recorded<WorkshopResponse>>,
+
+    #[serde(default)]
+    recent_workshop_levels: Vec<Recorded<WorkshopResponse>>,
+
+    /// How the run enumerated workshop levels. `None` in cassettes recorded before this was.
+    #[serde(default)]
+    workshop_enumeration: Option<WorkshopEnumeration>,
+}
+
+/// The first kind of workshop enumeration a run asked for. An incremental enumeration may be
+/// followed by a full one if the backend can't do it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkshopEnumeration {
+    Full,
+    Incremental { since: DateTime<Utc> },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedRange {
+    leaderboard_name: String,
+    start: u32,
+    end: u32,
+    response: Recorded<LeaderboardResponse>,
+
+    /// When the response arrived. `None` in cassettes recorded before this was.
+    #[serde(default)]
+    fetched_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Recorded<T> {
+    Ok(T),
+    Unsupported(String),
+    Err(String),
+}
+
+/// Passes requests through to another backend, adding every response to a cassette.
+#[derive(Debug)]
+pub struct Recorder {
+    inner: Rc<dyn Backend>,
+    cassette: Rc<RefCell<Cassette>>,
+}
+
+/// A leaderboard name, and the first and last rank requested from it.
+type RangeKey = (String, u32, u32);
+
+/// Serves the responses from a cassette, in the order they were recorded, and tells the update
+/// procedure when the recorded run happened so that the replay makes the same decisions.
+#[derive(Debug)]
+pub struct Replayer {
+    leaderboard_ranges: RefCell<HashMap<RangeKey, VecDeque<Recorded<LeaderboardResponse>>>>,
+    workshop_levels: Vec<Recorded<WorkshopResponse>>,
+    recent_workshop_levels: Vec<Recorded<WorkshopResponse>>,
+    started: Option<DateTime<Utc>>,
+    workshop_enumeration: Option<WorkshopEnumeration>,
+
+    /// Every recorded leaderboard, with when its last response arrived, if that was recorded.
+    leaderboards: HashMap<String, Option<DateTime<Utc>>>,
+}
+
+impl Cassette {
+    /// An empty cassette for a run that started at `started`.
+    pub fn new(started: DateTime<Utc>) -> Self {
+        Cassette { started: Some(started), ..Cassette::default() }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        load_file(path).with_context(|| format!("Error loading cassette '{}'", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let serialized = serde_json::to_vec(self)?;
+        write_file_atomically(&serialized, path)
+            .with_context(|| format!("Error saving cassette '{}'", path.display()))
+    }
+}
+
+impl<T> Recorded<T> {
+    fn new(result: &Result<T, Error>) -> Self
+    where
+        T: Clone,
+    {
+        match result {
+            Ok(x) => Recorded::Ok(x.clone()),
+            Err(e) if e.is::<Unsupported>() => Recorded::Unsupported(e.to_string()),
+            Err(e) => Recorded::Err(format!("{:#}", e)),
+        }
+    }
+
+    fn into_result(self, operation: &'static str) -> Result<T, Error> {
+        match self {
+            Recorded::Ok(x) => Ok(x),
+            Recorded::Unsupported(_) => Err(Unsupported { backend: NAME, operation }.into()),
+            Recorded::Err(e) => Err(format_err!("{}", e)),
+        }
+    }
+}
+
+impl Recorder {
+    pub fn new(inner: Rc<dyn Backend>, cassette: Rc<RefCell<Cassette>>) -> Self {
+        Recorder { inner, cassette }
+    }
+
+    fn record_workshop_levels<'a>(
+        &'a self,
+        levels: LocalBoxStream<'a, LocalBoxFuture<'a, Result<WorkshopResponse, Error>>>,
+        recording: fn(&mut Cassette) -> &mut Vec<Recorded<WorkshopResponse>>,
+    ) -> LocalBoxStream<'a, LocalBoxFuture<'a, Result<WorkshopResponse, Error>>> {
+        // The futures may complete in any order, so reserve each one's slot up front to keep the
+        // recording in stream order
+        let slots = Rc::new(RefCell::new(Vec::new()));
+        levels
+            .map(move |level| {
+                let slots = slots.clone();
+                let index = {
+                    let mut slots = slots.borrow_mut();
+                    slots.push(None);
+                    slots.len() - 1
+                };
+
+                level
+                    .inspect(move |result| {
+                        let mut slots = slots.borrow_mut();
+                        slots[index] = Some(Recorded::new(result));
+
+                        // Only completed results in a row are moved to the cassette
+                        let mut cassette = self.cassette.borrow_mut();
+                        let recording = recording(&mut cassette);
+                        let recorded = recording.len();
+                        let ready = slots[recorded..].iter().take_while(|x| x.is_some()).count();
+                        recording
+                            .extend(slots[recorded..recorded + ready].iter().flatten().cloned());
+                    })
+                    .boxed_local()
+            })
+            .boxed_local()
+    }
+}
+
+impl Backend for Recorder {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn get_leaderboard_range(
+        &self,
+        leaderboard_name: String,
+        start: u32,
+        end: u32,
+    ) -> LocalBoxFuture<'_, Result<LeaderboardResponse, Error>> {
+        async move {
+            let result =
+                self.inner.get_leaderboard_range(leaderboard_name.clone(), start, end).await;
+            self.cassette.borrow_mut().leaderboard_ranges.push(RecordedRange {
+                leaderboard_name,
+                start,
+                end,
+                response: Recorded::new(&result),
+                fetched_at: Some(Utc::now()),
+            });
+
+            result
+        }
+        .boxed_local()
+    }
+
+    fn get_all_workshop_sprint_challenge_stunt_levels(
+        &self,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>> {
+        self.cassette.borrow_mut().workshop_enumeration.get_or_insert(WorkshopEnumeration::Full);
+        self.record_workshop_levels(
+            self.inner.get_all_workshop_sprint_challenge_stunt_levels(),
+            |cassette| &mut cassette.workshop_levels,
+        )
+    }
+
+    fn get_recently_updated_workshop_levels(
+        &self,
+        since: DateTime<Utc>,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>> {
+        self.cassette
+            .borrow_mut()
+            .workshop_enumeration
+            .get_or_insert(WorkshopEnumeration::Incremental { since });
+        self.record_workshop_levels(
+            self.inner.get_recently_updated_workshop_levels(since),
+            |cassette| &mut cassette.recent_workshop_levels,
+        )
+    }
+
+    fn download_ugc(&self, ugc_id: u64) -> LocalBoxFuture<'_, Result<Vec<u8>, Error>> {
+        self.inner.download_ugc(ugc_id)
+    }
+}
+
+impl Replayer {
+    pub fn new(cassette: Cassette) -> Self {
+        let mut leaderboard_ranges: HashMap<_, VecDeque<_>> = HashMap::new();
+        let mut leaderboards = HashMap::new();
+        for range in cassette.leaderboard_ranges {
+            leaderboards.insert(range.leaderboard_name.clone(), range.fetched_at);
+            leaderboard_ranges
+                .entry((range.leaderboard_name, range.start, range.end))
+                .or_default()
+                .push_back(range.response);
+        }
+
+        Replayer {
+            leaderboard_ranges: RefCell::new(leaderboard_ranges),
+            workshop_levels: cassette.workshop_levels,
+            recent_workshop_levels: cassette.recent_workshop_levels,
+            started: cassette.started,
+            workshop_enumeration: cassette.workshop_enumeration,
+            leaderboards,
+        }
+    }
+
+    /// When the recorded run started.
+    pub fn started(&self) -> Option<DateTime<Utc>> {
+        self.started
+    }
+
+    /// How the recorded run enumerated workshop levels.
+    pub fn workshop_enumeration(&self) -> Option<WorkshopEnumeration> {
+        self.workshop_enumeration
+    }
+
+    /// Whether the recorded run fetched the leaderboard.
+    pub fn has_leaderboard(&self, leaderboard_name: &str) -> bool {
+        self.leaderboards.contains_key(leaderboard_name)
+    }
+
+    /// When the recorded run last got a response for the leaderboard.
+    pub fn fetched_at(&self, leaderboard_name: &str) -> Option<DateTime<Utc>> {
+        self.leaderboards.get(leaderboard_name).copied().flatten()
+    }
+
+    fn replay_workshop_levels<'a>(
+        levels: &'a [Recorded<WorkshopResponse>],
+        operation: &'static str,
+    ) -> LocalBoxStream<'a, LocalBoxFuture<'a, Result<WorkshopResponse, Error>>> {
+        stream::iter(levels.iter().cloned())
+            .map(move |recorded| future::ready(recorded.into_result(operation)).boxed_local())
+            .boxed_local()
+    }
+}
+
+impl Backend for Replayer {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn get_leaderboard_range(
+        &self,
+        leaderboard_name: String,
+        start: u32,
+        end: u32,
+    ) -> LocalBoxFuture<'_, Result<LeaderboardResponse, Error>> {
+        let recorded = self
+            .leaderboard_ranges
+            .borrow_mut()
+            .get_mut(&(leaderboard_name.clone(), start, end))
+            .and_then(|responses| responses.pop_front());
+        let result = match recorded {
+            Some(x) => x.into_result("Querying leaderboards"),
+            None => Err(format_err!(
+                "The cassette has no response for entries {} to {} of leaderboard '{}'",
+                start,
+                end,
+                leaderboard_name
+            )),
+        };
+
+        future::ready(result).boxed_local()
+    }
+
+    fn get_all_workshop_sprint_challenge_stunt_levels(
+        &self,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>> {
+        Self::replay_workshop_levels(&self.workshop_levels, "Workshop enumeration")
+    }
+
+    fn get_recently_updated_workshop_levels(
+        &self,
+        _since: DateTime<Utc>,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>> {
+        // Replays whatever the recorded run got, regardless of `since`
+        Self::replay_workshop_levels(
+            &self.recent_workshop_levels,
+            "Incremental workshop enumeration",
+        )
+    }
+
+    fn download_ugc(&self, _ugc_id: u64) -> LocalBoxFuture<'_, Result<Vec<u8>, Error>> {
+        let error = Unsupported { backend: NAME, operation: "Downloading UGC" };
+        future::err(error.into()).boxed_local()
+    }
+}
+
+#[test]
+fn test_replay() {
+    use async_std::task;
+    use chrono::TimeZone;
+
+    let response = |entry_count| LeaderboardResponse {
+        entries: Box::new([]),
+        entry_count: Some(entry_count),
+        source: Some("steamworks".to_owned()),
+    };
+    let started = Utc.with_ymd_and_hms(2020, 5, 1, 12, 0, 0).unwrap();
+    let range = |response, seconds| RecordedRange {
+        leaderboard_name: "Broken Symmetry_1_stable".to_owned(),
+        start: 1,
+        end: 2,
+        response,
+        fetched_at: Some(started + chrono::Duration::seconds(seconds)),
+    };
+    let cassette = Cassette {
+        started: Some(started),
+        leaderboard_ranges: vec![
+            range(Recorded::Ok(response(10)), 1),
+            range(Recorded::Err("timed out".to_owned()), 2),
+        ],
+        workshop_levels: vec![Recorded::Unsupported("not supported".to_owned())],
+        recent_workshop_levels: vec![],
+        workshop_enumeration: Some(WorkshopEnumeration::Full),
+    };
+    let serialized = serde_json::to_string(&cassette).unwrap();
+    let cassette: Cassette = serde_json::from_str(&serialized).unwrap();
+    let replayer = Replayer::new(cassette);
+
+    // The replay happens when, and the way, the recorded run did
+    assert_eq!(replayer.started(), Some(started));
+    assert_eq!(replayer.workshop_enumeration(), Some(WorkshopEnumeration::Full));
+    assert!(replayer.has_leaderboard("Broken Symmetry_1_stable"));
+    assert!(!replayer.has_leaderboard("Broken Symmetry_5_stable"));
+    assert_eq!(
+        replayer.fetched_at("Broken Symmetry_1_stable"),
+        Some(started + chrono::Duration::seconds(2))
+    );
+
+    // Repeated requests get the responses in the order they were recorded
+    let get_range = || {
+        task::block_on(replayer.get_leaderboard_range("Broken Symmetry_1_stable".to_owned(), 1, 2))
+    };
+    assert_eq!(get_range().unwrap().entry_count, Some(10));
+    assert_eq!(get_range().unwrap_err().to_string(), "timed out");
+    assert!(get_range().is_err());
+
+    let levels: Vec<_> = task::block_on(
+        replayer.get_all_workshop_sprint_challenge_stunt_levels().then(|x| x).collect::<Vec<_>>(),
+    );
+    assert_eq!(levels.len(), 1);
+    assert!(levels[0].as_ref().unwrap_err().is::<Unsupported>());
+}
@@ -0,0 +1,323 @@
+use crate::{
+    backend::{
+        Backend, LeaderboardEntry, LeaderboardResponse, NotFound, Unsupported, WorkshopResponse,
+        DISTANCE_APP_ID,
+    },
+    config::CommunityXmlConfig,
+    http,
+};
+use anyhow::{format_err, Context, Error};
+use async_std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use futures::{
+    future::LocalBoxFuture,
+    prelude::*,
+    stream::{FuturesOrdered, LocalBoxStream},
+};
+use log::warn;
+use roxmltree::{Document, Node};
+use std::{collections::HashMap, str::FromStr};
+
+const NAME: &str = "community_xml";
+
+/// Reads the public leaderboards on the Steam Community website, so no Steam client is needed.
+/// Workshop levels can't be enumerated this way.
+#[derive(Debug)]
+pub struct CommunityXml {
+    agent: ureq::Agent,
+    base_url: String,
+    leaderboards: Mutex<Option<Arc<HashMap<String, ListedLeaderboard>>>>,
+    player_names: Mutex<HashMap<u64, String>>,
+}
+
+/// A leaderboard as it appears in the list of all of the game's leaderboards.
+#[derive(Debug, Clone, Copy)]
+struct ListedLeaderboard {
+    id: u64,
+    entries: u32,
+}
+
+#[derive(Debug)]
+struct LeaderboardPage {
+    total_entries: u32,
+    entries: Vec<PageEntry>,
+}
+
+#[derive(Debug)]
+struct PageEntry {
+    steam_id: u64,
+    rank: i32,
+    score: i32,
+    ugc_id: Option<u64>,
+}
+
+impl CommunityXml {
+    pub fn new(config: &CommunityXmlConfig) -> Self {
+        CommunityXml {
+            agent: http::agent(),
+            base_url: config.base_url.trim_end_matches('/').to_owned(),
+            leaderboards: Mutex::new(None),
+            player_names: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The number of entries the leaderboard list gives for the leaderboard, as of when the list
+    /// was fetched, or `None` if it isn't listed. Backends that can't count entries themselves
+    /// use this.
+    pub async fn listed_entry_count(&self, leaderboard_name: &str) -> Result<Option<u32>, Error> {
+        let leaderboards = self.leaderboard_list().await?;
+        Ok(leaderboards.get(leaderboard_name).map(|leaderboard| leaderboard.entries))
+    }
+
+    async fn leaderboard_id(&self, leaderboard_name: &str) -> Result<u64, Error> {
+        let leaderboards = self.leaderboard_list().await?;
+        leaderboards
+            .get(leaderboard_name)
+            .map(|leaderboard| leaderboard.id)
+            .ok_or_else(|| NotFound { leaderboard_name: leaderboard_name.to_owned() }.into())
+    }
+
+    async fn leaderboard_list(&self) -> Result<Arc<HashMap<String, ListedLeaderboard>>, Error> {
+        // Hold the lock while fetching so the list is only fetched once
+        let mut leaderboards = self.leaderboards.lock().await;
+        match &*leaderboards {
+            Some(x) => Ok(x.clone()),
+            None => {
+                let url =
+                    format!("{}/stats/{}/leaderboards/?xml=1", self.base_url, DISTANCE_APP_ID);
+                let xml = http::get_string(&self.agent, url).await?;
+                let list = Arc::new(
+                    parse_leaderboard_list(&xml).context("Error parsing the leaderboard list")?,
+                );
+                *leaderboards = Some(list.clone());
+                Ok(list)
+            }
+        }
+    }
+
+    /// The player's profile name. A profile that can't be read shouldn't cost the whole
+    /// leaderboard, so the Steam ID stands in for the name for the rest of the run.
+    async fn player_name(&self, steam_id: u64) -> String {
+        if let Some(name) = self.player_names.lock().await.get(&steam_id) {
+            return name.clone();
+        }
+
+        let name = match self.fetch_player_name(steam_id).await {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Couldn't get the name of player {}: {:#}", steam_id, e);
+                steam_id.to_string()
+            }
+        };
+        self.player_names.lock().await.insert(steam_id, name.clone());
+
+        name
+    }
+
+    async fn fetch_player_name(&self, steam_id: u64) -> Result<String, Error> {
+        let url = format!("{}/profiles/{}/?xml=1", self.base_url, steam_id);
+        let xml = http::get_string(&self.agent, url).await?;
+        let doc = Document::parse(&xml)?;
+        Ok(child_text(doc.root_element(), "steamID")?.to_owned())
+    }
+}
+
+impl Backend for CommunityXml {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn get_leaderboard_range(
+        &self,
+        leaderboard_name: String,
+        start: u32,
+        end: u32,
+    ) -> LocalBoxFuture<'_, Result<LeaderboardResponse, Error>> {
+        async move {
+            let id = self.leaderboard_id(&leaderboard_name).await?;
+            let url = format!(
+                "{}/stats/{}/leaderboards/{}/?xml=1&start={}&end={}",
+                self.base_url, DISTANCE_APP_ID, id, start, end
+            );
+            let xml = http::get_string(&self.agent, url).await?;
+            let page = parse_leaderboard_page(&xml)
+                .with_context(|| format!("Error parsing leaderboard '{}'", leaderboard_name))?;
+
+            let entries: FuturesOrdered<_> = page
+                .entries
+                .into_iter()
+                .map(|entry| async move {
+                    LeaderboardEntry {
+                        steam_id: entry.steam_id,
+                        global_rank: entry.rank,
+                        score: entry.score,
+                        player_name: self.player_name(entry.steam_id).await,
+                        ugc_id: entry.ugc_id,
+                    }
+                })
+                .collect();
+
+            Ok(LeaderboardResponse {
+                entries: entries.collect::<Vec<_>>().await.into_boxed_slice(),
+                entry_count: Some(page.total_entries),
+                source: Some(NAME.to_owned()),
+            })
+        }
+        .boxed_local()
+    }
+
+    fn get_all_workshop_sprint_challenge_stunt_levels(
+        &self,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>> {
+        let error = Unsupported { backend: NAME, operation: "Workshop enumeration" };
+        stream::once(future::ready(future::err(error.into()).boxed_local())).boxed_local()
+    }
+
+    fn get_recently_updated_workshop_levels(
+        &self,
+        _since: DateTime<Utc>,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>> {
+        let error = Unsupported { backend: NAME, operation: "Incremental workshop enumeration" };
+        stream::once(future::ready(future::err(error.into()).boxed_local())).boxed_local()
+    }
+
+    fn download_ugc(&self, _ugc_id: u64) -> LocalBoxFuture<'_, Result<Vec<u8>, Error>> {
+        let error = Unsupported { backend: NAME, operation: "Downloading UGC" };
+        future::err(error.into()).boxed_local()
+    }
+}
+
+fn parse_leaderboard_list(xml: &str) -> Result<HashMap<String, ListedLeaderboard>, Error> {
+    let doc = Document::parse(xml)?;
+    doc.root_element()
+        .children()
+        .filter(|node| node.has_tag_name("leaderboard"))
+        .map(|node| {
+            let leaderboard = ListedLeaderboard {
+                id: parse_child(node, "lbid")?,
+                entries: parse_child(node, "entries")?,
+            };
+            Ok((child_text(node, "name")?.to_owned(), leaderboard))
+        })
+        .collect()
+}
+
+fn parse_leaderboard_page(xml: &str) -> Result<LeaderboardPage, Error> {
+    let doc = Document::parse(xml)?;
+    let root = doc.root_element();
+    let entries = match root.children().find(|node| node.has_tag_name("entries")) {
+        Some(entries) => entries
+            .children()
+            .filter(|node| node.has_tag_name("entry"))
+            .map(|node| {
+                // Entries without an attachment have a UGC ID of -1
+                let ugc_id = child_text(node, "ugcid")
+                    .ok()
+                    .and_then(|x| x.parse::<u64>().ok())
+                    .filter(|&x| x != 0 && x != u64::MAX);
+
+                Ok(PageEntry {
+                    steam_id: parse_child(node, "steamid")?,
+                    rank: parse_child(node, "rank")?,
+                    score: parse_child(node, "score")?,
+                    ugc_id,
+                })
+            })
+            .collect::<Result<_, Error>>()?,
+        None => Vec::new(),
+    };
+
+    Ok(LeaderboardPage { total_entries: parse_child(root, "totalLeaderboardEntries")?, entries })
+}
+
+fn child_text<'a>(node: Node<'a, '_>, tag_name: &str) -> Result<&'a str, Error> {
+    node.children()
+        .find(|child| child.has_tag_name(tag_name))
+        .map(|child| child.text().unwrap_or("").trim())
+        .ok_or_else(|| format_err!("missing <{}> element", tag_name))
+}
+
+fn parse_child<T>(node: Node<'_, '_>, tag_name: &str) -> Result<T, Error>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let text = child_text(node, tag_name)?;
+    text.parse().with_context(|| format!("invalid <{}> value '{}'", tag_name, text))
+}
+
+#[test]
+fn test_community_xml_backend() {
+    use crate::test_util::MockServer;
+    use async_std::task;
+
+    let server = MockServer::start(|request| match request.url.as_str() {
+        "/stats/233610/leaderboards/?xml=1" => {
+            (200, include_str!("../../../test_data/community_xml/leaderboards.xml").into())
+        }
+        "/stats/233610/leaderboards/1854012/?xml=1&start=1&end=2" => {
+            (200, include_str!("../../../test_data/community_xml/leaderboard.xml").into())
+        }
+        "/profiles/76561198043251234/?xml=1" => {
+            (200, include_str!("../../../test_data/community_xml/profile_1.xml").into())
+        }
+        "/profiles/76561198087654321/?xml=1" => {
+            (200, include_str!("../../../test_data/community_xml/profile_2.xml").into())
+        }
+        _ => (404, String::new()),
+    });
+    let backend = CommunityXml::new(&CommunityXmlConfig { base_url: server.url() });
+
+    let response =
+        task::block_on(backend.get_leaderboard_range("Broken Symmetry_1_stable".to_owned(), 1, 2))
+            .unwrap();
+    assert_eq!(response.entry_count, Some(4012));
+    assert_eq!(response.entries.len(), 2);
+    assert_eq!(response.entries[0].steam_id, 76561198043251234);
+    assert_eq!(response.entries[0].player_name, "Seeker");
+    assert_eq!(response.entries[0].score, 23520);
+    assert_eq!(response.entries[0].ugc_id, Some(930612735411823451));
+    assert_eq!(response.entries[1].global_rank, 2);
+    assert_eq!(response.entries[1].player_name, "Runner & Co");
+    assert_eq!(response.entries[1].ugc_id, None);
+
+    assert!(task::block_on(backend.get_leaderboard_range("Nonexistent_1_stable".to_owned(), 1, 2))
+        .is_err());
+
+    // Entry counts can also be read off the leaderboard list
+    let count = |name: &str| task::block_on(backend.listed_entry_count(name)).unwrap();
+    assert_eq!(count("Broken Symmetry_5_stable"), Some(2211));
+    assert_eq!(count("Nonexistent_1_stable"), None);
+
+    // The leaderboard list and player names are only fetched once
+    let requests = server.requests();
+    assert_eq!(requests.iter().filter(|r| r.url.contains("leaderboards/?xml=1")).count(), 1);
+    assert_eq!(requests.len(), 4);
+
+    // A profile that can't be read doesn't fail the leaderboard, and isn't asked for again
+    let server = MockServer::start(|request| match request.url.as_str() {
+        "/stats/233610/leaderboards/?xml=1" => {
+            (200, include_str!("../../../test_data/community_xml/leaderboards.xml").into())
+        }
+        "/stats/233610/leaderboards/1854012/?xml=1&start=1&end=2" => {
+            (200, include_str!("../../../test_data/community_xml/leaderboard.xml").into())
+        }
+        "/profiles/76561198043251234/?xml=1" => {
+            (200, include_str!("../../../test_data/community_xml/profile_1.xml").into())
+        }
+        _ => (500, String::new()),
+    });
+    let backend = CommunityXml::new(&CommunityXmlConfig { base_url: server.url() });
+    for _ in 0..2 {
+        let response = task::block_on(backend.get_leaderboard_range(
+            "Broken Symmetry_1_stable".to_owned(),
+            1,
+            2,
+        ))
+        .unwrap();
+        assert_eq!(response.entries[0].player_name, "Seeker");
+        assert_eq!(response.entries[1].player_name, "76561198087654321");
+    }
+    let requests = server.requests();
+    assert_eq!(requests.iter().filter(|r| r.url.starts_with("/profiles/")).count(), 2);
+}
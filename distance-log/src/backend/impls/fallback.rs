@@ -0,0 +1,261 @@
+use crate::backend::{
+    health::BackendStats, Backend, LeaderboardResponse, NotFound, Unsupported, WorkshopResponse,
+};
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use futures::{future::LocalBoxFuture, prelude::*, stream::LocalBoxStream};
+use log::warn;
+use std::{cell::RefCell, rc::Rc};
+
+const NAME: &str = "fallback";
+
+/// After this many consecutive failures, a backend is skipped for the rest of the run unless it is
+/// the last one in the chain.
+const MAX_CONSECUTIVE_FAILURES: u32 = 20;
+
+type WorkshopStream<'a> = LocalBoxStream<'a, LocalBoxFuture<'a, Result<WorkshopResponse, Error>>>;
+
+/// Tries each request on a chain of backends in order, until one of them succeeds.
+#[derive(Debug)]
+pub struct Fallback {
+    backends: Vec<Rc<dyn Backend>>,
+    state: RefCell<Vec<BackendState>>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct BackendState {
+    requests: u64,
+    errors: u64,
+    consecutive_failures: u32,
+}
+
+impl Fallback {
+    pub fn new(backends: Vec<Rc<dyn Backend>>) -> Self {
+        assert!(!backends.is_empty(), "a fallback chain needs at least one backend");
+
+        let state = RefCell::new(vec![BackendState::default(); backends.len()]);
+        Fallback { backends, state }
+    }
+
+    /// The number of requests made to, and failed by, each backend in the chain so far.
+    pub fn stats(&self) -> Vec<BackendStats> {
+        self.backends
+            .iter()
+            .zip(self.state.borrow().iter())
+            .map(|(backend, state)| BackendStats {
+                backend: backend.name().to_owned(),
+                requests: state.requests,
+                errors: state.errors,
+            })
+            .collect()
+    }
+
+    fn should_skip(&self, i: usize) -> bool {
+        i + 1 < self.backends.len()
+            && self.state.borrow()[i].consecutive_failures >= MAX_CONSECUTIVE_FAILURES
+    }
+
+    /// Records the outcome of a request to the `i`th backend. Returns whether the request
+    /// succeeded. A leaderboard the backend doesn't know about is neither a success nor a failure:
+    /// the next backend is asked, but this one isn't held responsible.
+    fn record<T>(&self, i: usize, result: &Result<T, Error>) -> bool {
+        let mut state = self.state.borrow_mut();
+        let state = &mut state[i];
+        match result {
+            Ok(_) => {
+                state.requests += 1;
+                state.consecutive_failures = 0;
+                true
+            }
+            Err(e) if e.is::<Unsupported>() => false,
+            Err(e) if e.is::<NotFound>() => {
+                state.requests += 1;
+                state.consecutive_failures = 0;
+                false
+            }
+            Err(e) => {
+                state.requests += 1;
+                state.errors += 1;
+                state.consecutive_failures += 1;
+                if state.consecutive_failures == MAX_CONSECUTIVE_FAILURES
+                    && i + 1 < self.backends.len()
+                {
+                    warn!(
+                        "The {} backend failed {} times in a row; skipping it from now on. Last \
+                         error: {}",
+                        self.backends[i].name(),
+                        MAX_CONSECUTIVE_FAILURES,
+                        e
+                    );
+                }
+
+                false
+            }
+        }
+    }
+
+    async fn first_success<'a, T, F>(&'a self, f: F) -> Result<T, Error>
+    where
+        F: Fn(&'a dyn Backend) -> LocalBoxFuture<'a, Result<T, Error>>,
+    {
+        let mut last_error = None;
+        for (i, backend) in self.backends.iter().enumerate() {
+            if self.should_skip(i) {
+                continue;
+            }
+
+            let result = f(&**backend).await;
+            if self.record(i, &result) {
+                return result;
+            }
+
+            if let Err(e) = result {
+                last_error = Some(most_informative(last_error, e));
+            }
+        }
+
+        Err(last_error
+            .unwrap_or_else(|| Unsupported { backend: NAME, operation: "This request" }.into()))
+    }
+
+    /// Streams workshop levels from the first backend whose first result isn't an error.
+    fn first_successful_stream<'a>(
+        &'a self,
+        operation: &'static str,
+        get: impl Fn(&'a dyn Backend) -> WorkshopStream<'a> + 'a,
+    ) -> WorkshopStream<'a> {
+        // A stream can't be retried halfway through, so a backend is only given up on if its very
+        // first result is an error.
+        async move {
+            let mut last_error = None;
+            for (i, backend) in self.backends.iter().enumerate() {
+                if self.should_skip(i) {
+                    continue;
+                }
+
+                let mut levels = get(backend.as_ref());
+                let first = match levels.next().await {
+                    Some(x) => x.await.map(Some),
+                    None => Ok(None),
+                };
+                self.record(i, &first);
+
+                match first {
+                    Ok(Some(first)) => {
+                        let levels = levels.map(move |level| {
+                            level
+                                .inspect(move |x| {
+                                    self.record(i, x);
+                                })
+                                .boxed_local()
+                        });
+                        return stream::once(future::ready(future::ok(first).boxed_local()))
+                            .chain(levels)
+                            .boxed_local();
+                    }
+                    Ok(None) => return stream::empty().boxed_local(),
+                    Err(e) => last_error = Some(most_informative(last_error, e)),
+                }
+            }
+
+            let error =
+                last_error.unwrap_or_else(|| Unsupported { backend: NAME, operation }.into());
+            stream::once(future::ready(future::err(error).boxed_local())).boxed_local()
+        }
+        .flatten_stream()
+        .boxed_local()
+    }
+}
+
+impl Backend for Fallback {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn get_leaderboard_range(
+        &self,
+        leaderboard_name: String,
+        start: u32,
+        end: u32,
+    ) -> LocalBoxFuture<'_, Result<LeaderboardResponse, Error>> {
+        self.first_success(move |backend| {
+            backend.get_leaderboard_range(leaderboard_name.clone(), start, end)
+        })
+        .boxed_local()
+    }
+
+    fn get_all_workshop_sprint_challenge_stunt_levels(
+        &self,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>> {
+        self.first_successful_stream("Workshop enumeration", |backend| {
+            backend.get_all_workshop_sprint_challenge_stunt_levels()
+        })
+    }
+
+    fn get_recently_updated_workshop_levels(
+        &self,
+        since: DateTime<Utc>,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>> {
+        self.first_successful_stream("Incremental workshop enumeration", move |backend| {
+            backend.get_recently_updated_workshop_levels(since)
+        })
+    }
+
+    fn download_ugc(&self, ugc_id: u64) -> LocalBoxFuture<'_, Result<Vec<u8>, Error>> {
+        self.first_success(move |backend| backend.download_ugc(ugc_id)).boxed_local()
+    }
+}
+
+/// Picks which of two errors to report, preferring real failures over `Unsupported`.
+fn most_informative(last: Option<Error>, new: Error) -> Error {
+    match last {
+        Some(last) if !last.is::<Unsupported>() || new.is::<Unsupported>() => last,
+        _ => new,
+    }
+}
+
+#[test]
+fn test_fallback_chain() {
+    use crate::test_util::MockBackend;
+    use async_std::task;
+
+    let fallback = Fallback::new(vec![
+        Rc::new(MockBackend::new("primary").down()),
+        Rc::new(MockBackend::new("backup").with_leaderboard("x", Vec::new())),
+    ]);
+    for _ in 0..MAX_CONSECUTIVE_FAILURES + 5 {
+        let response =
+            task::block_on(fallback.get_leaderboard_range("x".to_owned(), 1, 2)).unwrap();
+        assert_eq!(response.source.as_deref(), Some("backup"));
+    }
+
+    // The primary is skipped once it has failed too many times in a row
+    let stats = fallback.stats();
+    assert_eq!((stats[0].requests, stats[0].errors), (20, 20));
+    assert_eq!((stats[1].requests, stats[1].errors), (25, 0));
+
+    // Workshop enumeration isn't supported by either backend
+    let error = task::block_on(async {
+        fallback.get_all_workshop_sprint_challenge_stunt_levels().next().await.unwrap().await
+    })
+    .unwrap_err();
+    assert!(error.is::<Unsupported>());
+    assert_eq!(fallback.stats()[1].requests, 25);
+
+    // A leaderboard the primary doesn't know about isn't held against it
+    let fallback = Fallback::new(vec![
+        Rc::new(MockBackend::new("primary")),
+        Rc::new(MockBackend::new("backup").with_leaderboard("x", Vec::new())),
+    ]);
+    for _ in 0..MAX_CONSECUTIVE_FAILURES + 5 {
+        let response =
+            task::block_on(fallback.get_leaderboard_range("x".to_owned(), 1, 2)).unwrap();
+        assert_eq!(response.source.as_deref(), Some("backup"));
+    }
+    let stats = fallback.stats();
+    assert_eq!((stats[0].requests, stats[0].errors), (25, 0));
+
+    let error = task::block_on(fallback.get_leaderboard_range("y".to_owned(), 1, 2)).unwrap_err();
+    assert!(error.is::<NotFound>());
+    assert_eq!(fallback.stats()[1].errors, 0);
+}
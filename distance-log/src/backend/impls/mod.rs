@@ -0,0 +1,6 @@
+pub mod cassette;
+pub mod community_xml;
+pub mod fallback;
+pub mod steamworks;
+pub mod throttled;
+pub mod web_api;
@@ -0,0 +1,149 @@
+use crate::backend::{
+    impls::community_xml::CommunityXml, Backend, LeaderboardEntry, LeaderboardResponse,
+    Unsupported, WorkshopResponse,
+};
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use futures::{
+    future::LocalBoxFuture,
+    prelude::*,
+    stream::{FuturesOrdered, LocalBoxStream},
+};
+use log::warn;
+use std::{cell::Cell, rc::Rc};
+use steamworks::{ugc::MatchingUgcType, Client, InitError};
+
+const NAME: &str = "steamworks";
+
+/// Talks to Steam through a running Steam client. steamworks-rs doesn't expose leaderboard entry
+/// counts, so they are read off the Steam Community leaderboard list through `community_xml`
+/// instead.
+#[derive(Debug, Clone)]
+pub struct Steamworks {
+    client: Client,
+    community_xml: Rc<CommunityXml>,
+    entry_counts_unavailable: Cell<bool>,
+}
+
+impl Steamworks {
+    pub fn new(community_xml: Rc<CommunityXml>) -> Result<Self, InitError> {
+        Ok(Steamworks {
+            client: Client::init()?,
+            community_xml,
+            entry_counts_unavailable: Cell::new(false),
+        })
+    }
+
+    /// The leaderboard's entry count, unless the leaderboard list can't be fetched. That's only
+    /// reported, and tried, once.
+    async fn entry_count(&self, leaderboard_name: &str) -> Option<u32> {
+        if self.entry_counts_unavailable.get() {
+            return None;
+        }
+
+        match self.community_xml.listed_entry_count(leaderboard_name).await {
+            Ok(x) => x,
+            Err(e) => {
+                if !self.entry_counts_unavailable.replace(true) {
+                    warn!("Leaderboard entry counts are unavailable for this run: {:#}", e);
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Backend for Steamworks {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn get_leaderboard_range(
+        &self,
+        leaderboard_name: String,
+        start: u32,
+        end: u32,
+    ) -> LocalBoxFuture<'_, Result<LeaderboardResponse, Error>> {
+        async move {
+            let leaderboard = self.client.find_leaderboard(leaderboard_name.clone()).await?;
+
+            let entries: FuturesOrdered<_> = leaderboard
+                .download_global(start, end, 0)
+                .await
+                .into_iter()
+                .map(|entry| async move {
+                    let player_name = entry.steam_id.persona_name(&self.client).await;
+
+                    LeaderboardEntry {
+                        steam_id: entry.steam_id.into(),
+                        global_rank: entry.global_rank,
+                        score: entry.score,
+                        player_name,
+                        // steamworks-rs doesn't expose the entry's attached UGC
+                        ugc_id: None,
+                    }
+                })
+                .collect();
+
+            let response = LeaderboardResponse {
+                entries: entries.collect::<Vec<_>>().await.into_boxed_slice(),
+                entry_count: self.entry_count(&leaderboard_name).await,
+                source: Some(NAME.to_owned()),
+            };
+
+            Ok(response)
+        }
+        .boxed_local()
+    }
+
+    fn get_all_workshop_sprint_challenge_stunt_levels(
+        &self,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>> {
+        self.client
+            .query_all_ugc(MatchingUgcType::ItemsReadyToUse)
+            .match_any_tags()
+            .required_tags(["Sprint", "Challenge", "Stunt"].iter().copied())
+            .run()
+            .try_filter(|details| future::ready(!details.file_name.is_empty()))
+            .map(move |details| {
+                future::ready(details)
+                    .and_then(move |details| {
+                        let tags: Vec<_> = details.tags.iter().map(|s| s.to_owned()).collect();
+                        async move {
+                            let author_name =
+                                details.steam_id_owner.persona_name(&self.client).await;
+                            Ok(WorkshopResponse {
+                                published_file_id: details.published_file_id.into(),
+                                steam_id_owner: details.steam_id_owner.into(),
+                                file_name: details.file_name,
+                                title: details.title,
+                                score: details.score,
+                                tags: tags.into_boxed_slice(),
+                                author_name,
+                                preview_url: details.preview_url,
+                                // steamworks-rs doesn't expose either of these
+                                time_updated: None,
+                                file_ugc_id: None,
+                                source: Some(NAME.to_owned()),
+                            })
+                        }
+                    })
+                    .err_into()
+                    .boxed_local()
+            })
+            .boxed_local()
+    }
+
+    fn get_recently_updated_workshop_levels(
+        &self,
+        _since: DateTime<Utc>,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>> {
+        let error = Unsupported { backend: NAME, operation: "Incremental workshop enumeration" };
+        stream::once(future::ready(future::err(error.into()).boxed_local())).boxed_local()
+    }
+
+    fn download_ugc(&self, _ugc_id: u64) -> LocalBoxFuture<'_, Result<Vec<u8>, Error>> {
+        let error = Unsupported { backend: NAME, operation: "Downloading UGC" };
+        future::err(error.into()).boxed_local()
+    }
+}
@@ -0,0 +1,361 @@
+use crate::{
+    backend::{Backend, LeaderboardResponse, NotFound, Unsupported, WorkshopResponse},
+    config::RateLimitConfig,
+};
+use anyhow::Error;
+use async_std::task;
+use chrono::{DateTime, Utc};
+use futures::{channel::oneshot, future::LocalBoxFuture, prelude::*, stream::LocalBoxStream};
+use log::debug;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+/// Limits the rate of requests with a token bucket, and the number of requests in flight with a
+/// limit that grows while requests are fast and successful, and is cut when they slow down or
+/// fail (additive increase, multiplicative decrease).
+#[derive(Debug)]
+pub struct Limiter {
+    config: RateLimitConfig,
+    state: RefCell<LimiterState>,
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    tokens: f64,
+    last_refill: Instant,
+    concurrency_limit: f64,
+    in_flight: u32,
+    last_decrease: Option<Instant>,
+    stats: ThrottleStats,
+    total_latency: Duration,
+
+    /// Requests waiting for a concurrency slot, woken in order as slots free up.
+    waiters: VecDeque<oneshot::Sender<()>>,
+}
+
+/// What the limiter did over a run.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ThrottleStats {
+    pub requests_per_second: f64,
+    pub requests: u64,
+    pub slow_or_failed_requests: u64,
+    pub min_concurrency_limit: u32,
+    pub max_concurrency_limit: u32,
+    pub final_concurrency_limit: u32,
+    pub mean_latency_ms: u64,
+
+    /// Total time requests spent waiting for the limiter, summed over all requests.
+    pub waiting_secs: f64,
+}
+
+/// Passes requests through to another backend once the limiter allows them. Workshop enumeration
+/// and UGC downloads are passed through as is, since one request can return many levels and one
+/// download can take several requests; backends that send requests for them throttle those
+/// themselves.
+#[derive(Debug)]
+pub struct Throttled {
+    inner: Rc<dyn Backend>,
+    limiter: Rc<Limiter>,
+}
+
+enum Wait<'a> {
+    ForSlot(Waiter<'a>),
+    ForToken(Duration),
+}
+
+/// A request waiting for a concurrency slot. If it's dropped after being woken, the slot it was
+/// woken for is passed on to the next waiter.
+struct Waiter<'a> {
+    limiter: &'a Limiter,
+    receiver: oneshot::Receiver<()>,
+}
+
+/// A request allowed through by the limiter. Dropping it without calling `finish` counts as the
+/// request having failed.
+struct Permit<'a> {
+    limiter: &'a Limiter,
+    started: Instant,
+    finished: bool,
+}
+
+impl Limiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        let concurrency_limit =
+            config.initial_concurrency.max(config.min_concurrency).min(config.max_concurrency);
+        let state = LimiterState {
+            tokens: config.burst as f64,
+            last_refill: Instant::now(),
+            concurrency_limit: concurrency_limit as f64,
+            in_flight: 0,
+            last_decrease: None,
+            stats: ThrottleStats {
+                requests_per_second: config.requests_per_second,
+                min_concurrency_limit: concurrency_limit,
+                max_concurrency_limit: concurrency_limit,
+                final_concurrency_limit: concurrency_limit,
+                ..ThrottleStats::default()
+            },
+            total_latency: Duration::default(),
+            waiters: VecDeque::new(),
+        };
+
+        Limiter { config, state: RefCell::new(state) }
+    }
+
+    pub fn stats(&self) -> ThrottleStats {
+        self.state.borrow().stats.clone()
+    }
+
+    /// Sends `request` once the limiter allows it.
+    pub async fn throttle<T>(
+        &self,
+        request: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let permit = self.acquire().await;
+        let result = request.await;
+        permit.finish(&result);
+
+        result
+    }
+
+    async fn acquire(&self) -> Permit<'_> {
+        let waiting_since = Instant::now();
+        loop {
+            let wait = {
+                let mut state = self.state.borrow_mut();
+                let now = Instant::now();
+                let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+                state.tokens = (state.tokens + elapsed * self.config.requests_per_second)
+                    .min(self.config.burst as f64);
+                state.last_refill = now;
+
+                if state.in_flight >= state.concurrency_limit as u32 {
+                    let (sender, receiver) = oneshot::channel();
+                    state.waiters.push_back(sender);
+                    Wait::ForSlot(Waiter { limiter: self, receiver })
+                } else if state.tokens < 1. {
+                    Wait::ForToken(Duration::from_secs_f64(
+                        (1. - state.tokens) / self.config.requests_per_second,
+                    ))
+                } else {
+                    state.tokens -= 1.;
+                    state.in_flight += 1;
+                    state.stats.waiting_secs += waiting_since.elapsed().as_secs_f64();
+
+                    return Permit { limiter: self, started: now, finished: false };
+                }
+            };
+
+            match wait {
+                Wait::ForSlot(mut waiter) => {
+                    let _ = (&mut waiter.receiver).await;
+                }
+                Wait::ForToken(duration) => task::sleep(duration).await,
+            }
+        }
+    }
+
+    fn release(&self, latency: Duration, healthy: bool) {
+        let mut state = self.state.borrow_mut();
+        state.in_flight -= 1;
+        state.stats.requests += 1;
+        state.total_latency += latency;
+        state.stats.mean_latency_ms =
+            (state.total_latency.as_millis() / u128::from(state.stats.requests)) as u64;
+
+        let target_latency = Duration::from_millis(self.config.target_latency_ms);
+        let min = self.config.min_concurrency as f64;
+        let max = self.config.max_concurrency as f64;
+        if healthy && latency <= target_latency {
+            // Grows by about one per limit's worth of requests
+            state.concurrency_limit =
+                (state.concurrency_limit + 1. / state.concurrency_limit).min(max);
+        } else {
+            state.stats.slow_or_failed_requests += 1;
+
+            // Requests in flight at the same time tend to fail together, so only back off once
+            // per target latency
+            let now = Instant::now();
+            let can_decrease = match state.last_decrease {
+                Some(x) => now.duration_since(x) > target_latency,
+                None => true,
+            };
+            if can_decrease {
+                state.concurrency_limit = (state.concurrency_limit * 0.5).max(min);
+                state.last_decrease = Some(now);
+                debug!(
+                    "Backing off to {} concurrent requests after a {} request",
+                    state.concurrency_limit as u32,
+                    if healthy { "slow" } else { "failed" }
+                );
+            }
+        }
+
+        let limit = state.concurrency_limit as u32;
+        let stats = &mut state.stats;
+        stats.final_concurrency_limit = limit;
+        stats.min_concurrency_limit = stats.min_concurrency_limit.min(limit);
+        stats.max_concurrency_limit = stats.max_concurrency_limit.max(limit);
+
+        state.wake_waiters();
+    }
+}
+
+impl LimiterState {
+    /// Wakes as many waiting requests as there are free concurrency slots.
+    fn wake_waiters(&mut self) {
+        let mut free = (self.concurrency_limit as u32).saturating_sub(self.in_flight);
+        while free > 0 {
+            match self.waiters.pop_front() {
+                // A waiter that was dropped in the meantime doesn't take a slot
+                Some(waiter) => {
+                    if waiter.send(()).is_ok() {
+                        free -= 1;
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        if let Ok(Some(())) = self.receiver.try_recv() {
+            self.limiter.state.borrow_mut().wake_waiters();
+        }
+    }
+}
+
+impl Permit<'_> {
+    fn finish<T>(mut self, result: &Result<T, Error>) {
+        self.finished = true;
+        let healthy = match result {
+            Ok(_) => true,
+            // The backend answered; the request just can't be served
+            Err(e) => e.is::<Unsupported>() || e.is::<NotFound>(),
+        };
+        self.limiter.release(self.started.elapsed(), healthy);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.limiter.release(self.started.elapsed(), false);
+        }
+    }
+}
+
+impl Throttled {
+    pub fn new(inner: Rc<dyn Backend>, limiter: Rc<Limiter>) -> Self {
+        Throttled { inner, limiter }
+    }
+}
+
+impl Backend for Throttled {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn get_leaderboard_range(
+        &self,
+        leaderboard_name: String,
+        start: u32,
+        end: u32,
+    ) -> LocalBoxFuture<'_, Result<LeaderboardResponse, Error>> {
+        self.limiter
+            .throttle(self.inner.get_leaderboard_range(leaderboard_name, start, end))
+            .boxed_local()
+    }
+
+    fn get_all_workshop_sprint_challenge_stunt_levels(
+        &self,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>> {
+        self.inner.get_all_workshop_sprint_challenge_stunt_levels()
+    }
+
+    fn get_recently_updated_workshop_levels(
+        &self,
+        since: DateTime<Utc>,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>> {
+        self.inner.get_recently_updated_workshop_levels(since)
+    }
+
+    fn download_ugc(&self, ugc_id: u64) -> LocalBoxFuture<'_, Result<Vec<u8>, Error>> {
+        self.inner.download_ugc(ugc_id)
+    }
+}
+
+#[test]
+fn test_adaptive_concurrency() {
+    use anyhow::format_err;
+
+    let config = RateLimitConfig {
+        requests_per_second: 1000.,
+        burst: 1000,
+        initial_concurrency: 16,
+        min_concurrency: 2,
+        max_concurrency: 17,
+        target_latency_ms: 60_000,
+    };
+    let limiter = Limiter::new(config);
+    let request = |result: Result<(), Error>| {
+        task::block_on(limiter.acquire()).finish(&result);
+    };
+
+    // Failures close together only cut the limit once
+    request(Err(format_err!("timed out")));
+    request(Err(format_err!("timed out")));
+    assert_eq!(limiter.stats().final_concurrency_limit, 8);
+
+    // Successes grow it by about one per limit's worth of requests, up to the maximum
+    (0..9).for_each(|_| request(Ok(())));
+    assert_eq!(limiter.stats().final_concurrency_limit, 9);
+    (0..1000).for_each(|_| request(Ok(())));
+
+    let stats = limiter.stats();
+    assert_eq!(stats.final_concurrency_limit, 17);
+    assert_eq!(stats.min_concurrency_limit, 8);
+    assert_eq!(stats.requests, 1011);
+    assert_eq!(stats.slow_or_failed_requests, 2);
+
+    // Missing leaderboards aren't held against the backend
+    request(Err(NotFound { leaderboard_name: "x".to_owned() }.into()));
+    assert_eq!(limiter.stats().final_concurrency_limit, 17);
+    assert_eq!(limiter.stats().slow_or_failed_requests, 2);
+}
+
+#[test]
+fn test_waiting_for_a_slot() {
+    use futures::future::FutureExt;
+
+    let config = RateLimitConfig {
+        requests_per_second: 1000.,
+        burst: 1000,
+        initial_concurrency: 1,
+        min_concurrency: 1,
+        max_concurrency: 1,
+        target_latency_ms: 60_000,
+    };
+    let limiter = Limiter::new(config);
+    let first = task::block_on(limiter.acquire());
+
+    // With the only slot taken, the next requests wait until it is released
+    let mut second = limiter.acquire().boxed_local();
+    let mut third = limiter.acquire().boxed_local();
+    assert!((&mut second).now_or_never().is_none());
+    assert!((&mut third).now_or_never().is_none());
+    first.finish(&Ok(()));
+
+    // A woken request that gives up passes the slot on
+    drop(second);
+    let third = task::block_on(third);
+    assert_eq!(limiter.state.borrow().in_flight, 1);
+    third.finish(&Ok(()));
+}
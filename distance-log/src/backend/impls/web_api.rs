@@ -0,0 +1,393 @@
+use crate::{
+    backend::{
+        impls::throttled::Limiter, Backend, LeaderboardResponse, Unsupported, WorkshopResponse,
+        DISTANCE_APP_ID,
+    },
+    config::WebApiConfig,
+    http,
+};
+use anyhow::{format_err, Context, Error};
+use chrono::{DateTime, TimeZone, Utc};
+use futures::{future::LocalBoxFuture, prelude::*, stream::LocalBoxStream};
+use itertools::Itertools;
+use serde_derive::Deserialize;
+use std::{collections::HashMap, rc::Rc};
+
+const NAME: &str = "web_api";
+const PAGE_SIZE: u32 = 100;
+
+// EPublishedFileQueryType::k_PublishedFileQueryType_RankedByPublicationDate
+const QUERY_TYPE_RANKED_BY_PUBLICATION_DATE: u32 = 1;
+
+// EPublishedFileQueryType::k_PublishedFileQueryType_RankedByLastUpdatedDate
+const QUERY_TYPE_RANKED_BY_LAST_UPDATED_DATE: u32 = 21;
+
+/// How much older than the time asked for a level must be to end a query for recently updated
+/// levels.
+const ORDERING_MARGIN_HOURS: i64 = 24;
+
+/// The public Steam Web API, which needs an API key but no Steam client. It can enumerate workshop
+/// levels and download UGC, but has no access to leaderboards.
+#[derive(Debug)]
+pub struct WebApi {
+    agent: ureq::Agent,
+    base_url: String,
+    key: String,
+    limiter: Option<Rc<Limiter>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryFilesResponse {
+    response: QueryFilesPage,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryFilesPage {
+    #[serde(default)]
+    publishedfiledetails: Vec<PublishedFileDetails>,
+    next_cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PublishedFileDetails {
+    publishedfileid: String,
+    #[serde(default)]
+    creator: String,
+    #[serde(default)]
+    filename: String,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    preview_url: String,
+    #[serde(default)]
+    tags: Vec<PublishedFileTag>,
+    time_updated: Option<i64>,
+    hcontent_file: Option<String>,
+    vote_data: Option<VoteData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PublishedFileTag {
+    tag: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct VoteData {
+    score: f32,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlayerSummariesResponse {
+    response: PlayerSummaries,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlayerSummaries {
+    players: Vec<PlayerSummary>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlayerSummary {
+    steamid: String,
+    personaname: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UgcFileDetailsResponse {
+    data: UgcFileDetails,
+}
+
+#[derive(Debug, Deserialize)]
+struct UgcFileDetails {
+    url: String,
+}
+
+impl WebApi {
+    /// Workshop queries, which `Throttled` can't tell apart from the levels they return, and UGC
+    /// downloads, which take two requests each, are throttled with `limiter` here.
+    pub fn new(config: &WebApiConfig, limiter: Option<Rc<Limiter>>) -> Result<Self, Error> {
+        let key = config
+            .key
+            .clone()
+            .ok_or_else(|| format_err!("The web_api backend requires web_api.key to be set"))?;
+
+        Ok(WebApi {
+            agent: http::agent(),
+            base_url: config.base_url.trim_end_matches('/').to_owned(),
+            key,
+            limiter,
+        })
+    }
+
+    /// Sends a GET request, once the limiter allows it.
+    async fn get(&self, url: String, query: Vec<(&'static str, String)>) -> Result<String, Error> {
+        let request = http::get_string_with_query(&self.agent, url, query);
+        match &self.limiter {
+            Some(limiter) => limiter.throttle(request).await,
+            None => request.await,
+        }
+    }
+
+    /// Downloads a file, once the limiter allows it.
+    async fn get_bytes(&self, url: String) -> Result<Vec<u8>, Error> {
+        let request = http::get_bytes(&self.agent, url);
+        match &self.limiter {
+            Some(limiter) => limiter.throttle(request).await,
+            None => request.await,
+        }
+    }
+
+    /// Fetches every page of workshop levels, in the order given by `query_type`.
+    fn query_workshop(
+        &self,
+        query_type: u32,
+    ) -> impl Stream<Item = Result<WorkshopResponse, Error>> + '_ {
+        stream::unfold(Some("*".to_owned()), move |cursor| async move {
+            let page = self.query_workshop_page(query_type, cursor?).await;
+            let (responses, next_cursor) = match page {
+                Ok((responses, next_cursor)) => (Ok(responses), next_cursor),
+                Err(e) => (Err(e), None),
+            };
+
+            Some((responses, next_cursor))
+        })
+        .map(|page| {
+            let results: Vec<_> = match page {
+                Ok(responses) => responses.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            };
+
+            stream::iter(results)
+        })
+        .flatten()
+    }
+
+    /// Fetches one page of workshop levels, returning it along with the cursor of the next page.
+    async fn query_workshop_page(
+        &self,
+        query_type: u32,
+        cursor: String,
+    ) -> Result<(Vec<WorkshopResponse>, Option<String>), Error> {
+        let url = format!("{}/IPublishedFileService/QueryFiles/v1/", self.base_url);
+        let query = vec![
+            ("key", self.key.clone()),
+            ("appid", DISTANCE_APP_ID.to_string()),
+            ("query_type", query_type.to_string()),
+            ("cursor", cursor.clone()),
+            ("numperpage", PAGE_SIZE.to_string()),
+            ("requiredtags[0]", "Sprint".to_owned()),
+            ("requiredtags[1]", "Challenge".to_owned()),
+            ("requiredtags[2]", "Stunt".to_owned()),
+            ("match_all_tags", "false".to_owned()),
+            ("return_tags", "true".to_owned()),
+            ("return_vote_data", "true".to_owned()),
+        ];
+        let response: QueryFilesResponse = serde_json::from_str(&self.get(url, query).await?)
+            .context("Error parsing a QueryFiles response")?;
+        let page = response.response;
+
+        let details: Vec<_> =
+            page.publishedfiledetails.into_iter().filter(|x| !x.filename.is_empty()).collect();
+        let author_names =
+            self.player_names(details.iter().map(|x| x.creator.as_str()).unique()).await?;
+        let responses = details
+            .into_iter()
+            .map(|details| {
+                Ok(WorkshopResponse {
+                    published_file_id: details.publishedfileid.parse()?,
+                    steam_id_owner: details.creator.parse()?,
+                    author_name: author_names.get(&details.creator).cloned().unwrap_or_default(),
+                    file_name: details.filename,
+                    title: details.title,
+                    score: details.vote_data.map(|x| x.score).unwrap_or(0.0),
+                    tags: details.tags.into_iter().map(|x| x.tag).collect(),
+                    preview_url: details.preview_url,
+                    time_updated: details
+                        .time_updated
+                        .and_then(|x| Utc.timestamp_opt(x, 0).single()),
+                    file_ugc_id: details.hcontent_file.and_then(|x| x.parse().ok()),
+                    source: Some(NAME.to_owned()),
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+
+        // The last page hands back the cursor it was requested with
+        let next_cursor = page.next_cursor.filter(|next| !next.is_empty() && *next != cursor);
+
+        Ok((responses, next_cursor))
+    }
+
+    async fn player_names<'a>(
+        &self,
+        steam_ids: impl Iterator<Item = &'a str>,
+    ) -> Result<HashMap<String, String>, Error> {
+        const MAX_STEAM_IDS_PER_REQUEST: usize = 100;
+
+        let mut names = HashMap::new();
+        for chunk in &steam_ids.chunks(MAX_STEAM_IDS_PER_REQUEST) {
+            let url = format!("{}/ISteamUser/GetPlayerSummaries/v2/", self.base_url);
+            let query =
+                vec![("key", self.key.clone()), ("steamids", chunk.collect::<Vec<_>>().join(","))];
+            let response: PlayerSummariesResponse =
+                serde_json::from_str(&self.get(url, query).await?)
+                    .context("Error parsing a GetPlayerSummaries response")?;
+            names.extend(response.response.players.into_iter().map(|x| (x.steamid, x.personaname)));
+        }
+
+        Ok(names)
+    }
+}
+
+impl Backend for WebApi {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn get_leaderboard_range(
+        &self,
+        _leaderboard_name: String,
+        _start: u32,
+        _end: u32,
+    ) -> LocalBoxFuture<'_, Result<LeaderboardResponse, Error>> {
+        let error = Unsupported { backend: NAME, operation: "Fetching leaderboards" };
+        future::err(error.into()).boxed_local()
+    }
+
+    fn get_all_workshop_sprint_challenge_stunt_levels(
+        &self,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>> {
+        self.query_workshop(QUERY_TYPE_RANKED_BY_PUBLICATION_DATE)
+            .map(|x| future::ready(x).boxed_local())
+            .boxed_local()
+    }
+
+    fn get_recently_updated_workshop_levels(
+        &self,
+        since: DateTime<Utc>,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>> {
+        // Steam only roughly orders the results by update time, so levels updated since `since`
+        // can come after a few older ones. Only a level older than that by a margin ends the
+        // query; the older levels before it are returned too, and merge into the index unchanged.
+        let cutoff = since - chrono::Duration::hours(ORDERING_MARGIN_HOURS);
+
+        // Most recently updated first, so the first level older than `cutoff` ends the query
+        self.query_workshop(QUERY_TYPE_RANKED_BY_LAST_UPDATED_DATE)
+            .take_while(move |x| {
+                let is_older = match x {
+                    Ok(WorkshopResponse { time_updated: Some(time_updated), .. }) => {
+                        *time_updated < cutoff
+                    }
+                    _ => false,
+                };
+                future::ready(!is_older)
+            })
+            .map(|x| future::ready(x).boxed_local())
+            .boxed_local()
+    }
+
+    fn download_ugc(&self, ugc_id: u64) -> LocalBoxFuture<'_, Result<Vec<u8>, Error>> {
+        async move {
+            let url = format!("{}/ISteamRemoteStorage/GetUGCFileDetails/v1/", self.base_url);
+            let query = vec![
+                ("key", self.key.clone()),
+                ("appid", DISTANCE_APP_ID.to_string()),
+                ("ugcid", ugc_id.to_string()),
+            ];
+            let response: UgcFileDetailsResponse =
+                serde_json::from_str(&self.get(url, query).await?)
+                    .context("Error parsing a GetUGCFileDetails response")?;
+
+            self.get_bytes(response.data.url).await
+        }
+        .boxed_local()
+    }
+}
+
+#[test]
+fn test_web_api_workshop_enumeration() {
+    use crate::test_util::MockServer;
+    use async_std::task;
+
+    let server = MockServer::start(|request| {
+        let (path, query) = request.url.split_once('?').unwrap_or((&request.url, ""));
+        assert!(query.contains("key=secret"));
+        match path {
+            "/IPublishedFileService/QueryFiles/v1/" if query.contains("cursor=*") => {
+                (200, include_str!("../../../test_data/web_api/query_files_1.json").into())
+            }
+            "/IPublishedFileService/QueryFiles/v1/" if query.contains("cursor=AoJ4%2Bn8%3D") => {
+                (200, include_str!("../../../test_data/web_api/query_files_2.json").into())
+            }
+            "/ISteamUser/GetPlayerSummaries/v2/" => {
+                (200, include_str!("../../../test_data/web_api/player_summaries.json").into())
+            }
+            _ => (404, String::new()),
+        }
+    });
+    let backend =
+        WebApi::new(&WebApiConfig { base_url: server.url(), key: Some("secret".to_owned()) }, None)
+            .unwrap();
+
+    let responses: Vec<_> = task::block_on(
+        backend.get_all_workshop_sprint_challenge_stunt_levels().then(|x| x).try_collect(),
+    )
+    .unwrap();
+    assert_eq!(responses.len(), 3);
+    assert_eq!(responses[0].published_file_id, 2064862164);
+    assert_eq!(responses[0].steam_id_owner, 76561198043251234);
+    assert_eq!(responses[0].author_name, "Seeker");
+    assert_eq!(responses[0].file_name, "some workshop level.bytes");
+    assert_eq!(&*responses[0].tags, ["Level", "Sprint", "Challenge"]);
+    assert_eq!(responses[0].file_ugc_id, Some(1010743367478311552));
+    assert_eq!(responses[0].time_updated.unwrap().timestamp(), 1587254400);
+    assert_eq!(responses[2].author_name, "Runner & Co");
+    assert_eq!(responses[2].score, 0.5);
+
+    // Two pages plus one batch of player names per page
+    assert_eq!(server.requests().len(), 4);
+}
+
+#[test]
+fn test_web_api_recently_updated_workshop_levels() {
+    use crate::test_util::MockServer;
+    use async_std::task;
+
+    // The fixtures happen to be sorted by last update too
+    let server = MockServer::start(|request| {
+        let (path, query) = request.url.split_once('?').unwrap_or((&request.url, ""));
+        match path {
+            "/IPublishedFileService/QueryFiles/v1/" if query.contains("query_type=21") => {
+                if query.contains("cursor=*") {
+                    (200, include_str!("../../../test_data/web_api/query_files_1.json").into())
+                } else {
+                    (200, include_str!("../../../test_data/web_api/query_files_2.json").into())
+                }
+            }
+            "/ISteamUser/GetPlayerSummaries/v2/" => {
+                (200, include_str!("../../../test_data/web_api/player_summaries.json").into())
+            }
+            _ => (404, String::new()),
+        }
+    });
+    let backend =
+        WebApi::new(&WebApiConfig { base_url: server.url(), key: Some("secret".to_owned()) }, None)
+            .unwrap();
+
+    let since = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+    let responses: Vec<_> = task::block_on(
+        backend.get_recently_updated_workshop_levels(since).then(|x| x).try_collect(),
+    )
+    .unwrap();
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].published_file_id, 2064862164);
+
+    // The second page is slightly out of order: a level updated before `since` doesn't end the
+    // query if it's within the margin, so the level after it isn't missed
+    let since = Utc.with_ymd_and_hms(2019, 11, 27, 0, 0, 0).unwrap();
+    let responses: Vec<_> = task::block_on(
+        backend.get_recently_updated_workshop_levels(since).then(|x| x).try_collect::<Vec<_>>(),
+    )
+    .unwrap();
+    let ids: Vec<_> = responses.iter().map(|x| x.published_file_id).collect();
+    assert_eq!(ids, [2064862164, 1925301122, 1925301123]);
+}
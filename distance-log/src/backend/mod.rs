@@ -0,0 +1,265 @@
+pub mod health;
+pub mod impls;
+
+use crate::{
+    backend::{
+        health::BackendStats,
+        impls::{
+            cassette::{Cassette, Recorder, Replayer, WorkshopEnumeration},
+            community_xml::CommunityXml,
+            fallback::Fallback,
+            steamworks::Steamworks,
+            throttled::{Limiter, ThrottleStats, Throttled},
+            web_api::WebApi,
+        },
+    },
+    config::{BackendKind, Config},
+};
+use anyhow::{bail, Error};
+use chrono::{DateTime, Utc};
+use futures::{future::LocalBoxFuture, stream::LocalBoxStream};
+use serde_derive::{Deserialize, Serialize};
+use std::{cell::RefCell, collections::BTreeMap, fmt::Debug, rc::Rc};
+use thiserror::Error;
+
+pub const DISTANCE_APP_ID: u32 = 233610;
+
+/// The backends used for each kind of request.
+#[derive(Debug, Clone)]
+pub struct Backends {
+    /// Fetches leaderboards and the ghosts attached to them.
+    pub leaderboards: Rc<dyn Backend>,
+
+    /// Enumerates workshop levels and downloads their level files.
+    pub workshop: Rc<dyn Backend>,
+
+    /// The fallback chains behind the backends above, kept for their stats.
+    chains: Vec<Rc<Fallback>>,
+
+    /// Throttles the requests of both chains.
+    limiter: Option<Rc<Limiter>>,
+
+    /// The cassette being replayed, if any.
+    replayer: Option<Rc<Replayer>>,
+}
+
+/// A source of leaderboard and workshop data.
+pub trait Backend: Debug {
+    /// A short name identifying the backend, recorded in the responses it produces.
+    fn name(&self) -> &'static str;
+
+    fn get_leaderboard_range(
+        &self,
+        leaderboard_name: String,
+        start: u32,
+        end: u32,
+    ) -> LocalBoxFuture<'_, Result<LeaderboardResponse, Error>>;
+
+    fn get_all_workshop_sprint_challenge_stunt_levels(
+        &self,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>>;
+
+    /// Like `get_all_workshop_sprint_challenge_stunt_levels`, but only the levels created or
+    /// updated at or after `since`.
+    fn get_recently_updated_workshop_levels(
+        &self,
+        since: DateTime<Utc>,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>>;
+
+    fn download_ugc(&self, ugc_id: u64) -> LocalBoxFuture<'_, Result<Vec<u8>, Error>>;
+}
+
+impl Backends {
+    /// Creates the backends selected in `config`. A backend selected for more than one kind of
+    /// request is only created once.
+    pub fn from_config(config: &Config) -> Result<Self, Error> {
+        let limiter = config.rate_limit.clone().map(|x| Rc::new(Limiter::new(x)));
+        // Steamworks reads entry counts off the same leaderboard list
+        let community_xml = Rc::new(CommunityXml::new(&config.community_xml));
+        let mut created: BTreeMap<BackendKind, Rc<dyn Backend>> = BTreeMap::new();
+        let mut get_or_create = |kind: &BackendKind| -> Result<Rc<dyn Backend>, Error> {
+            if let Some(backend) = created.get(kind) {
+                return Ok(backend.clone());
+            }
+
+            let backend: Rc<dyn Backend> = match kind {
+                BackendKind::Steamworks => Rc::new(Steamworks::new(community_xml.clone())?),
+                BackendKind::CommunityXml => community_xml.clone(),
+                BackendKind::WebApi => Rc::new(WebApi::new(&config.web_api, limiter.clone())?),
+            };
+            created.insert(*kind, backend.clone());
+
+            Ok(backend)
+        };
+        let mut create_chain = |kinds: &[BackendKind]| -> Result<Rc<Fallback>, Error> {
+            if kinds.is_empty() {
+                bail!("A backend chain must contain at least one backend");
+            }
+
+            let backends = kinds.iter().map(&mut get_or_create).collect::<Result<_, _>>()?;
+            Ok(Rc::new(Fallback::new(backends)))
+        };
+
+        let leaderboards = create_chain(&config.backend)?;
+        let workshop = create_chain(config.workshop_backend.as_ref().unwrap_or(&config.backend))?;
+        let throttled = |chain: &Rc<Fallback>| -> Rc<dyn Backend> {
+            match &limiter {
+                Some(limiter) => Rc::new(Throttled::new(chain.clone(), limiter.clone())),
+                None => chain.clone(),
+            }
+        };
+
+        Ok(Backends {
+            leaderboards: throttled(&leaderboards),
+            workshop: throttled(&workshop),
+            chains: vec![leaderboards, workshop],
+            limiter,
+            replayer: None,
+        })
+    }
+
+    /// Serves every request from a previously recorded cassette instead of a real backend.
+    pub fn replay(cassette: Cassette) -> Self {
+        let replayer = Rc::new(Replayer::new(cassette));
+        Backends {
+            leaderboards: replayer.clone(),
+            workshop: replayer.clone(),
+            chains: Vec::new(),
+            limiter: None,
+            replayer: Some(replayer),
+        }
+    }
+
+    /// Records every response from these backends into `cassette`.
+    pub fn record(self, cassette: &Rc<RefCell<Cassette>>) -> Self {
+        Backends {
+            leaderboards: Rc::new(Recorder::new(self.leaderboards, cassette.clone())),
+            workshop: Rc::new(Recorder::new(self.workshop, cassette.clone())),
+            chains: self.chains,
+            limiter: self.limiter,
+            replayer: self.replayer,
+        }
+    }
+
+    /// When replaying a cassette, how the recorded run enumerated workshop levels, so that the
+    /// replay can make the same requests.
+    pub fn replayed_workshop_enumeration(&self) -> Option<WorkshopEnumeration> {
+        self.replayer.as_ref().and_then(|x| x.workshop_enumeration())
+    }
+
+    /// Whether the responses come from a cassette.
+    pub fn is_replay(&self) -> bool {
+        self.replayer.is_some()
+    }
+
+    /// Whether the leaderboard can be fetched: when replaying a cassette, only the leaderboards
+    /// the recorded run fetched can be.
+    pub fn has_leaderboard(&self, leaderboard_name: &str) -> bool {
+        match &self.replayer {
+            Some(replayer) => replayer.has_leaderboard(leaderboard_name),
+            None => true,
+        }
+    }
+
+    /// The time the run takes place at. When replaying a cassette, that's when the recorded run
+    /// started, so that the replay decides what to fetch the same way.
+    pub fn now(&self) -> DateTime<Utc> {
+        self.replayer.as_ref().and_then(|x| x.started()).unwrap_or_else(Utc::now)
+    }
+
+    /// When a leaderboard response that just arrived was fetched. When replaying a cassette,
+    /// that's when the recorded run got it.
+    pub fn fetch_time(&self, leaderboard_name: &str) -> DateTime<Utc> {
+        match &self.replayer {
+            Some(replayer) => replayer.fetched_at(leaderboard_name).unwrap_or_else(|| self.now()),
+            None => Utc::now(),
+        }
+    }
+
+    /// What the rate limiter has done so far, if requests are rate limited.
+    pub fn throttle_stats(&self) -> Option<ThrottleStats> {
+        self.limiter.as_ref().map(|x| x.stats())
+    }
+
+    /// The number of requests made to, and failed by, each backend so far.
+    pub fn stats(&self) -> Vec<BackendStats> {
+        let mut totals: BTreeMap<String, BackendStats> = BTreeMap::new();
+        for stats in self.chains.iter().flat_map(|chain| chain.stats()) {
+            let total = totals.entry(stats.backend.clone()).or_insert(BackendStats {
+                backend: stats.backend,
+                requests: 0,
+                errors: 0,
+            });
+            total.requests += stats.requests;
+            total.errors += stats.errors;
+        }
+
+        totals.into_values().collect()
+    }
+}
+
+/// Returned by backends for operations they have no way of performing.
+#[derive(Error, Debug)]
+#[error("{operation} is not supported by the {backend} backend")]
+pub struct Unsupported {
+    pub backend: &'static str,
+    pub operation: &'static str,
+}
+
+/// The backend answered, but the leaderboard doesn't exist. Unlike other errors, this doesn't mean
+/// the backend is unhealthy.
+#[derive(Error, Debug)]
+#[error("No leaderboard named '{leaderboard_name}' exists")]
+pub struct NotFound {
+    pub leaderboard_name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderboardResponse {
+    pub entries: Box<[LeaderboardEntry]>,
+
+    /// Total number of entries on the leaderboard. `None` for responses stored before this was
+    /// recorded.
+    #[serde(default)]
+    pub entry_count: Option<u32>,
+
+    /// The name of the backend that produced this response.
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    pub steam_id: u64,
+    pub global_rank: i32,
+    pub score: i32,
+    pub player_name: String,
+
+    /// The UGC handle of the ghost attached to this entry, if there is one.
+    #[serde(default)]
+    pub ugc_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkshopResponse {
+    pub published_file_id: u64,
+    pub steam_id_owner: u64,
+    pub file_name: String,
+    pub title: String,
+    pub score: f32,
+    pub tags: Box<[String]>,
+    pub author_name: String,
+    pub preview_url: String,
+
+    /// When the item was last updated. `None` for responses stored before this was recorded.
+    #[serde(default)]
+    pub time_updated: Option<DateTime<Utc>>,
+
+    /// The UGC handle of the level file.
+    #[serde(default)]
+    pub file_ugc_id: Option<u64>,
+
+    /// The name of the backend that produced this response.
+    #[serde(default)]
+    pub source: Option<String>,
+}
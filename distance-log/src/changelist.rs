@@ -0,0 +1,147 @@
+use crate::domain::{ChangelistEntry, LevelInfo};
+use distance_util::LeaderboardGameMode;
+use if_chain::if_chain;
+use itertools::{EitherOrBoth, Itertools};
+use std::collections::BTreeMap;
+
+/// The leaderboards for which `add_missing_entries_from` used the other set of results.
+#[derive(Debug, Default)]
+pub struct MissingEntries {
+    /// Leaderboards that were empty, but had entries in the other results.
+    pub backfilled: Vec<String>,
+
+    /// Leaderboards only present in the other results.
+    pub carried_over: Vec<String>,
+}
+
+/// Merges `other` into `level_infos`, keeping the entries from `other` for leaderboards that are
+/// missing or empty in `level_infos`.
+///
+/// Deals with Steam sometimes failing to return data by supplementing it with the previously
+/// stored data.
+pub fn add_missing_entries_from(
+    mut level_infos: Vec<LevelInfo>,
+    mut other: Vec<LevelInfo>,
+) -> (Vec<LevelInfo>, MissingEntries) {
+    let sort = |x: &mut [LevelInfo]| {
+        x.sort_unstable_by(|a, b| a.leaderboard_name.cmp(&b.leaderboard_name))
+    };
+
+    sort(&mut level_infos);
+    sort(&mut other);
+
+    let mut missing = MissingEntries::default();
+    let merged = level_infos
+        .into_iter()
+        .merge_join_by(other, |a, b| a.leaderboard_name.cmp(&b.leaderboard_name))
+        .map(|x| match x {
+            EitherOrBoth::Both(l, r) => {
+                if l.leaderboard_response.entries.len() == 0
+                    && r.leaderboard_response.entries.len() > 0
+                {
+                    missing.backfilled.push(r.leaderboard_name.clone());
+                    r
+                } else {
+                    l
+                }
+            }
+            EitherOrBoth::Left(x) => x,
+            EitherOrBoth::Right(x) => {
+                missing.carried_over.push(x.leaderboard_name.clone());
+                x
+            }
+        })
+        .collect();
+
+    (merged, missing)
+}
+
+/// Appends an entry to `changelist` for every world record in `new` that improves on `old`, or that
+/// is on a leaderboard `old` has no record for.
+pub fn update_changelist(
+    changelist: &mut Vec<ChangelistEntry>,
+    new: &mut [LevelInfo],
+    old: Vec<LevelInfo>,
+) {
+    new.sort_by_key(|level_info| {
+        level_info.workshop_response.as_ref().map(|x| x.published_file_id).unwrap_or(0)
+    });
+    let old: BTreeMap<_, _> = old
+        .into_iter()
+        .map(|level_info| (level_info.leaderboard_name.clone(), level_info))
+        .collect();
+
+    let entries = new.iter().filter_map(|level_info| {
+        let LevelInfo {
+            name,
+            mode,
+            leaderboard_name,
+            workshop_response,
+            leaderboard_response,
+            timestamp,
+            ..
+        } = level_info;
+        let first_entry = if let Some(x) = leaderboard_response.entries.get(0) {
+            x.clone()
+        } else {
+            return None;
+        };
+
+        let (old_recordholder, record_old, steam_id_old_recordholder, score_old) = if_chain! {
+            if let Some(level_info_old) = old.get(leaderboard_name);
+            if let Some(previous_first_entry) = level_info_old.leaderboard_response.entries.get(0);
+            then {
+                if is_score_better(first_entry.score, previous_first_entry.score, *mode) {
+                    (Some(previous_first_entry.player_name.clone()),
+                        Some(distance_util::format_score(previous_first_entry.score, *mode).unwrap()),
+                        Some(format!("{}", previous_first_entry.steam_id)),
+                        Some(previous_first_entry.score))
+                } else {
+                    return None;
+                }
+            } else {
+                (None, None, None, None)
+            }
+        };
+
+        Some(ChangelistEntry {
+            map_name: name.clone(),
+            map_author: workshop_response.as_ref().map(|x| x.author_name.clone()),
+            map_preview: workshop_response.as_ref().map(|x| x.preview_url.clone()),
+            mode: format!("{}", mode),
+            new_recordholder: first_entry.player_name,
+            old_recordholder,
+            record_new: distance_util::format_score(first_entry.score, *mode).unwrap(),
+            record_old,
+            workshop_item_id: workshop_response
+                .as_ref()
+                .map(|x| format!("{}", x.published_file_id)),
+            steam_id_author: workshop_response
+                .as_ref()
+                .map(|x| format!("{}", x.steam_id_owner)),
+            steam_id_new_recordholder: format!("{}", first_entry.steam_id),
+            steam_id_old_recordholder,
+            fetch_time: timestamp.to_rfc2822(),
+            score_new: Some(first_entry.score),
+            score_old,
+        })
+    });
+
+    let entries: Vec<_> = entries
+        .filter(|new_entry| {
+            changelist
+                .iter()
+                .all(|existing_entry| !new_entry.is_likely_a_duplicate_of(existing_entry))
+        })
+        .rev()
+        .collect();
+
+    changelist.extend(entries);
+}
+
+pub fn is_score_better(score_1: i32, score_2: i32, game_mode: LeaderboardGameMode) -> bool {
+    match game_mode {
+        LeaderboardGameMode::Sprint | LeaderboardGameMode::Challenge => score_1 < score_2,
+        LeaderboardGameMode::Stunt => score_1 > score_2,
+    }
+}
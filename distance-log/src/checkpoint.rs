@@ -0,0 +1,72 @@
+use crate::{
+    domain::LevelInfo,
+    persistence::{
+        impls::file_json::{load_file, write_file_atomically},
+        LoadError,
+    },
+};
+use anyhow::{Context, Error};
+use chrono::{DateTime, Duration, Utc};
+use serde_derive::{Deserialize, Serialize};
+use std::{fs, io, path::Path};
+
+/// The levels fetched by an interrupted run, so that the next run only has to fetch the rest.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub created: DateTime<Utc>,
+    pub level_infos: Vec<LevelInfo>,
+}
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> Result<Option<Self>, Error> {
+        match load_file(path) {
+            Ok(x) => Ok(Some(x)),
+            Err(LoadError::DoesNotExist) => Ok(None),
+            Err(e) => {
+                Err(e).with_context(|| format!("Error loading checkpoint '{}'", path.display()))
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let serialized = serde_json::to_vec(self)?;
+        write_file_atomically(&serialized, path)
+            .with_context(|| format!("Error saving checkpoint '{}'", path.display()))
+    }
+
+    /// Whether the checkpoint was created more than `max_age` before `now`.
+    pub fn is_older_than(&self, max_age: Duration, now: DateTime<Utc>) -> bool {
+        now - self.created > max_age
+    }
+
+    /// Removes the checkpoint once the levels in it have made it into the query results.
+    pub fn remove(path: &Path) -> Result<(), Error> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Error removing checkpoint '{}'", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_checkpoint() {
+    use crate::test_util::level_info;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("checkpoint.json");
+    assert!(Checkpoint::load(&path).unwrap().is_none());
+
+    let created = Utc::now();
+    let level_infos = vec![level_info("Broken Symmetry", "a", Some(("Seeker", 20000)), created)];
+    Checkpoint { created, level_infos }.save(&path).unwrap();
+    let checkpoint = Checkpoint::load(&path).unwrap().unwrap();
+    assert_eq!(checkpoint.level_infos[0].leaderboard_name, "a");
+    assert!(!checkpoint.is_older_than(Duration::hours(6), created + Duration::hours(6)));
+    assert!(checkpoint.is_older_than(Duration::hours(6), created + Duration::hours(7)));
+
+    Checkpoint::remove(&path).unwrap();
+    Checkpoint::remove(&path).unwrap();
+    assert!(Checkpoint::load(&path).unwrap().is_none());
+}
@@ -0,0 +1,557 @@
+use anyhow::{Context, Error};
+use serde::{Deserialize as _, Deserializer};
+use serde_derive::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Optional settings, read from `distance-log.toml` in the working directory. Every section may be
+/// omitted; a missing file is the same as an empty one.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where leaderboard data comes from. Either a single backend or a list, in which case later
+    /// backends are used when earlier ones fail.
+    #[serde(deserialize_with = "one_or_many")]
+    pub backend: Vec<BackendKind>,
+
+    /// Where the list of workshop levels comes from. Defaults to `backend`.
+    #[serde(deserialize_with = "optional_one_or_many")]
+    pub workshop_backend: Option<Vec<BackendKind>>,
+
+    pub community_xml: CommunityXmlConfig,
+
+    pub web_api: WebApiConfig,
+
+    /// When present, requests to the backends are rate limited. Otherwise they're only limited by
+    /// how many leaderboards are fetched at once.
+    pub rate_limit: Option<RateLimitConfig>,
+
+    /// Which leaderboards are fetched in a run.
+    pub schedule: ScheduleConfig,
+
+    /// How workshop levels are discovered.
+    pub workshop: WorkshopConfig,
+
+    /// When present, leaderboards without recent activity are fetched less often than every run.
+    pub polling: Option<PollingConfig>,
+
+    /// When present, feeds of the latest changelist entries are written alongside the changelist.
+    pub feed: Option<FeedConfig>,
+
+    /// When present, the latest changelist entries are published as the outbox of an ActivityPub
+    /// actor, in static files.
+    pub activitypub: Option<ActivityPubConfig>,
+
+    /// When present, a static website with a page for every level and player is generated after
+    /// every run.
+    pub site: Option<SiteConfig>,
+
+    /// When present, the changelist is also written split into small JSON files, with a manifest
+    /// of their hashes.
+    pub shards: Option<ShardsConfig>,
+
+    /// Where new changelist entries are sent, each with rules for which entries it gets.
+    pub notifier: Vec<NotifierConfig>,
+
+    /// When present, the new changelist entries are emailed as a digest at a fixed interval.
+    pub digest: Option<DigestConfig>,
+
+    /// When present, the ghost attached to each world record is downloaded and archived.
+    pub ghost_archive: Option<GhostArchiveConfig>,
+
+    /// When present, the level file of every tracked workshop level is downloaded and archived.
+    pub workshop_archive: Option<WorkshopArchiveConfig>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    /// The Steamworks API, through a running Steam client.
+    #[default]
+    Steamworks,
+
+    /// The public leaderboard pages on the Steam Community website.
+    CommunityXml,
+
+    /// The Steam Web API. Requires an API key.
+    WebApi,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> From<OneOrMany<T>> for Vec<T> {
+    fn from(x: OneOrMany<T>) -> Self {
+        match x {
+            OneOrMany::One(x) => vec![x],
+            OneOrMany::Many(x) => x,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommunityXmlConfig {
+    pub base_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebApiConfig {
+    pub base_url: String,
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// The sustained request rate.
+    pub requests_per_second: f64,
+
+    /// How many requests may be sent at once after a quiet period.
+    pub burst: u32,
+
+    /// The concurrency limit starts here, then adapts to how the backends respond, staying within
+    /// `min_concurrency` and `max_concurrency`.
+    pub initial_concurrency: u32,
+    pub min_concurrency: u32,
+    pub max_concurrency: u32,
+
+    /// Requests slower than this count against the concurrency limit, as failed requests do.
+    pub target_latency_ms: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// How long a run may spend fetching, in seconds. When it runs out, the leaderboards not yet
+    /// fetched keep their previous results; the least recently refreshed leaderboards are fetched
+    /// first, so every leaderboard is eventually refreshed. No limit by default.
+    pub budget_secs: Option<u64>,
+
+    /// Checkpoints left by interrupted runs more than this many hours ago are discarded rather
+    /// than resumed, as the levels in them are out of date.
+    pub checkpoint_max_age_hours: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedConfig {
+    pub title: String,
+
+    /// Where the changelist is published. Feed entries for official levels link here, and the
+    /// feed files are expected to be served from the same directory.
+    pub site_url: String,
+
+    /// How many of the newest changelist entries the feeds hold.
+    pub max_entries: usize,
+
+    pub atom_path: PathBuf,
+
+    /// When present, an RSS 2.0 feed with the same entries is written here too.
+    pub rss_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ActivityPubConfig {
+    /// The URL `directory` is served from. Actor, outbox and note IDs are built from it.
+    pub base_url: String,
+
+    /// The account is `@username@host`, where host is that of `base_url`.
+    pub username: String,
+
+    /// The account's display name.
+    #[serde(default = "default_activitypub_name")]
+    pub name: String,
+
+    #[serde(default)]
+    pub summary: String,
+
+    /// Where the actor, outbox and note files are written.
+    #[serde(default = "default_activitypub_directory")]
+    pub directory: PathBuf,
+
+    /// How many of the newest changelist entries the outbox holds.
+    #[serde(default = "default_activitypub_max_items")]
+    pub max_items: usize,
+
+    /// A PEM-encoded RSA public key to publish with the actor. Some servers refuse to look up
+    /// actors without one.
+    pub public_key_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SiteConfig {
+    /// The URL `directory` is served from, used for canonical links and the sitemap.
+    pub base_url: String,
+
+    #[serde(default = "default_site_title")]
+    pub title: String,
+
+    /// Where the pages are written. Pages are only ever added or replaced, never removed.
+    #[serde(default = "default_site_directory")]
+    pub directory: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShardsConfig {
+    #[serde(default = "default_shards_directory")]
+    pub directory: PathBuf,
+
+    /// How many of the newest changelist entries `latest.json` holds.
+    #[serde(default = "default_shards_latest_entries")]
+    pub latest_entries: usize,
+}
+
+/// One destination for notifications. Exactly one of `discord`, `webhook`, `matrix` and
+/// `mastodon` must be set.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotifierConfig {
+    /// Identifies the notifier's delivery state between runs, so renaming a notifier drops the
+    /// notifications still waiting to be delivered to it.
+    pub name: String,
+
+    #[serde(default)]
+    pub filter: NotificationFilter,
+
+    pub discord: Option<DiscordConfig>,
+    pub webhook: Option<WebhookConfig>,
+    pub matrix: Option<MatrixConfig>,
+    pub mastodon: Option<MastodonConfig>,
+}
+
+/// Which changelist entries a notifier gets. Every condition that is set must hold.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationFilter {
+    /// Only entries for official levels when `true`, or only workshop levels when `false`.
+    pub official: Option<bool>,
+
+    /// Only entries in these modes, e.g. "Stunt".
+    pub modes: Vec<String>,
+
+    /// Only entries where one of these players set or lost the record.
+    pub steam_ids: Vec<String>,
+
+    /// Only entries on leaderboards with at least this many entries.
+    pub min_entries: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiscordConfig {
+    pub webhook_url: String,
+
+    /// Overrides the name the webhook posts under.
+    pub username: Option<String>,
+}
+
+/// Posts each notification as JSON to an arbitrary URL.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+
+    /// Extra request headers, e.g. for authentication.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Sends each notification as a message to a Matrix room.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MatrixConfig {
+    pub homeserver_url: String,
+
+    /// The room's ID, like "!abcdefg:matrix.org", not an alias.
+    pub room_id: String,
+
+    /// The access token of the account that sends the messages, which must have joined the room.
+    pub access_token: String,
+}
+
+/// Emails the changelist entries added since the previous digest, grouped by mode and level.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DigestConfig {
+    /// How often, in hours, a digest is sent: 24 for a daily digest, 168 for a weekly one.
+    #[serde(default = "default_digest_interval_hours")]
+    pub interval_hours: i64,
+
+    /// The sender and recipients, either bare addresses or like "Name <name@example.org>".
+    pub from: String,
+    pub to: Vec<String>,
+
+    /// The number of new records is appended to this.
+    #[serde(default = "default_digest_subject")]
+    pub subject: String,
+
+    pub smtp: SmtpConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpConfig {
+    pub host: String,
+
+    /// Defaults to 587 with `starttls`, 465 with `tls`, and 25 with `none`.
+    pub port: Option<u16>,
+
+    #[serde(default)]
+    pub security: SmtpSecurity,
+
+    /// Credentials are only sent when both are set.
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// Upgrade the connection with STARTTLS, failing if the server doesn't support it.
+    #[default]
+    Starttls,
+
+    /// Connect over TLS from the start.
+    Tls,
+
+    /// Send everything in plain text. Only meant for local mail servers.
+    None,
+}
+
+/// Posts each notification as a status on a Mastodon instance.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MastodonConfig {
+    pub instance_url: String,
+
+    /// An access token of the posting account with the `write:statuses` and `write:media` scopes.
+    pub access_token: String,
+
+    /// "public", "unlisted", "private" or "direct".
+    #[serde(default = "default_mastodon_visibility")]
+    pub visibility: String,
+
+    /// Whether the level's preview image is attached to the status.
+    #[serde(default = "default_attach_preview")]
+    pub attach_preview: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkshopConfig {
+    /// How often, in hours, every workshop level is enumerated. In between, only levels created or
+    /// updated since the previous run are queried, and the rest are taken from the workshop index.
+    /// The `steamworks` and `community_xml` backends can't query recent levels, so with them every
+    /// level is enumerated every run.
+    pub full_enumeration_hours: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PollingConfig {
+    /// Leaderboards with a new record or new entries within this many days are fetched every run.
+    pub active_days: i64,
+
+    /// Other leaderboards are fetched again once this fraction of the time since their last
+    /// activity has passed. With 0.1, a leaderboard dormant for 10 days is fetched once a day.
+    pub interval_fraction: f64,
+
+    /// No leaderboard goes longer than this many hours between fetches.
+    pub max_staleness_hours: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GhostArchiveConfig {
+    #[serde(default = "default_ghost_archive_directory")]
+    pub directory: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkshopArchiveConfig {
+    #[serde(default = "default_workshop_archive_directory")]
+    pub directory: PathBuf,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match fs::read_to_string(path) {
+            Ok(s) => toml::from_str(&s)
+                .with_context(|| format!("Error parsing config file '{}'", path.display())),
+            Err(e) => {
+                if let io::ErrorKind::NotFound = e.kind() {
+                    Ok(Config::default())
+                } else {
+                    Err(e)
+                        .with_context(|| format!("Error reading config file '{}'", path.display()))
+                }
+            }
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            backend: vec![BackendKind::default()],
+            workshop_backend: None,
+            community_xml: Default::default(),
+            web_api: Default::default(),
+            rate_limit: None,
+            schedule: Default::default(),
+            workshop: Default::default(),
+            polling: None,
+            feed: None,
+            activitypub: None,
+            site: None,
+            shards: None,
+            notifier: Vec::new(),
+            digest: None,
+            ghost_archive: None,
+            workshop_archive: None,
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            requests_per_second: 20.,
+            burst: 40,
+            initial_concurrency: 16,
+            min_concurrency: 2,
+            max_concurrency: 128,
+            target_latency_ms: 5000,
+        }
+    }
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        FeedConfig {
+            title: "Distance world records".to_owned(),
+            site_url: "https://seekr.pw/distance-log/".to_owned(),
+            max_entries: 50,
+            atom_path: PathBuf::from("changelist.atom"),
+            rss_path: None,
+        }
+    }
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        ScheduleConfig { budget_secs: None, checkpoint_max_age_hours: 6 }
+    }
+}
+
+impl Default for WorkshopConfig {
+    fn default() -> Self {
+        WorkshopConfig { full_enumeration_hours: 24 }
+    }
+}
+
+impl Default for PollingConfig {
+    fn default() -> Self {
+        PollingConfig { active_days: 7, interval_fraction: 0.1, max_staleness_hours: 24 }
+    }
+}
+
+impl Default for CommunityXmlConfig {
+    fn default() -> Self {
+        CommunityXmlConfig { base_url: "https://steamcommunity.com".to_owned() }
+    }
+}
+
+impl Default for WebApiConfig {
+    fn default() -> Self {
+        WebApiConfig { base_url: "https://api.steampowered.com".to_owned(), key: None }
+    }
+}
+
+fn default_digest_interval_hours() -> i64 {
+    24
+}
+
+fn default_digest_subject() -> String {
+    "Distance world records".to_owned()
+}
+
+fn default_mastodon_visibility() -> String {
+    "public".to_owned()
+}
+
+fn default_attach_preview() -> bool {
+    true
+}
+
+fn default_activitypub_name() -> String {
+    "Distance world records".to_owned()
+}
+
+fn default_activitypub_directory() -> PathBuf {
+    "activitypub".into()
+}
+
+fn default_activitypub_max_items() -> usize {
+    50
+}
+
+fn default_site_title() -> String {
+    "Distance world records".to_owned()
+}
+
+fn default_site_directory() -> PathBuf {
+    "site".into()
+}
+
+fn default_shards_directory() -> PathBuf {
+    "shards".into()
+}
+
+fn default_shards_latest_entries() -> usize {
+    100
+}
+
+fn default_ghost_archive_directory() -> PathBuf {
+    "ghosts".into()
+}
+
+fn default_workshop_archive_directory() -> PathBuf {
+    "workshop_levels".into()
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<BackendKind>, D::Error> {
+    OneOrMany::deserialize(deserializer).map(Vec::from)
+}
+
+fn optional_one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<BackendKind>>, D::Error> {
+    one_or_many(deserializer).map(Some)
+}
+
+#[test]
+fn test_backend_lists() {
+    let config: Config = toml::from_str("").unwrap();
+    assert_eq!(config.backend, [BackendKind::Steamworks]);
+    assert_eq!(config.workshop_backend, None);
+
+    let config: Config =
+        toml::from_str("backend = \"web_api\"\nworkshop_backend = [\"steamworks\", \"web_api\"]")
+            .unwrap();
+    assert_eq!(config.backend, [BackendKind::WebApi]);
+    assert_eq!(config.workshop_backend, Some(vec![BackendKind::Steamworks, BackendKind::WebApi]));
+}
@@ -0,0 +1,192 @@
+use crate::{
+    changelist::{is_score_better, update_changelist},
+    domain::{ChangelistEntry, LevelInfo},
+    dry_run::describe_entry,
+    output::OutputFormat,
+};
+use anyhow::Error;
+use itertools::{EitherOrBoth, Itertools};
+use serde_derive::Serialize;
+use std::fmt::Write;
+
+/// The differences between two sets of query results.
+#[derive(Debug, Default, Serialize)]
+pub struct SnapshotDiff {
+    /// The changelist entries an update from the old results to the new ones would produce.
+    pub new_records: Vec<ChangelistEntry>,
+
+    /// Leaderboards whose world record got worse or disappeared, typically because it was
+    /// removed.
+    pub regressions: Vec<Regression>,
+
+    pub added_levels: Vec<String>,
+    pub removed_levels: Vec<String>,
+    pub name_changes: Vec<NameChange>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Regression {
+    pub leaderboard_name: String,
+    pub map_name: String,
+    pub mode: String,
+    pub record_old: String,
+    pub old_recordholder: String,
+    pub record_new: Option<String>,
+    pub new_recordholder: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NameChange {
+    pub leaderboard_name: String,
+    pub old_name: String,
+    pub new_name: String,
+}
+
+/// Compares two sets of query results, matching levels up by leaderboard name.
+pub fn diff(old: &[LevelInfo], new: &[LevelInfo]) -> SnapshotDiff {
+    let mut diff = SnapshotDiff::default();
+    update_changelist(&mut diff.new_records, &mut new.to_vec(), old.to_vec());
+
+    fn sorted(x: &[LevelInfo]) -> Vec<&LevelInfo> {
+        let mut x: Vec<_> = x.iter().collect();
+        x.sort_unstable_by(|a, b| a.leaderboard_name.cmp(&b.leaderboard_name));
+        x
+    }
+    let pairs = sorted(old)
+        .into_iter()
+        .merge_join_by(sorted(new), |a, b| a.leaderboard_name.cmp(&b.leaderboard_name));
+    for pair in pairs {
+        let (old, new) = match pair {
+            EitherOrBoth::Both(old, new) => (old, new),
+            EitherOrBoth::Left(old) => {
+                diff.removed_levels.push(old.leaderboard_name.clone());
+                continue;
+            }
+            EitherOrBoth::Right(new) => {
+                diff.added_levels.push(new.leaderboard_name.clone());
+                continue;
+            }
+        };
+
+        if old.name != new.name {
+            diff.name_changes.push(NameChange {
+                leaderboard_name: new.leaderboard_name.clone(),
+                old_name: old.name.clone(),
+                new_name: new.name.clone(),
+            });
+        }
+
+        if let Some(old_first) = old.leaderboard_response.entries.first() {
+            let new_first = new.leaderboard_response.entries.first();
+            let regressed = match new_first {
+                Some(new_first) => is_score_better(old_first.score, new_first.score, new.mode),
+                None => true,
+            };
+            if regressed {
+                let format_score = |score| distance_util::format_score(score, new.mode).unwrap();
+                diff.regressions.push(Regression {
+                    leaderboard_name: new.leaderboard_name.clone(),
+                    map_name: new.name.clone(),
+                    mode: new.mode.to_string(),
+                    record_old: format_score(old_first.score),
+                    old_recordholder: old_first.player_name.clone(),
+                    record_new: new_first.map(|x| format_score(x.score)),
+                    new_recordholder: new_first.map(|x| x.player_name.clone()),
+                });
+            }
+        }
+    }
+
+    diff
+}
+
+impl SnapshotDiff {
+    pub fn render(&self, format: OutputFormat) -> Result<String, Error> {
+        match format {
+            OutputFormat::Text => Ok(self.render_text()),
+            OutputFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+        }
+    }
+
+    fn render_text(&self) -> String {
+        let mut s = String::new();
+
+        writeln!(s, "New records ({}):", self.new_records.len()).unwrap();
+        for entry in &self.new_records {
+            writeln!(s, "  {}", describe_entry(entry)).unwrap();
+        }
+
+        writeln!(s, "Regressions ({}):", self.regressions.len()).unwrap();
+        for regression in &self.regressions {
+            let now = match (&regression.record_new, &regression.new_recordholder) {
+                (Some(record), Some(holder)) => format!("{} by {}", record, holder),
+                _ => "no entries".to_owned(),
+            };
+            writeln!(
+                s,
+                "  [{}] {}: {} by {}, now {}",
+                regression.mode,
+                regression.map_name,
+                regression.record_old,
+                regression.old_recordholder,
+                now
+            )
+            .unwrap();
+        }
+
+        for (heading, levels) in
+            [("Added levels", &self.added_levels), ("Removed levels", &self.removed_levels)].iter()
+        {
+            writeln!(s, "{} ({}):", heading, levels.len()).unwrap();
+            for level in levels.iter() {
+                writeln!(s, "  {}", level).unwrap();
+            }
+        }
+
+        writeln!(s, "Name changes ({}):", self.name_changes.len()).unwrap();
+        for change in &self.name_changes {
+            writeln!(
+                s,
+                "  {}: '{}' -> '{}'",
+                change.leaderboard_name, change.old_name, change.new_name
+            )
+            .unwrap();
+        }
+
+        s
+    }
+}
+
+#[test]
+fn test_diff() {
+    use crate::test_util;
+    use chrono::Utc;
+
+    let level = |name, leaderboard_name, record| {
+        test_util::level_info(name, leaderboard_name, record, Utc::now())
+    };
+
+    let old = [
+        level("Broken Symmetry", "a", Some(("Seeker", 20000))),
+        level("Lost Society", "b", Some(("Seeker", 30000))),
+        level("Old Level", "c", Some(("Runner", 40000))),
+        level("Renamed", "d", None),
+    ];
+    let new = [
+        level("Broken Symmetry", "a", Some(("Runner", 19000))),
+        level("Lost Society", "b", Some(("Runner", 31000))),
+        level("Renamed Level", "d", None),
+        level("New Level", "e", Some(("Seeker", 50000))),
+    ];
+    let diff = diff(&old, &new);
+
+    let new_records: Vec<_> = diff.new_records.iter().map(|x| x.map_name.as_str()).collect();
+    assert_eq!(new_records, ["New Level", "Broken Symmetry"]);
+    assert_eq!(diff.regressions.len(), 1);
+    assert_eq!(diff.regressions[0].leaderboard_name, "b");
+    assert_eq!(diff.regressions[0].new_recordholder.as_deref(), Some("Runner"));
+    assert_eq!(diff.added_levels, ["e"]);
+    assert_eq!(diff.removed_levels, ["c"]);
+    assert_eq!(diff.name_changes.len(), 1);
+    assert_eq!(diff.name_changes[0].new_name, "Renamed Level");
+}
@@ -0,0 +1,332 @@
+use crate::{
+    config::{DigestConfig, SmtpConfig, SmtpSecurity},
+    domain::ChangelistEntry,
+    feed::{escape, profile_link},
+    persistence::{
+        impls::file_json::{load_file, write_file_atomically},
+        LoadError,
+    },
+};
+use anyhow::{Context, Error};
+use async_std::task;
+use chrono::{DateTime, Duration, Utc};
+use lettre::{
+    message::{Mailbox, MultiPart},
+    transport::smtp::authentication::Credentials,
+    Message, SmtpTransport, Transport,
+};
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt::Write, path::Path};
+
+const SMTP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// When the previous digest went out.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DigestState {
+    pub last_sent: Option<DateTime<Utc>>,
+}
+
+/// The changelist entries fetched within a period, by mode, then by level.
+#[derive(Debug)]
+struct Digest<'a> {
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    modes: BTreeMap<&'a str, BTreeMap<(String, String), Vec<&'a ChangelistEntry>>>,
+}
+
+/// Sends a digest of the entries fetched since the previous one, if `interval_hours` have passed
+/// since then. The first digest only starts the clock, so the whole changelist isn't sent at once.
+pub async fn send_if_due(
+    config: &DigestConfig,
+    changelist: &[ChangelistEntry],
+    state_path: &Path,
+) -> Result<(), Error> {
+    send_if_due_at(config, changelist, state_path, Utc::now()).await
+}
+
+async fn send_if_due_at(
+    config: &DigestConfig,
+    changelist: &[ChangelistEntry],
+    state_path: &Path,
+    now: DateTime<Utc>,
+) -> Result<(), Error> {
+    let mut state = DigestState::load(state_path)?;
+    let since = match state.last_sent {
+        Some(x) if now - x < Duration::hours(config.interval_hours) => return Ok(()),
+        Some(x) => x,
+        None => {
+            info!("Starting the digest period; the first digest covers the records from now on");
+            state.last_sent = Some(now);
+            return state.save(state_path);
+        }
+    };
+
+    let digest = Digest::new(changelist, since, now);
+    if digest.is_empty() {
+        info!("No new records since the last digest");
+    } else {
+        let message = digest.to_message(config)?;
+        send(&config.smtp, message).await.context("Error sending the digest email")?;
+    }
+
+    state.last_sent = Some(now);
+    state.save(state_path)
+}
+
+impl DigestState {
+    /// Loads the state, treating a missing state file as no digest having been sent.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match load_file(path) {
+            Ok(x) => Ok(x),
+            Err(LoadError::DoesNotExist) => Ok(DigestState::default()),
+            Err(e) => {
+                Err(e).with_context(|| format!("Error loading digest state '{}'", path.display()))
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let serialized = serde_json::to_vec(self)?;
+        write_file_atomically(&serialized, path)
+            .with_context(|| format!("Error saving digest state '{}'", path.display()))
+    }
+}
+
+impl<'a> Digest<'a> {
+    /// Collects the entries fetched after `since` and no later than `until`. Entries whose fetch
+    /// time can't be parsed are left out.
+    fn new(changelist: &'a [ChangelistEntry], since: DateTime<Utc>, until: DateTime<Utc>) -> Self {
+        let mut modes: BTreeMap<_, BTreeMap<_, Vec<_>>> = BTreeMap::new();
+        for entry in changelist {
+            let fetch_time = match DateTime::parse_from_rfc2822(&entry.fetch_time) {
+                Ok(x) => x.with_timezone(&Utc),
+                Err(_) => continue,
+            };
+            if fetch_time > since && fetch_time <= until {
+                modes
+                    .entry(entry.mode.as_str())
+                    .or_default()
+                    .entry((entry.map_name.clone(), entry.level_key().0))
+                    .or_default()
+                    .push(entry);
+            }
+        }
+
+        Digest { since, until, modes }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.modes.is_empty()
+    }
+
+    fn entry_count(&self) -> usize {
+        self.modes.values().flat_map(|levels| levels.values()).map(Vec::len).sum()
+    }
+
+    fn to_message(&self, config: &DigestConfig) -> Result<Message, Error> {
+        let count = self.entry_count();
+        let subject = format!(
+            "{}: {} new record{}",
+            config.subject,
+            count,
+            if count == 1 { "" } else { "s" }
+        );
+
+        let mut builder = Message::builder().subject(subject).from(parse_mailbox(&config.from)?);
+        for to in &config.to {
+            builder = builder.to(parse_mailbox(to)?);
+        }
+
+        Ok(builder
+            .multipart(MultiPart::alternative_plain_html(self.render_text(), self.render_html()))?)
+    }
+
+    fn period(&self) -> String {
+        format!(
+            "{} to {}",
+            self.since.format("%Y-%m-%d %H:%M UTC"),
+            self.until.format("%Y-%m-%d %H:%M UTC")
+        )
+    }
+
+    fn render_text(&self) -> String {
+        let mut s = String::new();
+
+        writeln!(s, "{} new world records from {}.", self.entry_count(), self.period()).unwrap();
+        for (mode, levels) in &self.modes {
+            write!(s, "\n== {} ==\n", mode).unwrap();
+            for ((map_name, _), entries) in levels {
+                write!(s, "\n{}", map_name).unwrap();
+                if let Some(url) = entries[0].workshop_item_url() {
+                    write!(s, " <{}>", url).unwrap();
+                }
+                s.push('\n');
+                for entry in entries {
+                    write!(s, "  {} by {}", entry.record_new, entry.new_recordholder).unwrap();
+                    if let (Some(record_old), Some(old_recordholder)) =
+                        (&entry.record_old, &entry.old_recordholder)
+                    {
+                        write!(s, " (previously {} by {})", record_old, old_recordholder).unwrap();
+                    }
+                    s.push('\n');
+                }
+            }
+        }
+
+        s
+    }
+
+    fn render_html(&self) -> String {
+        let mut s = String::new();
+
+        s.push_str("<!DOCTYPE html>\n<html>\n<body>\n");
+        writeln!(
+            s,
+            "<p>{} new world records from {}.</p>",
+            self.entry_count(),
+            escape(&self.period())
+        )
+        .unwrap();
+        for (mode, levels) in &self.modes {
+            writeln!(s, "<h2>{}</h2>", escape(mode)).unwrap();
+            for ((map_name, _), entries) in levels {
+                match entries[0].workshop_item_url() {
+                    Some(url) => writeln!(
+                        s,
+                        "<h3><a href=\"{}\">{}</a></h3>",
+                        escape(&url),
+                        escape(map_name)
+                    ),
+                    None => writeln!(s, "<h3>{}</h3>", escape(map_name)),
+                }
+                .unwrap();
+                s.push_str("<ul>\n");
+                for entry in entries {
+                    write!(
+                        s,
+                        "<li>{} by {}",
+                        escape(&entry.record_new),
+                        profile_link(&entry.steam_id_new_recordholder, &entry.new_recordholder)
+                    )
+                    .unwrap();
+                    if let (Some(record_old), Some(old_recordholder), Some(steam_id)) = (
+                        &entry.record_old,
+                        &entry.old_recordholder,
+                        &entry.steam_id_old_recordholder,
+                    ) {
+                        write!(
+                            s,
+                            " (previously {} by {})",
+                            escape(record_old),
+                            profile_link(steam_id, old_recordholder)
+                        )
+                        .unwrap();
+                    }
+                    s.push_str("</li>\n");
+                }
+                s.push_str("</ul>\n");
+            }
+        }
+        s.push_str("</body>\n</html>\n");
+
+        s
+    }
+}
+
+fn parse_mailbox(s: &str) -> Result<Mailbox, Error> {
+    s.parse().with_context(|| format!("Invalid email address '{}'", s))
+}
+
+/// Delivers the message on a blocking thread.
+async fn send(config: &SmtpConfig, message: Message) -> Result<(), Error> {
+    let config = config.clone();
+    task::spawn_blocking(move || {
+        let mut builder = match config.security {
+            SmtpSecurity::Starttls => SmtpTransport::starttls_relay(&config.host)?,
+            SmtpSecurity::Tls => SmtpTransport::relay(&config.host)?,
+            SmtpSecurity::None => SmtpTransport::builder_dangerous(&config.host),
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (config.username, config.password) {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        builder.timeout(Some(SMTP_TIMEOUT)).build().send(&message)?;
+
+        Ok(())
+    })
+    .await
+}
+
+#[test]
+fn test_digest_delivery() {
+    use crate::test_util::{changelist_of, day, level_info, MockSmtpServer};
+    use distance_util::LeaderboardGameMode;
+
+    let mut changelist = changelist_of(&[
+        vec![level_info("Broken <Symmetry>", "a", Some(("Seeker & Co", 20000)), day(1))],
+        vec![level_info("Lost Society", "b", Some(("Runner", 30000)), day(2))],
+        vec![level_info("Lost Society", "b", Some(("Jumper", 29000)), day(3))],
+    ]);
+    changelist[0].mode = "Stunt".to_owned();
+
+    let server = MockSmtpServer::start();
+    let config = DigestConfig {
+        interval_hours: 24,
+        from: "distance-log <log@example.org>".to_owned(),
+        to: vec!["someone@example.org".to_owned()],
+        subject: "Distance world records".to_owned(),
+        smtp: SmtpConfig {
+            host: "127.0.0.1".to_owned(),
+            port: Some(server.port()),
+            security: SmtpSecurity::None,
+            username: Some("user".to_owned()),
+            password: Some("password".to_owned()),
+        },
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = dir.path().join("digest_state.json");
+
+    // The first run only starts the clock
+    task::block_on(send_if_due_at(&config, &changelist, &state_path, day(1) + Duration::hours(12)))
+        .unwrap();
+    assert!(server.messages().is_empty());
+
+    // Not a day later yet
+    task::block_on(send_if_due_at(&config, &changelist, &state_path, day(2))).unwrap();
+    assert!(server.messages().is_empty());
+
+    task::block_on(send_if_due_at(&config, &changelist, &state_path, day(3))).unwrap();
+    let messages = server.messages();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].authenticated);
+    assert_eq!(messages[0].from, "log@example.org");
+    assert_eq!(messages[0].to, vec!["someone@example.org".to_owned()]);
+    let data = &messages[0].data;
+    assert!(data.contains("Subject: Distance world records: 2 new records"));
+    assert!(data.contains("multipart/alternative"));
+    assert!(data.contains("== Sprint ==\r\n\r\nLost Society\r\n"));
+    let format = |score| distance_util::format_score(score, LeaderboardGameMode::Sprint).unwrap();
+    assert!(data.contains(&format!(
+        "  {} by Jumper (previously {} by Runner)",
+        format(29000),
+        format(30000)
+    )));
+    // The entry from before the digest period is left out
+    assert!(!data.contains("Broken"));
+    assert_eq!(DigestState::load(&state_path).unwrap().last_sent, Some(day(3)));
+
+    // A digest that can't be sent is left for the next run
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let unreachable =
+        DigestConfig { smtp: SmtpConfig { port: Some(port), ..config.smtp.clone() }, ..config };
+    let mut entry = changelist[2].clone();
+    entry.fetch_time = day(4).to_rfc2822();
+    changelist.push(entry);
+    let later = day(4) + Duration::hours(1);
+    assert!(task::block_on(send_if_due_at(&unreachable, &changelist, &state_path, later)).is_err());
+    assert_eq!(DigestState::load(&state_path).unwrap().last_sent, Some(day(3)));
+}
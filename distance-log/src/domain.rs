@@ -1,7 +1,10 @@
-use crate::steamworks::{LeaderboardResponse, WorkshopResponse};
-use chrono::{DateTime, Utc};
+use crate::backend::{LeaderboardResponse, WorkshopResponse};
+use chrono::{DateTime, NaiveDate, Utc};
 use distance_util::LeaderboardGameMode;
 use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const STEAM_COMMUNITY_URL: &str = "https://steamcommunity.com";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LevelInfo {
@@ -11,9 +14,48 @@ pub struct LevelInfo {
     pub workshop_response: Option<WorkshopResponse>,
     pub leaderboard_response: LeaderboardResponse,
     pub timestamp: DateTime<Utc>,
+
+    /// When the leaderboard was last fetched successfully. `None` for results stored before this
+    /// was recorded.
+    #[serde(default)]
+    pub last_refreshed: Option<DateTime<Utc>>,
+}
+
+impl LevelInfo {
+    /// When the leaderboard was last fetched successfully, falling back to the time these results
+    /// were stored.
+    pub fn last_refreshed(&self) -> DateTime<Utc> {
+        self.last_refreshed.unwrap_or(self.timestamp)
+    }
+
+    /// Identifies the leaderboard the same way changelist entries do; see
+    /// `ChangelistEntry::level_key`.
+    pub fn level_key(&self) -> (String, String) {
+        let id = match &self.workshop_response {
+            Some(x) => x.published_file_id.to_string(),
+            None => self.name.clone(),
+        };
+        (id, self.mode.to_string())
+    }
+}
+
+/// The entry count history of a single leaderboard, sampled at most once per day.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelPopularity {
+    pub leaderboard_name: String,
+    pub samples: Vec<EntryCountSample>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntryCountSample {
+    pub date: NaiveDate,
+    pub entry_count: u32,
+
+    /// Average number of new entries per day since the previous sample.
+    pub new_entries_per_day: Option<f64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChangelistEntry {
     pub map_name: String,
     pub map_author: Option<String>,
@@ -28,6 +70,13 @@ pub struct ChangelistEntry {
     pub steam_id_new_recordholder: String,
     pub steam_id_old_recordholder: Option<String>,
     pub fetch_time: String,
+
+    /// The raw scores behind `record_new` and `record_old`. `None` for entries recorded before
+    /// these were stored.
+    #[serde(default)]
+    pub score_new: Option<i32>,
+    #[serde(default)]
+    pub score_old: Option<i32>,
 }
 
 impl ChangelistEntry {
@@ -39,4 +88,51 @@ impl ChangelistEntry {
             && self.steam_id_author == other.steam_id_author
             && self.steam_id_new_recordholder == other.steam_id_new_recordholder
     }
+
+    /// Changelist entries don't name their leaderboard, so this identifies it by the workshop item
+    /// ID, or the map name for official levels, along with the mode.
+    pub fn level_key(&self) -> (String, String) {
+        let id = self.workshop_item_id.clone().unwrap_or_else(|| self.map_name.clone());
+        (id, self.mode.clone())
+    }
+
+    /// An ID that stays the same however often the entry is serialized or loaded again, derived
+    /// from the fields that identify it.
+    pub fn id(&self) -> String {
+        let fields = [
+            self.map_name.as_str(),
+            self.mode.as_str(),
+            self.record_new.as_str(),
+            self.workshop_item_id.as_deref().unwrap_or(""),
+            self.steam_id_new_recordholder.as_str(),
+            self.fetch_time.as_str(),
+        ];
+        format!("{:x}", Sha256::digest(fields.join("\n").as_bytes()))
+    }
+
+    /// The Steam Workshop page of the level, for workshop levels.
+    pub fn workshop_item_url(&self) -> Option<String> {
+        self.workshop_item_id.as_deref().map(workshop_item_url)
+    }
+
+    /// `None` if the mode name isn't recognized.
+    pub fn game_mode(&self) -> Option<LeaderboardGameMode> {
+        [LeaderboardGameMode::Sprint, LeaderboardGameMode::Challenge, LeaderboardGameMode::Stunt]
+            .iter()
+            .copied()
+            .find(|mode| mode.name() == self.mode)
+    }
+
+    /// How much the new record beats the old one by, formatted like the records themselves.
+    pub fn improvement(&self) -> Option<String> {
+        distance_util::format_score((self.score_new? - self.score_old?).abs(), self.game_mode()?)
+    }
+}
+
+pub fn steam_profile_url(steam_id: &str) -> String {
+    format!("{}/profiles/{}", STEAM_COMMUNITY_URL, steam_id)
+}
+
+pub fn workshop_item_url(workshop_item_id: &str) -> String {
+    format!("{}/sharedfiles/filedetails/?id={}", STEAM_COMMUNITY_URL, workshop_item_id)
 }
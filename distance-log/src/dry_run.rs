@@ -0,0 +1,65 @@
+use crate::{domain::ChangelistEntry, output::OutputFormat};
+use anyhow::Error;
+use serde_derive::Serialize;
+use std::fmt::Write;
+
+/// What an update would have changed, had it not been a dry run.
+#[derive(Debug, Default, Serialize)]
+pub struct DryRunReport {
+    /// Entries that would have been added to the changelist.
+    pub new_entries: Vec<ChangelistEntry>,
+
+    /// Leaderboards that came back empty, whose entries were filled in from the previous query
+    /// results.
+    pub backfilled_levels: Vec<String>,
+
+    /// Leaderboards in the previous query results that weren't fetched this time. Their previous
+    /// results are carried over.
+    pub removed_levels: Vec<String>,
+}
+
+impl DryRunReport {
+    pub fn render(&self, format: OutputFormat) -> Result<String, Error> {
+        match format {
+            OutputFormat::Text => Ok(self.render_text()),
+            OutputFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+        }
+    }
+
+    fn render_text(&self) -> String {
+        let mut s = String::new();
+
+        writeln!(s, "New changelist entries ({}):", self.new_entries.len()).unwrap();
+        for entry in &self.new_entries {
+            writeln!(s, "  {}", describe_entry(entry)).unwrap();
+        }
+
+        for (heading, levels) in [
+            ("Back-filled levels", &self.backfilled_levels),
+            ("Removed levels", &self.removed_levels),
+        ]
+        .iter()
+        {
+            writeln!(s, "{} ({}):", heading, levels.len()).unwrap();
+            for level in levels.iter() {
+                writeln!(s, "  {}", level).unwrap();
+            }
+        }
+
+        s
+    }
+}
+
+/// A one-line summary of a changelist entry.
+pub fn describe_entry(entry: &ChangelistEntry) -> String {
+    let mut s = format!(
+        "[{}] {}: {} by {}",
+        entry.mode, entry.map_name, entry.record_new, entry.new_recordholder
+    );
+    if let (Some(record_old), Some(old_recordholder)) = (&entry.record_old, &entry.old_recordholder)
+    {
+        write!(s, " (previously {} by {})", record_old, old_recordholder).unwrap();
+    }
+
+    s
+}
@@ -0,0 +1,387 @@
+use crate::{
+    backend::LeaderboardEntry,
+    domain::{ChangelistEntry, LevelInfo},
+    feed::fetch_time,
+    persistence::impls::file_json::write_file_atomically,
+};
+use anyhow::{bail, Context, Error};
+use chrono::{DateTime, SecondsFormat, Utc};
+use distance_util::LeaderboardGameMode;
+use log::info;
+use parquet::{
+    basic::Compression,
+    column::writer::ColumnWriterImpl,
+    data_type::{ByteArray, ByteArrayType, DataType, Int32Type, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+use std::{fs, path::Path, str::FromStr, sync::Arc};
+
+/// Which file format tables are exported in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "parquet" => Ok(ExportFormat::Parquet),
+            _ => bail!("unknown export format '{}'; expected 'csv' or 'parquet'", s),
+        }
+    }
+}
+
+/// Only changelist entries fetched, and leaderboards last refreshed, at or after `since` and
+/// before `until` are exported.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TimeRange {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// A flat table whose columns each have a single type. Every column is nullable.
+#[derive(Debug, Default)]
+struct Table {
+    columns: Vec<Column>,
+}
+
+#[derive(Debug)]
+struct Column {
+    name: &'static str,
+    values: Values,
+}
+
+#[derive(Debug)]
+enum Values {
+    Int32(Vec<Option<i32>>),
+    Int64(Vec<Option<i64>>),
+    String(Vec<Option<String>>),
+
+    /// Written to CSV as RFC 3339 with milliseconds, and to Parquet as milliseconds since the Unix
+    /// epoch, adjusted to UTC.
+    Timestamp(Vec<Option<DateTime<Utc>>>),
+}
+
+/// Writes `changelist` and `leaderboards` tables to `directory`, as `.csv` and `.parquet` files
+/// depending on `formats`.
+pub fn write_tables(
+    directory: &Path,
+    formats: &[ExportFormat],
+    changelist: &[ChangelistEntry],
+    level_infos: &[LevelInfo],
+    range: TimeRange,
+) -> Result<(), Error> {
+    let entries: Vec<_> = changelist.iter().filter(|x| range.contains(fetch_time(x))).collect();
+    let level_infos: Vec<_> =
+        level_infos.iter().filter(|x| range.contains(Some(x.last_refreshed()))).collect();
+    let tables = [
+        ("changelist", changelist_table(&entries)),
+        ("leaderboards", leaderboards_table(&level_infos)),
+    ];
+
+    fs::create_dir_all(directory)
+        .with_context(|| format!("Error creating directory '{}'", directory.display()))?;
+    for (name, table) in &tables {
+        for &format in formats {
+            let (extension, contents) = match format {
+                ExportFormat::Csv => ("csv", table.to_csv()?),
+                ExportFormat::Parquet => ("parquet", table.to_parquet(name)?),
+            };
+            let path = directory.join(format!("{}.{}", name, extension));
+            write_file_atomically(&contents, &path)
+                .with_context(|| format!("Error writing '{}'", path.display()))?;
+            info!("Wrote {} rows to '{}'", table.len(), path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// One row per entry.
+fn changelist_table(entries: &[&ChangelistEntry]) -> Table {
+    let mut table = Table::default();
+    let string = |f: fn(&ChangelistEntry) -> Option<&String>| {
+        Values::String(entries.iter().map(|x| f(x).cloned()).collect())
+    };
+
+    table.add("id", Values::String(entries.iter().map(|x| Some(x.id())).collect()));
+    table.add("fetch_time", Values::Timestamp(entries.iter().map(|x| fetch_time(x)).collect()));
+    table.add("level", string(|x| Some(&x.map_name)));
+    table.add("workshop_item_id", string(|x| x.workshop_item_id.as_ref()));
+    table.add("mode", string(|x| Some(&x.mode)));
+    table.add("author", string(|x| x.map_author.as_ref()));
+    table.add("author_steam_id", string(|x| x.steam_id_author.as_ref()));
+    table.add("new_recordholder", string(|x| Some(&x.new_recordholder)));
+    table.add("new_recordholder_steam_id", string(|x| Some(&x.steam_id_new_recordholder)));
+    table.add("score_new", Values::Int32(entries.iter().map(|x| x.score_new).collect()));
+    table.add(
+        "time_new_ms",
+        Values::Int64(entries.iter().map(|x| time_ms(x.game_mode(), x.score_new)).collect()),
+    );
+    table.add("record_new", string(|x| Some(&x.record_new)));
+    table.add("old_recordholder", string(|x| x.old_recordholder.as_ref()));
+    table.add("old_recordholder_steam_id", string(|x| x.steam_id_old_recordholder.as_ref()));
+    table.add("score_old", Values::Int32(entries.iter().map(|x| x.score_old).collect()));
+    table.add(
+        "time_old_ms",
+        Values::Int64(entries.iter().map(|x| time_ms(x.game_mode(), x.score_old)).collect()),
+    );
+    table.add("record_old", string(|x| x.record_old.as_ref()));
+
+    table
+}
+
+/// One row per stored leaderboard entry, which are the top entries of each leaderboard, and one
+/// row with only the leaderboard's columns for empty leaderboards.
+fn leaderboards_table(level_infos: &[&LevelInfo]) -> Table {
+    let rows: Vec<_> = level_infos
+        .iter()
+        .flat_map(|level_info| {
+            let entries = &level_info.leaderboard_response.entries;
+            let entries: Vec<_> =
+                if entries.is_empty() { vec![None] } else { entries.iter().map(Some).collect() };
+            entries.into_iter().map(move |entry| (*level_info, entry))
+        })
+        .collect();
+    let mut table = Table::default();
+    let string = |f: &dyn Fn(&LevelInfo, Option<&LeaderboardEntry>) -> Option<String>| {
+        Values::String(rows.iter().map(|(level_info, entry)| f(level_info, *entry)).collect())
+    };
+
+    table.add("leaderboard_name", string(&|x, _| Some(x.leaderboard_name.clone())));
+    table.add("level", string(&|x, _| Some(x.name.clone())));
+    table.add(
+        "workshop_item_id",
+        string(&|x, _| x.workshop_response.as_ref().map(|x| x.published_file_id.to_string())),
+    );
+    table.add("mode", string(&|x, _| Some(x.mode.to_string())));
+    table
+        .add("author", string(&|x, _| x.workshop_response.as_ref().map(|x| x.author_name.clone())));
+    table.add(
+        "author_steam_id",
+        string(&|x, _| x.workshop_response.as_ref().map(|x| x.steam_id_owner.to_string())),
+    );
+    table.add(
+        "entry_count",
+        Values::Int64(
+            rows.iter().map(|(x, _)| x.leaderboard_response.entry_count.map(i64::from)).collect(),
+        ),
+    );
+    table.add(
+        "last_refreshed",
+        Values::Timestamp(rows.iter().map(|(x, _)| Some(x.last_refreshed())).collect()),
+    );
+    table.add("rank", Values::Int32(rows.iter().map(|(_, x)| x.map(|x| x.global_rank)).collect()));
+    table.add("steam_id", string(&|_, x| x.map(|x| x.steam_id.to_string())));
+    table.add("player", string(&|_, x| x.map(|x| x.player_name.clone())));
+    table.add("score", Values::Int32(rows.iter().map(|(_, x)| x.map(|x| x.score)).collect()));
+    table.add(
+        "time_ms",
+        Values::Int64(
+            rows.iter()
+                .map(|(level_info, x)| time_ms(Some(level_info.mode), x.map(|x| x.score)))
+                .collect(),
+        ),
+    );
+    table.add(
+        "record",
+        string(&|level_info, x| {
+            x.and_then(|x| distance_util::format_score(x.score, level_info.mode))
+        }),
+    );
+
+    table
+}
+
+/// Scores in timed modes are milliseconds. `None` for other modes.
+fn time_ms(mode: Option<LeaderboardGameMode>, score: Option<i32>) -> Option<i64> {
+    match mode? {
+        LeaderboardGameMode::Sprint | LeaderboardGameMode::Challenge => score.map(i64::from),
+        LeaderboardGameMode::Stunt => None,
+    }
+}
+
+impl TimeRange {
+    /// Times that aren't known are only in unbounded ranges.
+    fn contains(&self, time: Option<DateTime<Utc>>) -> bool {
+        match (time, self.since, self.until) {
+            (_, None, None) => true,
+            (None, _, _) => false,
+            (Some(time), since, until) => {
+                since.map(|x| time >= x).unwrap_or(true) && until.map(|x| time < x).unwrap_or(true)
+            }
+        }
+    }
+}
+
+impl Table {
+    fn add(&mut self, name: &'static str, values: Values) {
+        self.columns.push(Column { name, values });
+    }
+
+    fn len(&self) -> usize {
+        self.columns.first().map(|x| x.values.len()).unwrap_or(0)
+    }
+
+    fn to_csv(&self) -> Result<Vec<u8>, Error> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(self.columns.iter().map(|x| x.name))?;
+        for row in 0..self.len() {
+            writer.write_record(self.columns.iter().map(|x| x.values.csv_field(row)))?;
+        }
+
+        writer.into_inner().map_err(|e| e.into_error().into())
+    }
+
+    fn to_parquet(&self, name: &str) -> Result<Vec<u8>, Error> {
+        let fields: Vec<_> = self.columns.iter().map(|x| x.values.parquet_field(x.name)).collect();
+        let schema = parse_message_type(&format!("message {} {{ {} }}", name, fields.join(" ")))?;
+        let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+
+        let mut writer =
+            SerializedFileWriter::new(Vec::new(), Arc::new(schema), Arc::new(properties))?;
+        let mut row_group = writer.next_row_group()?;
+        for column in &self.columns {
+            let mut column_writer =
+                row_group.next_column()?.context("The Parquet schema is missing columns")?;
+            match &column.values {
+                Values::Int32(x) => {
+                    write_column::<Int32Type>(column_writer.typed(), x.iter().copied())?
+                }
+                Values::Int64(x) => {
+                    write_column::<Int64Type>(column_writer.typed(), x.iter().copied())?
+                }
+                Values::String(x) => write_column::<ByteArrayType>(
+                    column_writer.typed(),
+                    x.iter().map(|x| x.as_deref().map(ByteArray::from)),
+                )?,
+                Values::Timestamp(x) => write_column::<Int64Type>(
+                    column_writer.typed(),
+                    x.iter().map(|x| x.map(|x| x.timestamp_millis())),
+                )?,
+            }
+            column_writer.close()?;
+        }
+        row_group.close()?;
+
+        Ok(writer.into_inner()?)
+    }
+}
+
+fn write_column<T: DataType>(
+    writer: &mut ColumnWriterImpl<'_, T>,
+    values: impl Iterator<Item = Option<T::T>>,
+) -> Result<(), Error> {
+    let mut present = Vec::new();
+    let mut definition_levels = Vec::new();
+    for value in values {
+        definition_levels.push(value.is_some() as i16);
+        present.extend(value);
+    }
+    writer.write_batch(&present, Some(&definition_levels), None)?;
+
+    Ok(())
+}
+
+impl Values {
+    fn len(&self) -> usize {
+        match self {
+            Values::Int32(x) => x.len(),
+            Values::Int64(x) => x.len(),
+            Values::String(x) => x.len(),
+            Values::Timestamp(x) => x.len(),
+        }
+    }
+
+    /// Empty for nulls.
+    fn csv_field(&self, row: usize) -> String {
+        match self {
+            Values::Int32(x) => x[row].map(|x| x.to_string()),
+            Values::Int64(x) => x[row].map(|x| x.to_string()),
+            Values::String(x) => x[row].clone(),
+            Values::Timestamp(x) => x[row].map(|x| x.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        }
+        .unwrap_or_default()
+    }
+
+    fn parquet_field(&self, name: &str) -> String {
+        let field_type = match self {
+            Values::Int32(_) => "INT32 {}",
+            Values::Int64(_) => "INT64 {}",
+            Values::String(_) => "BYTE_ARRAY {} (STRING)",
+            Values::Timestamp(_) => "INT64 {} (TIMESTAMP(MILLIS, true))",
+        };
+
+        format!("OPTIONAL {};", field_type.replace("{}", name))
+    }
+}
+
+#[test]
+fn test_export() {
+    use crate::test_util::{changelist_of, day, level_info};
+    use parquet::{
+        basic::LogicalType,
+        file::reader::{FileReader, SerializedFileReader},
+    };
+
+    let first = level_info("Broken Symmetry", "Broken Symmetry", Some(("Seeker", 20000)), day(1));
+    let mut second =
+        level_info("Broken Symmetry", "Broken Symmetry", Some(("Other", 19000)), day(2));
+    second.leaderboard_response.entries[0].steam_id = 76561198000000000;
+    let empty = level_info("Lost Society", "Lost Society", None, day(2));
+    let changelist = changelist_of(&[vec![first], vec![second.clone()]]);
+    assert_eq!(changelist.len(), 2);
+
+    let dir = tempfile::tempdir().unwrap();
+    let range = TimeRange { since: Some(day(2)), until: None };
+    write_tables(
+        dir.path(),
+        &[ExportFormat::Csv, ExportFormat::Parquet],
+        &changelist,
+        &[second, empty],
+        range,
+    )
+    .unwrap();
+
+    let mut reader = csv::Reader::from_path(dir.path().join("changelist.csv")).unwrap();
+    let headers = reader.headers().unwrap().clone();
+    let rows: Vec<_> = reader.records().map(|x| x.unwrap()).collect();
+    assert_eq!(rows.len(), 1);
+    let field = |name| &rows[0][headers.iter().position(|x| x == name).unwrap()];
+    assert_eq!(field("fetch_time"), "2020-05-02T00:00:00.000Z");
+    assert_eq!(field("new_recordholder_steam_id"), "76561198000000000");
+    assert_eq!(field("score_new"), "19000");
+    assert_eq!(field("time_new_ms"), "19000");
+    assert_eq!(field("old_recordholder"), "Seeker");
+    assert_eq!(field("workshop_item_id"), "");
+
+    let reader = csv::Reader::from_path(dir.path().join("leaderboards.csv")).unwrap();
+    assert_eq!(reader.into_records().count(), 2);
+
+    let file = fs::File::open(dir.path().join("changelist.parquet")).unwrap();
+    let reader = SerializedFileReader::new(file).unwrap();
+    assert_eq!(reader.metadata().file_metadata().num_rows(), 1);
+    let schema = reader.metadata().file_metadata().schema_descr();
+    let column = |name| schema.columns().iter().find(|x| x.name() == name).unwrap().clone();
+    assert!(matches!(
+        column("fetch_time").logical_type(),
+        Some(LogicalType::Timestamp { is_adjusted_to_u_t_c: true, .. })
+    ));
+    assert_eq!(column("new_recordholder_steam_id").logical_type(), Some(LogicalType::String));
+    let row = reader.get_row_iter(None).unwrap().next().unwrap().unwrap();
+    let fields: Vec<_> =
+        row.get_column_iter().map(|(name, x)| (name.clone(), x.to_string())).collect();
+    assert!(fields.contains(&("score_new".to_owned(), "19000".to_owned())));
+    assert!(fields.contains(&("score_old".to_owned(), "20000".to_owned())));
+    let record_old = distance_util::format_score(20000, LeaderboardGameMode::Sprint).unwrap();
+    assert!(fields.contains(&("record_old".to_owned(), format!("\"{}\"", record_old))));
+
+    let file = fs::File::open(dir.path().join("leaderboards.parquet")).unwrap();
+    let reader = SerializedFileReader::new(file).unwrap();
+    assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+}
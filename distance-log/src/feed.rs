@@ -0,0 +1,201 @@
+use crate::{
+    config::FeedConfig,
+    domain::{steam_profile_url, ChangelistEntry},
+    dry_run::describe_entry,
+    persistence::impls::file_json::write_file_atomically,
+};
+use anyhow::{Context, Error};
+use chrono::{DateTime, Utc};
+use std::{fmt::Write, path::Path};
+
+/// Writes the Atom feed of the latest changelist entries, and the RSS feed if one is configured.
+/// Each file is replaced atomically, so readers never see a partial feed.
+pub fn write_feeds(config: &FeedConfig, changelist: &[ChangelistEntry]) -> Result<(), Error> {
+    let entries = latest_entries(changelist, config.max_entries);
+
+    write_file_atomically(render_atom(config, &entries).as_bytes(), &config.atom_path)
+        .with_context(|| format!("Error writing Atom feed '{}'", config.atom_path.display()))?;
+    if let Some(rss_path) = &config.rss_path {
+        write_file_atomically(render_rss(config, &entries).as_bytes(), rss_path)
+            .with_context(|| format!("Error writing RSS feed '{}'", rss_path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// The newest `max_entries` entries, newest first. New entries are appended to the changelist.
+fn latest_entries(changelist: &[ChangelistEntry], max_entries: usize) -> Vec<&ChangelistEntry> {
+    changelist.iter().rev().take(max_entries).collect()
+}
+
+fn render_atom(config: &FeedConfig, entries: &[&ChangelistEntry]) -> String {
+    let mut s = String::new();
+    let updated = entries.first().and_then(|x| fetch_time(x)).unwrap_or_else(Utc::now);
+
+    s.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    s.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    writeln!(s, "  <id>{}</id>", escape(&config.site_url)).unwrap();
+    writeln!(s, "  <title>{}</title>", escape(&config.title)).unwrap();
+    writeln!(s, "  <updated>{}</updated>", updated.to_rfc3339()).unwrap();
+    writeln!(s, "  <link href=\"{}\"/>", escape(&config.site_url)).unwrap();
+    writeln!(s, "  <link rel=\"self\" href=\"{}\"/>", escape(&feed_url(config, &config.atom_path)))
+        .unwrap();
+    s.push_str("  <author><name>distance-log</name></author>\n");
+    for entry in entries {
+        let updated = fetch_time(entry).unwrap_or(updated);
+        s.push_str("  <entry>\n");
+        writeln!(s, "    <id>{}</id>", entry_id(entry)).unwrap();
+        writeln!(s, "    <title>{}</title>", escape(&describe_entry(entry))).unwrap();
+        writeln!(s, "    <updated>{}</updated>", updated.to_rfc3339()).unwrap();
+        writeln!(s, "    <link href=\"{}\"/>", escape(&entry_link(config, entry))).unwrap();
+        writeln!(s, "    <content type=\"html\">{}</content>", escape(&entry_html(entry))).unwrap();
+        s.push_str("  </entry>\n");
+    }
+    s.push_str("</feed>\n");
+
+    s
+}
+
+fn render_rss(config: &FeedConfig, entries: &[&ChangelistEntry]) -> String {
+    let mut s = String::new();
+
+    s.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    s.push_str("<rss version=\"2.0\">\n");
+    s.push_str("  <channel>\n");
+    writeln!(s, "    <title>{}</title>", escape(&config.title)).unwrap();
+    writeln!(s, "    <link>{}</link>", escape(&config.site_url)).unwrap();
+    writeln!(s, "    <description>{}</description>", escape(&config.title)).unwrap();
+    for entry in entries {
+        s.push_str("    <item>\n");
+        writeln!(s, "      <guid isPermaLink=\"false\">{}</guid>", entry_id(entry)).unwrap();
+        writeln!(s, "      <title>{}</title>", escape(&describe_entry(entry))).unwrap();
+        writeln!(s, "      <link>{}</link>", escape(&entry_link(config, entry))).unwrap();
+        // The changelist already stores fetch times in RFC 2822, as RSS wants
+        writeln!(s, "      <pubDate>{}</pubDate>", escape(&entry.fetch_time)).unwrap();
+        writeln!(s, "      <description>{}</description>", escape(&entry_html(entry))).unwrap();
+        s.push_str("    </item>\n");
+    }
+    s.push_str("  </channel>\n");
+    s.push_str("</rss>\n");
+
+    s
+}
+
+/// Stays the same every time the feed is regenerated.
+fn entry_id(entry: &ChangelistEntry) -> String {
+    format!("urn:sha256:{}", entry.id())
+}
+
+fn entry_link(config: &FeedConfig, entry: &ChangelistEntry) -> String {
+    entry.workshop_item_url().unwrap_or_else(|| config.site_url.clone())
+}
+
+/// Describes the entry in HTML, with links to the level and the players, followed by the level's
+/// preview image.
+pub fn entry_html(entry: &ChangelistEntry) -> String {
+    let mut s = entry_summary_html(entry);
+    if let Some(preview) = &entry.map_preview {
+        write!(s, "<img src=\"{}\" alt=\"{}\"/>", escape(preview), escape(&entry.map_name))
+            .unwrap();
+    }
+
+    s
+}
+
+/// Like `entry_html`, without the image.
+pub fn entry_summary_html(entry: &ChangelistEntry) -> String {
+    let mut s = String::new();
+
+    let map = match entry.workshop_item_url() {
+        Some(url) => format!("<a href=\"{}\">{}</a>", escape(&url), escape(&entry.map_name)),
+        None => escape(&entry.map_name),
+    };
+    write!(
+        s,
+        "<p>{} set a new {} record of {} on {}",
+        profile_link(&entry.steam_id_new_recordholder, &entry.new_recordholder),
+        escape(&entry.mode),
+        escape(&entry.record_new),
+        map
+    )
+    .unwrap();
+    if let (Some(author), Some(steam_id)) = (&entry.map_author, &entry.steam_id_author) {
+        write!(s, " by {}", profile_link(steam_id, author)).unwrap();
+    }
+    s.push('.');
+    if let (Some(record_old), Some(old_recordholder), Some(steam_id)) =
+        (&entry.record_old, &entry.old_recordholder, &entry.steam_id_old_recordholder)
+    {
+        write!(
+            s,
+            " The previous record was {} by {}.",
+            escape(record_old),
+            profile_link(steam_id, old_recordholder)
+        )
+        .unwrap();
+    }
+    s.push_str("</p>");
+
+    s
+}
+
+pub fn profile_link(steam_id: &str, name: &str) -> String {
+    format!("<a href=\"{}\">{}</a>", escape(&steam_profile_url(steam_id)), escape(name))
+}
+
+fn feed_url(config: &FeedConfig, path: &Path) -> String {
+    let file_name = path.file_name().map(|x| x.to_string_lossy()).unwrap_or_default();
+    format!("{}/{}", config.site_url.trim_end_matches('/'), file_name)
+}
+
+pub fn fetch_time(entry: &ChangelistEntry) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(&entry.fetch_time).ok().map(|x| x.with_timezone(&Utc))
+}
+
+/// Escapes text for use in XML and HTML, including attribute values.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[test]
+fn test_atom_feed() {
+    use crate::test_util::{changelist_of, day, level_info};
+
+    let changelist = changelist_of(&[
+        vec![level_info("Broken <Symmetry>", "a", Some(("Seeker & Co", 20000)), day(1))],
+        vec![level_info("Lost Society", "b", Some(("Runner", 30000)), day(2))],
+    ]);
+    let config = FeedConfig { max_entries: 1, ..FeedConfig::default() };
+
+    let entries = latest_entries(&changelist, config.max_entries);
+    let atom = render_atom(&config, &entries);
+    let document = roxmltree::Document::parse(&atom).unwrap();
+    let titles: Vec<_> = document
+        .descendants()
+        .filter(|x| x.has_tag_name("entry"))
+        .filter_map(|x| x.children().find(|x| x.has_tag_name("title")))
+        .filter_map(|x| x.text())
+        .collect();
+    assert_eq!(titles.len(), 1);
+    assert!(titles[0].contains("Lost Society"));
+
+    // IDs don't change when the feed is regenerated, but differ between entries
+    assert_eq!(entry_id(&changelist[0]), entry_id(&changelist[0].clone()));
+    assert_ne!(entry_id(&changelist[0]), entry_id(&changelist[1]));
+
+    let all = latest_entries(&changelist, 10);
+    roxmltree::Document::parse(&render_atom(&config, &all)).unwrap();
+    roxmltree::Document::parse(&render_rss(&config, &all)).unwrap();
+}
@@ -0,0 +1,156 @@
+use crate::{
+    archive::{self, ContentStore},
+    backend::Backend,
+    config::GhostArchiveConfig,
+    domain::LevelInfo,
+    persistence::impls::file_json::save_file,
+};
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use distance_util::LeaderboardGameMode;
+use futures::prelude::*;
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+};
+
+const INDEX_FILENAME: &str = "index.json";
+const OBJECTS_DIRECTORY: &str = "objects";
+
+/// An archived ghost. The ghost itself is stored in the archive's content store under `sha256`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GhostRecord {
+    pub leaderboard_name: String,
+    pub level_name: String,
+    pub mode: LeaderboardGameMode,
+    pub steam_id: u64,
+    pub score: i32,
+    pub ugc_id: u64,
+    pub sha256: String,
+    pub archived_at: DateTime<Utc>,
+}
+
+/// Downloads and archives the ghost of every world record in `level_infos` that isn't archived
+/// yet.
+pub async fn archive_world_record_ghosts(
+    backend: &dyn Backend,
+    config: &GhostArchiveConfig,
+    level_infos: &[LevelInfo],
+) -> Result<(), Error> {
+    const MAX_BUFFER: usize = 16;
+
+    fs::create_dir_all(&config.directory)?;
+    let store = ContentStore::new(config.directory.join(OBJECTS_DIRECTORY));
+    let index_path = config.directory.join(INDEX_FILENAME);
+    let mut index: Vec<GhostRecord> = archive::load_index(&index_path)?;
+
+    let archived: HashSet<_> = index
+        .iter()
+        .map(|record| (record.leaderboard_name.clone(), record.steam_id, record.score))
+        .collect();
+    // Some backends don't report which ghost is attached to an entry
+    let mut without_ghosts = BTreeMap::<_, usize>::new();
+    let missing: Vec<_> = level_infos
+        .iter()
+        .filter_map(|level_info| {
+            let entry = level_info.leaderboard_response.entries.first()?;
+            let ugc_id = match entry.ugc_id {
+                Some(x) => x,
+                None => {
+                    let source = level_info.leaderboard_response.source.as_deref();
+                    *without_ghosts.entry(source.unwrap_or("unknown")).or_default() += 1;
+                    return None;
+                }
+            };
+            let key = (level_info.leaderboard_name.clone(), entry.steam_id, entry.score);
+            if archived.contains(&key) {
+                None
+            } else {
+                Some((level_info, entry, ugc_id))
+            }
+        })
+        .collect();
+    for (backend, count) in without_ghosts {
+        warn!("{} world records from {} have no ghost to archive", count, backend);
+    }
+
+    let downloads: Vec<_> = stream::iter(missing)
+        .map(|(level_info, entry, ugc_id)| async move {
+            (level_info, entry, ugc_id, backend.download_ugc(ugc_id).await)
+        })
+        .buffer_unordered(MAX_BUFFER)
+        .collect()
+        .await;
+
+    let mut archived_count = 0;
+    for (level_info, entry, ugc_id, download) in downloads {
+        let data = match download {
+            Ok(x) => x,
+            Err(e) => {
+                warn!(
+                    "Couldn't download the world record ghost for {} ({}): {}",
+                    level_info.name, level_info.mode, e
+                );
+                continue;
+            }
+        };
+
+        index.push(GhostRecord {
+            leaderboard_name: level_info.leaderboard_name.clone(),
+            level_name: level_info.name.clone(),
+            mode: level_info.mode,
+            steam_id: entry.steam_id,
+            score: entry.score,
+            ugc_id,
+            sha256: store.insert(&data)?,
+            archived_at: Utc::now(),
+        });
+        archived_count += 1;
+    }
+
+    info!("Archived {} new world record ghosts", archived_count);
+    save_file(&index, &index_path)
+}
+
+#[test]
+fn test_archive_world_record_ghosts() {
+    use crate::test_util::{day, level_info, MockBackend};
+    use async_std::task;
+
+    // UGC 0 fails to download
+    let new_backend =
+        || MockBackend::new("mock").with_ugc(10, b"ghost 10").with_ugc(20, b"ghost 20");
+    let record = |leaderboard_name: &str, score, ugc_id| {
+        let mut level_info = level_info("Level", leaderboard_name, Some(("Runner", score)), day(1));
+        let mut entries = level_info.leaderboard_response.entries.into_vec();
+        entries[0].ugc_id = ugc_id;
+        level_info.leaderboard_response.entries = entries.into_boxed_slice();
+        level_info
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = GhostArchiveConfig { directory: dir.path().to_owned() };
+    let index =
+        || -> Vec<GhostRecord> { archive::load_index(&dir.path().join(INDEX_FILENAME)).unwrap() };
+
+    // Records without a ghost and failed downloads aren't archived
+    let backend = new_backend();
+    let level_infos =
+        [record("a", 1000, Some(10)), record("b", 1000, None), record("c", 1000, Some(0))];
+    task::block_on(archive_world_record_ghosts(&backend, &config, &level_infos)).unwrap();
+    assert_eq!(backend.downloads(), [10, 0]);
+    let archived = index();
+    assert_eq!(archived.len(), 1);
+    assert_eq!((archived[0].leaderboard_name.as_str(), archived[0].ugc_id), ("a", 10));
+    let object_path = dir.path().join(OBJECTS_DIRECTORY).join(&archived[0].sha256[..2]);
+    assert_eq!(fs::read(object_path.join(&archived[0].sha256)).unwrap(), b"ghost 10");
+
+    // Archived records are skipped; a new record on the same leaderboard is archived too
+    let backend = new_backend();
+    let level_infos = [record("a", 1000, Some(10)), record("a", 900, Some(20))];
+    task::block_on(archive_world_record_ghosts(&backend, &config, &level_infos)).unwrap();
+    assert_eq!(backend.downloads(), [20]);
+    assert_eq!(index().len(), 2);
+}
@@ -0,0 +1,99 @@
+use anyhow::Error;
+use async_std::task;
+use std::{collections::HashMap, io::Read, time::Duration};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(TIMEOUT)
+        .user_agent(concat!("distance-log/", env!("CARGO_PKG_VERSION")))
+        .build()
+}
+
+/// Performs a GET request on a blocking thread and returns the response body.
+pub async fn get_string(agent: &ureq::Agent, url: String) -> Result<String, Error> {
+    get_string_with_query(agent, url, Vec::new()).await
+}
+
+/// Like `get_string`, but with the given query parameters appended to the URL.
+pub async fn get_string_with_query(
+    agent: &ureq::Agent,
+    url: String,
+    query: Vec<(&'static str, String)>,
+) -> Result<String, Error> {
+    let agent = agent.clone();
+    task::spawn_blocking(move || {
+        let request = query
+            .iter()
+            .fold(agent.get(&url), |request, (param, value)| request.query(param, value));
+
+        Ok(request.call()?.into_string()?)
+    })
+    .await
+}
+
+pub async fn get_bytes(agent: &ureq::Agent, url: String) -> Result<Vec<u8>, Error> {
+    let agent = agent.clone();
+    task::spawn_blocking(move || {
+        let mut data = Vec::new();
+        agent.get(&url).call()?.into_reader().read_to_end(&mut data)?;
+
+        Ok(data)
+    })
+    .await
+}
+
+/// The media type of an image, going by its URL. Steam serves workshop previews as JPEG or PNG.
+pub fn image_media_type(url: &str) -> &'static str {
+    if url.to_lowercase().ends_with(".png") {
+        "image/png"
+    } else {
+        "image/jpeg"
+    }
+}
+
+/// A response to a request sent with `send`, whatever its status.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+
+    /// Header names are lowercase.
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+/// Performs a request on a blocking thread. Only failing to get a response at all is an error;
+/// error statuses are left to the caller.
+pub async fn send(
+    agent: &ureq::Agent,
+    method: &'static str,
+    url: String,
+    headers: Vec<(String, String)>,
+    content_type: String,
+    body: Vec<u8>,
+) -> Result<Response, Error> {
+    let agent = agent.clone();
+    task::spawn_blocking(move || {
+        let request = headers.iter().fold(
+            agent.request(method, &url).set("Content-Type", &content_type),
+            |request, (name, value)| request.set(name, value),
+        );
+        let response = match request.send_bytes(&body) {
+            Ok(x) | Err(ureq::Error::Status(_, x)) => x,
+            Err(e) => return Err(e.into()),
+        };
+        let status = response.status();
+        let headers = response
+            .headers_names()
+            .into_iter()
+            .filter_map(|name| {
+                let value = response.header(&name)?.to_owned();
+                Some((name.to_lowercase(), value))
+            })
+            .collect();
+
+        Ok(Response { status, headers, body: response.into_string()? })
+    })
+    .await
+}
@@ -7,51 +7,375 @@
     unused_qualifications
 )]
 
+mod activitypub;
+mod archive;
+mod backend;
+mod changelist;
+mod checkpoint;
+mod config;
+mod diff;
+mod digest;
 mod domain;
+mod dry_run;
+mod export;
+mod feed;
+mod ghosts;
+mod http;
+mod notifier;
 mod official_levels;
+mod output;
 mod persistence;
-mod steamworks;
+mod polling;
+mod popularity;
+mod rebuild;
+mod schedule;
+mod server;
+mod shards;
+mod shutdown;
+mod site;
+#[cfg(test)]
+mod test_util;
+mod workshop_archive;
+mod workshop_index;
 
 use crate::{
+    backend::{
+        impls::cassette::{Cassette, WorkshopEnumeration},
+        Backends, Unsupported, WorkshopResponse,
+    },
+    changelist::{add_missing_entries_from, update_changelist},
+    checkpoint::Checkpoint,
+    config::{Config, WorkshopConfig},
     domain::{ChangelistEntry, LevelInfo},
-    persistence::{impls::file_json::FileJson, LoadError, Persistence},
-    steamworks::Steamworks,
+    dry_run::DryRunReport,
+    export::{ExportFormat, TimeRange},
+    notifier::{DeliveryState, Notifiers},
+    output::OutputFormat,
+    persistence::{
+        impls::file_json::{load_file, save_file, FileJson},
+        LoadError, Persistence,
+    },
+    polling::PollingPolicy,
+    schedule::LevelTarget,
+    server::query::parse_time,
+    shutdown::Shutdown,
+    workshop_index::WorkshopIndex,
 };
-use anyhow::{Context, Error};
+use anyhow::{bail, Context, Error};
 use async_std::task;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use distance_util::LeaderboardGameMode;
-use futures::prelude::*;
-use if_chain::if_chain;
+use futures::{future::LocalBoxFuture, prelude::*, stream::LocalBoxStream};
 use indicatif::ProgressBar;
-use itertools::{EitherOrBoth, Itertools};
 use log::{info, warn};
-use std::{collections::BTreeMap, process, time::Duration};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    process,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
 
+const CONFIG_FILENAME: &str = "distance-log.toml";
 const QUERY_RESULTS_FILENAME: &str = "query_results.json";
 const CHANGELIST_FILENAME: &str = "changelist.json";
+const POPULARITY_FILENAME: &str = "popularity.json";
+const BACKEND_HEALTH_FILENAME: &str = "backend_health.json";
+const WORKSHOP_INDEX_FILENAME: &str = "workshop_index.json";
+const CHECKPOINT_FILENAME: &str = "checkpoint.json";
+const NOTIFICATION_STATE_FILENAME: &str = "notification_state.json";
+const DIGEST_STATE_FILENAME: &str = "digest_state.json";
+
+/// How long requests already in flight get to finish after a shutdown is requested.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// Record every leaderboard and workshop response from this run into a cassette file
+    #[structopt(long, parse(from_os_str), conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Serve leaderboard and workshop responses from a cassette file instead of Steam, as of when
+    /// it was recorded. Implies --dry-run
+    #[structopt(long, parse(from_os_str))]
+    replay: Option<PathBuf>,
+
+    /// Fetch and compute everything as usual, but only print what would change instead of saving
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// How the dry run report is printed: "text" or "json"
+    // Not `requires = "dry-run"`: the default value would make every other invocation fail
+    #[structopt(long, default_value = "text")]
+    format: OutputFormat,
+
+    /// When interrupted, update the changelist from the levels fetched so far instead of only
+    /// saving them to a checkpoint for the next run
+    #[structopt(long)]
+    allow_partial: bool,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+/// Without a subcommand, the leaderboards are fetched and the changelist is updated.
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Compare two query results files, without connecting to Steam
+    Diff {
+        /// The older query results
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+
+        /// The newer query results
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+
+        /// How the differences are printed: "text" or "json"
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+    },
+
+    /// Regenerate the changelist from archived query results snapshots, and report how it differs
+    /// from the existing one
+    Rebuild {
+        /// Query results snapshots, or directories of them, in any order
+        #[structopt(parse(from_os_str), required = true)]
+        snapshots: Vec<PathBuf>,
+
+        /// Where the regenerated changelist is written
+        #[structopt(long, parse(from_os_str), default_value = "changelist.rebuilt.json")]
+        output: PathBuf,
+
+        /// The existing changelist to reconcile the regenerated one with
+        #[structopt(long, parse(from_os_str), default_value = CHANGELIST_FILENAME)]
+        changelist: PathBuf,
+
+        /// How the reconciliation report is printed: "text" or "json"
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+    },
+
+    /// Serve a JSON API for querying the changelist and current world records, without
+    /// connecting to Steam
+    Serve {
+        /// The address to listen on
+        #[structopt(long, default_value = "127.0.0.1:8080")]
+        address: String,
+    },
+
+    /// Export the changelist and the current leaderboards as flat CSV and Parquet tables, without
+    /// connecting to Steam
+    Export {
+        /// The directory the tables are written to
+        #[structopt(long, parse(from_os_str), default_value = "export")]
+        output: PathBuf,
+
+        /// Which formats to write: "csv" or "parquet". May be repeated; both by default
+        #[structopt(long = "format")]
+        formats: Vec<ExportFormat>,
+
+        /// Only export changelist entries fetched, and leaderboards last refreshed, at or after
+        /// this time (RFC 3339 or YYYY-MM-DD)
+        #[structopt(long, parse(try_from_str = parse_time))]
+        since: Option<DateTime<Utc>>,
+
+        /// Only export changelist entries fetched, and leaderboards last refreshed, before this
+        /// time (RFC 3339 or YYYY-MM-DD)
+        #[structopt(long, parse(try_from_str = parse_time))]
+        until: Option<DateTime<Utc>>,
+    },
+}
 
 fn main() {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    if let Err(e) = task::block_on(run()) {
+    let opt = Opt::from_args();
+    let result = match opt.command {
+        Some(Command::Diff { ref old, ref new, format }) => run_diff(old, new, format),
+        Some(Command::Rebuild { ref snapshots, ref output, ref changelist, format }) => {
+            run_rebuild(snapshots, output, changelist, format)
+        }
+        Some(Command::Serve { ref address }) => run_serve(address),
+        Some(Command::Export { ref output, ref formats, since, until }) => {
+            run_export(output, formats, TimeRange { since, until })
+        }
+        None => task::block_on(run(opt)),
+    };
+    if let Err(e) = result {
         println!("{}", e);
         process::exit(-1);
     }
 }
 
-async fn run() -> Result<(), Error> {
-    let steamworks = Steamworks::new()?;
-    let persistence = FileJson::new(QUERY_RESULTS_FILENAME, CHANGELIST_FILENAME);
+async fn run(opt: Opt) -> Result<(), Error> {
+    let config = Config::load(Path::new(CONFIG_FILENAME))?;
+    let shutdown = Shutdown::install()?;
+    let mut backends = match &opt.replay {
+        Some(path) => {
+            info!("Replaying responses from cassette '{}'", path.display());
+            Backends::replay(Cassette::load(path)?)
+        }
+        None => Backends::from_config(&config)?,
+    };
+    let cassette = opt.record.as_ref().map(|_| Rc::new(RefCell::new(Cassette::new(Utc::now()))));
+    if let Some(cassette) = &cassette {
+        backends = backends.record(cassette);
+    }
+    let persistence =
+        FileJson::new(QUERY_RESULTS_FILENAME, CHANGELIST_FILENAME, POPULARITY_FILENAME);
 
     info!("Starting update procedure");
-    update(&steamworks, &persistence).await?;
+    // A replay only reproduces a past run, so it must not change anything or notify anyone
+    let dry_run = if opt.dry_run || opt.replay.is_some() { Some(opt.format) } else { None };
+    let result =
+        update(&backends, &persistence, &config, dry_run, &shutdown, opt.allow_partial).await;
+    if opt.replay.is_none() && !opt.dry_run {
+        record_backend_health(&backends);
+    }
+    if let (Some(path), Some(cassette)) = (&opt.record, &cassette) {
+        info!("Saving cassette '{}'", path.display());
+        cassette.borrow().save(path)?;
+    }
+    result?;
     info!("Finished update procedure");
 
     Ok(())
 }
 
-async fn update(steamworks: &Steamworks, persistence: impl Persistence) -> Result<(), Error> {
+fn run_diff(old: &Path, new: &Path, format: OutputFormat) -> Result<(), Error> {
+    let load = |path: &Path| -> Result<Vec<LevelInfo>, Error> {
+        load_file(path).with_context(|| format!("Error loading '{}'", path.display()))
+    };
+    let diff = diff::diff(&load(old)?, &load(new)?);
+    println!("{}", diff.render(format)?);
+
+    Ok(())
+}
+
+fn run_rebuild(
+    snapshot_paths: &[PathBuf],
+    output: &Path,
+    changelist: &Path,
+    format: OutputFormat,
+) -> Result<(), Error> {
+    let mut paths = Vec::new();
+    for path in snapshot_paths {
+        if path.is_dir() {
+            let mut entries = fs::read_dir(path)?
+                .map(|entry| entry.map(|x| x.path()))
+                .filter(|path| {
+                    path.as_ref().map(|x| x.extension() == Some("json".as_ref())).unwrap_or(true)
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("Error reading directory '{}'", path.display()))?;
+            entries.sort();
+            paths.extend(entries);
+        } else {
+            paths.push(path.clone());
+        }
+    }
+
+    info!("Loading {} snapshots", paths.len());
+    let snapshots = paths
+        .iter()
+        .map(|path| {
+            load_file::<Vec<LevelInfo>>(path)
+                .with_context(|| format!("Error loading snapshot '{}'", path.display()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let snapshot_count = snapshots.len();
+    let rebuilt = rebuild::rebuild(snapshots);
+
+    let existing = match load_file(changelist) {
+        Ok(x) => x,
+        Err(LoadError::DoesNotExist) => {
+            warn!("No existing changelist found at '{}'", changelist.display());
+            Vec::new()
+        }
+        Err(e) => return Err(e).context("Error loading changelist"),
+    };
+    let report = rebuild::reconcile(snapshot_count, &existing, &rebuilt);
+
+    info!("Saving the rebuilt changelist to '{}'", output.display());
+    save_file(&rebuilt, output)?;
+    println!("{}", report.render(format)?);
+
+    Ok(())
+}
+
+fn run_serve(address: &str) -> Result<(), Error> {
+    let persistence =
+        FileJson::new(QUERY_RESULTS_FILENAME, CHANGELIST_FILENAME, POPULARITY_FILENAME);
+
+    server::serve(&persistence, address)
+}
+
+fn run_export(output: &Path, formats: &[ExportFormat], range: TimeRange) -> Result<(), Error> {
+    let formats =
+        if formats.is_empty() { &[ExportFormat::Csv, ExportFormat::Parquet][..] } else { formats };
+    let load_changelist = || -> Result<Vec<ChangelistEntry>, Error> {
+        load_file(Path::new(CHANGELIST_FILENAME))
+            .with_context(|| format!("Error loading '{}'", CHANGELIST_FILENAME))
+    };
+    let load_query_results = || -> Result<Vec<LevelInfo>, Error> {
+        load_file(Path::new(QUERY_RESULTS_FILENAME))
+            .with_context(|| format!("Error loading '{}'", QUERY_RESULTS_FILENAME))
+    };
+
+    export::write_tables(output, formats, &load_changelist()?, &load_query_results()?, range)
+}
+
+/// Adds this run's request and error counts to the backend health history, logging how reliable
+/// each backend has been recently. Failing to do so shouldn't fail the run.
+fn record_backend_health(backends: &Backends) {
+    let throttle = backends.throttle_stats();
+    if let Some(x) = &throttle {
+        info!(
+            "Sent {} requests at up to {} per second; concurrency limit ranged from {} to {}, \
+             ending at {}; mean latency {} ms; {} slow or failed",
+            x.requests,
+            x.requests_per_second,
+            x.min_concurrency_limit,
+            x.max_concurrency_limit,
+            x.final_concurrency_limit,
+            x.mean_latency_ms,
+            x.slow_or_failed_requests
+        );
+    }
+
+    let path = Path::new(BACKEND_HEALTH_FILENAME);
+    match backend::health::record_run(path, backends.stats(), throttle) {
+        Ok(error_rates) => {
+            for (backend, error_rate) in error_rates {
+                info!(
+                    "Backend '{}' error rate over recent runs: {:.1}%",
+                    backend,
+                    error_rate * 100.
+                );
+            }
+        }
+        Err(e) => warn!("Error recording backend health: {:#}", e),
+    }
+}
+
+/// Fetches the current level information and updates the changelist and everything else derived
+/// from it. With `dry_run` set, nothing is saved; a report of what would have changed is printed in
+/// the given format instead.
+///
+/// If a shutdown is requested partway, the levels fetched so far are saved to a checkpoint that the
+/// next run resumes from, and nothing else is updated unless `allow_partial` is set.
+async fn update(
+    backends: &Backends,
+    persistence: impl Persistence,
+    config: &Config,
+    dry_run: Option<OutputFormat>,
+    shutdown: &Shutdown,
+    allow_partial: bool,
+) -> Result<(), Error> {
     let old_level_infos = match persistence.load_query_results() {
         Ok(x) => {
             info!("Loaded previous query results");
@@ -82,91 +406,329 @@ async fn update(steamworks: &Steamworks, persistence: impl Persistence) -> Resul
         }
     };
 
-    let spinner = ProgressBar::new_spinner();
-    let mut new_level_infos = get_level_infos(&steamworks)
-        .inspect(|res| {
-            if let Ok(level_info) = res {
-                spinner.set_message(&format!("Fetched level {}", &level_info.name));
+    let mut popularity = match persistence.load_popularity() {
+        Ok(x) => x,
+        Err(e) => {
+            if let LoadError::DoesNotExist = e {
+                warn!("No existing popularity history found");
+                Vec::new()
+            } else {
+                return Err(e).context("Error loading popularity history");
             }
-        })
-        .try_collect::<Vec<_>>()
+        }
+    };
+
+    let notifiers = Notifiers::from_config(&config.notifier)?;
+    let mut workshop_index = WorkshopIndex::load(Path::new(WORKSHOP_INDEX_FILENAME))?;
+    let checkpoint = match Checkpoint::load(Path::new(CHECKPOINT_FILENAME))? {
+        Some(x)
+            if x.is_older_than(
+                chrono::Duration::hours(config.schedule.checkpoint_max_age_hours),
+                backends.now(),
+            ) =>
+        {
+            info!("Discarding a checkpoint from {}", x.created);
+            None
+        }
+        x => x,
+    };
+
+    let started = Instant::now();
+    let spinner = ProgressBar::new_spinner();
+    refresh_workshop_index(backends, &mut workshop_index, &config.workshop, shutdown, &spinner)
         .await?;
+    if dry_run.is_none() {
+        workshop_index.save(Path::new(WORKSHOP_INDEX_FILENAME))?;
+    }
+
+    let mut targets = get_level_targets(&workshop_index);
+    let target_names: HashSet<_> = targets.iter().map(|x| x.leaderboard_name.clone()).collect();
+    if backends.is_replay() {
+        // The recorded run already decided which levels to fetch
+        targets.retain(|target| backends.has_leaderboard(&target.leaderboard_name));
+    }
+    if let Some(ref old) = old_level_infos {
+        if let Some(polling_config) = config.polling.as_ref().filter(|_| !backends.is_replay()) {
+            let policy = PollingPolicy::new(polling_config, old, &changelist, &popularity);
+            let previous: HashMap<_, _> =
+                old.iter().map(|x| (x.leaderboard_name.as_str(), x)).collect();
+            let now = backends.now();
+            let total = targets.len();
+            targets.retain(|target| match previous.get(target.leaderboard_name.as_str()) {
+                Some(previous) => policy.is_due(previous, now),
+                None => true,
+            });
+            info!("Skipping {} of {} levels that aren't due yet", total - targets.len(), total);
+        }
+
+        schedule::order_by_staleness(&mut targets, old);
+    }
+
+    // Levels fetched by an interrupted run don't need fetching again
+    let mut checkpointed = checkpoint.map(|x| x.level_infos).unwrap_or_default();
+    if !checkpointed.is_empty() {
+        checkpointed.retain(|x| target_names.contains(&x.leaderboard_name));
+        let fetched: HashSet<_> = checkpointed.iter().map(|x| x.leaderboard_name.clone()).collect();
+        targets.retain(|target| !fetched.contains(&target.leaderboard_name));
+        info!("Resuming from a checkpoint of {} levels", checkpointed.len());
+    }
+    let target_count = targets.len();
+
+    let level_infos = get_level_infos(backends, targets, shutdown).inspect(|res| {
+        if let Ok(level_info) = res {
+            spinner.set_message(&format!("Fetched level {}", &level_info.name));
+        }
+    });
+    let grace_period_over = async {
+        shutdown.requested().await;
+        task::sleep(SHUTDOWN_GRACE_PERIOD).await;
+    };
+    let level_infos = level_infos.take_until(grace_period_over);
+    // A replay has every response the recorded run got within its budget
+    let budget_secs = config.schedule.budget_secs.filter(|_| !backends.is_replay());
+    let mut new_level_infos = match budget_secs {
+        Some(budget_secs) => {
+            let remaining = Duration::from_secs(budget_secs).checked_sub(started.elapsed());
+            let budget = task::sleep(remaining.unwrap_or_default());
+            level_infos.take_until(budget).try_collect::<Vec<_>>().await?
+        }
+        None => level_infos.try_collect::<Vec<_>>().await?,
+    };
     spinner.finish_with_message("Finished fetching level information.");
+    if new_level_infos.len() < target_count {
+        info!(
+            "Fetched {} of {} levels; the rest keep their previous results",
+            new_level_infos.len(),
+            target_count
+        );
+    }
+    new_level_infos.extend(checkpointed);
+
+    if shutdown.is_requested() && !allow_partial {
+        if dry_run.is_none() {
+            let checkpoint = Checkpoint { created: backends.now(), level_infos: new_level_infos };
+            checkpoint.save(Path::new(CHECKPOINT_FILENAME))?;
+        }
+        bail!(
+            "Interrupted before every level was fetched; the changelist wasn't updated, and the \
+             next run resumes from the checkpoint"
+        );
+    }
+
+    let mut report = DryRunReport::default();
     if let Some(ref old) = old_level_infos {
-        new_level_infos = add_missing_entries_from(new_level_infos, old.clone());
+        let (merged, missing) = add_missing_entries_from(new_level_infos, old.clone());
+        new_level_infos = merged;
+        report.backfilled_levels = missing.backfilled;
+        report.removed_levels =
+            missing.carried_over.into_iter().filter(|x| !target_names.contains(x)).collect();
     }
 
     if let Some(old_level_infos) = old_level_infos {
         info!("Computing changelist");
+        let old_len = changelist.len();
         update_changelist(&mut changelist, &mut new_level_infos, old_level_infos);
 
-        info!("Saving changelist");
-        persistence.save_changelist(&changelist)?;
+        if dry_run.is_some() {
+            report.new_entries = changelist.split_off(old_len);
+        } else {
+            // Queued before anything else is saved, so that a failure further on can't leave new
+            // entries saved but never sent
+            let state_path = Path::new(NOTIFICATION_STATE_FILENAME);
+            let mut notification_state = None;
+            if !notifiers.is_empty() {
+                info!("Queueing notifications");
+                let mut state = DeliveryState::load(state_path)?;
+                notifiers.enqueue(&mut state, &changelist[old_len..], &new_level_infos);
+                state.save(state_path)?;
+                notification_state = Some(state);
+            }
+
+            info!("Saving changelist");
+            persistence.save_changelist(&changelist)?;
+
+            if let Some(feed_config) = &config.feed {
+                info!("Writing feeds");
+                feed::write_feeds(feed_config, &changelist)?;
+            }
+
+            if let Some(activitypub_config) = &config.activitypub {
+                info!("Writing ActivityPub outbox");
+                activitypub::write_outbox(activitypub_config, &changelist)?;
+            }
+
+            if let Some(mut state) = notification_state {
+                info!("Sending notifications");
+                notifiers.deliver(&mut state, state_path).await?;
+            }
+
+            if let Some(digest_config) = &config.digest {
+                info!("Checking whether a digest is due");
+                let state_path = Path::new(DIGEST_STATE_FILENAME);
+                // The digest period isn't advanced, so the next run tries again
+                if let Err(e) = digest::send_if_due(digest_config, &changelist, state_path).await {
+                    warn!("{:#}", e);
+                }
+            }
+        }
+    }
+
+    if let Some(format) = dry_run {
+        println!("{}", report.render(format)?);
+        return Ok(());
     }
 
     info!("Saving level info");
     persistence.save_query_results(&new_level_infos)?;
-    Ok(())
-}
 
-fn get_level_infos(steamworks: &Steamworks) -> impl Stream<Item = Result<LevelInfo, Error>> + '_ {
-    const MAX_BUFFER: usize = 512;
-    const TIMEOUT_SECS: u64 = 60;
+    if let Some(site_config) = &config.site {
+        info!("Generating site");
+        site::write_site(site_config, &changelist, &new_level_infos)?;
+    }
 
-    let stream = stream::iter(get_official_levels(steamworks)).buffer_unordered(MAX_BUFFER).chain(
-        get_workshop_levels(steamworks)
-            .buffer_unordered(MAX_BUFFER)
-            .filter_map(|x| future::ready(x.transpose())),
-    );
+    if let Some(shards_config) = &config.shards {
+        info!("Writing shards");
+        shards::write_shards(shards_config, &changelist, &new_level_infos)?;
+    }
+    Checkpoint::remove(Path::new(CHECKPOINT_FILENAME))?;
 
-    async_std::stream::StreamExt::timeout_repeat(stream, Duration::from_secs(TIMEOUT_SECS))
-        .take_while(|timeout_result| {
-            let timed_out = timeout_result.is_err();
-            if timed_out {
-                warn!("Skipping some levels that took too long to fetch");
-            }
+    popularity::record_entry_counts(&mut popularity, &new_level_infos);
 
-            future::ready(!timed_out)
-        })
-        .map(|timeout_result| timeout_result.unwrap())
+    info!("Saving popularity history");
+    persistence.save_popularity(&popularity)?;
+
+    if let Some(ghost_archive_config) = &config.ghost_archive {
+        info!("Archiving world record ghosts");
+        ghosts::archive_world_record_ghosts(
+            &*backends.leaderboards,
+            ghost_archive_config,
+            &new_level_infos,
+        )
+        .await
+        .context("Error archiving world record ghosts")?;
+    }
+
+    if let Some(workshop_archive_config) = &config.workshop_archive {
+        info!("Archiving workshop level files");
+        workshop_archive::archive_workshop_levels(
+            &*backends.workshop,
+            workshop_archive_config,
+            &new_level_infos,
+        )
+        .await
+        .context("Error archiving workshop level files")?;
+    }
+
+    Ok(())
 }
 
-// Deal with Steam sometimes failing to return data by supplementing it with the previously stored
-// data.
-fn add_missing_entries_from(
-    mut level_infos: Vec<LevelInfo>,
-    mut other: Vec<LevelInfo>,
-) -> Vec<LevelInfo> {
-    let sort = |x: &mut [LevelInfo]| {
-        x.sort_unstable_by(|a, b| a.leaderboard_name.cmp(&b.leaderboard_name))
+/// Brings the workshop index up to date: only the levels created or updated since the previous
+/// enumeration are queried, except when a full enumeration is due or the backend can't do
+/// anything else.
+async fn refresh_workshop_index(
+    backends: &Backends,
+    index: &mut WorkshopIndex,
+    config: &WorkshopConfig,
+    shutdown: &Shutdown,
+    spinner: &ProgressBar,
+) -> Result<(), Error> {
+    // Steam's update times aren't exact, so look a little further back than the last enumeration
+    const OVERLAP_HOURS: i64 = 1;
+
+    let started = backends.now();
+    let since = match backends.replayed_workshop_enumeration() {
+        // The cassette only has responses for the requests the recorded run made
+        Some(WorkshopEnumeration::Incremental { since }) => Some(since),
+        Some(WorkshopEnumeration::Full) => None,
+        None => {
+            let full_enumeration_due = index.needs_full_enumeration(
+                started,
+                chrono::Duration::hours(config.full_enumeration_hours),
+            );
+            match index.last_enumeration {
+                Some(x) if !full_enumeration_due => {
+                    Some(x - chrono::Duration::hours(OVERLAP_HOURS))
+                }
+                _ => None,
+            }
+        }
     };
 
-    sort(&mut level_infos);
-    sort(&mut other);
-
-    level_infos
-        .into_iter()
-        .merge_join_by(other, |a, b| a.leaderboard_name.cmp(&b.leaderboard_name))
-        .map(|x| match x {
-            EitherOrBoth::Both(l, r) => {
-                if l.leaderboard_response.entries.len() == 0
-                    && r.leaderboard_response.entries.len() > 0
-                {
-                    r
-                } else {
-                    l
+    if let Some(since) = since {
+        let levels = backends.workshop.get_recently_updated_workshop_levels(since);
+        match enumerate_workshop_levels(levels, shutdown, spinner).await {
+            Ok((levels, complete)) => {
+                info!("Found {} new or updated workshop levels", levels.len());
+                index.merge(levels);
+                // Otherwise the levels that were missed are queried again next run
+                if complete {
+                    index.last_enumeration = Some(started);
                 }
+
+                return Ok(());
             }
-            EitherOrBoth::Left(x) => x,
-            EitherOrBoth::Right(x) => x,
-        })
-        .collect()
+            Err(e) if e.is::<Unsupported>() => info!(
+                "{}; enumerating every workshop level instead. Set `workshop_backend` to \
+                 \"web_api\" to only query new and updated levels",
+                e
+            ),
+            Err(e) => return Err(e),
+        }
+    }
+
+    let levels = backends.workshop.get_all_workshop_sprint_challenge_stunt_levels();
+    match enumerate_workshop_levels(levels, shutdown, spinner).await {
+        // Far more likely a failure than every level having been removed
+        Ok((levels, true)) if levels.is_empty() && !index.levels.is_empty() => {
+            warn!("Found no workshop levels; keeping the {} known ones", index.levels.len())
+        }
+        Ok((levels, true)) => {
+            index.replace(levels, started);
+            index.last_enumeration = Some(started);
+        }
+        // Levels missing from an incomplete enumeration may still exist, so keep them
+        Ok((levels, false)) => index.merge(levels),
+        Err(e) if e.is::<Unsupported>() => warn!("Skipping workshop levels: {}", e),
+        Err(e) => return Err(e),
+    }
+
+    Ok(())
 }
 
-fn get_official_levels(
-    steamworks: &Steamworks,
-) -> impl Iterator<Item = impl Future<Output = Result<LevelInfo, Error>> + '_> + '_ {
-    official_levels::iter().map(move |(level_name, mode)| {
+/// Collects workshop levels until the stream ends, stalls or a shutdown is requested, returning
+/// them along with whether the stream ended.
+async fn enumerate_workshop_levels(
+    levels: LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>>,
+    shutdown: &Shutdown,
+    spinner: &ProgressBar,
+) -> Result<(Vec<WorkshopResponse>, bool), Error> {
+    // The end of the stream is marked with `None`, so that a timeout can be told apart from it
+    let levels = levels
+        .then(|x| x)
+        .map(Some)
+        .chain(stream::once(future::ready(None)))
+        .take_until(shutdown.requested());
+    let mut levels = skip_after_timeout(levels).boxed_local();
+
+    let mut responses = Vec::new();
+    let mut complete = false;
+    while let Some(level) = levels.next().await {
+        match level {
+            Some(level) => {
+                let level = level?;
+                spinner.set_message(&format!("Found level {}", &level.title));
+                responses.push(level);
+            }
+            None => complete = true,
+        }
+    }
+
+    Ok((responses, complete))
+}
+
+/// The official levels, followed by every workshop level in the index.
+fn get_level_targets(workshop_index: &WorkshopIndex) -> Vec<LevelTarget> {
+    let official_levels = official_levels::iter().map(|(level_name, mode)| {
         let leaderboard_name = distance_util::create_leaderboard_name_string(
             level_name, mode, None,
         )
@@ -177,162 +739,79 @@ fn get_official_levels(
             )
         });
 
-        async move {
-            let leaderboard_response =
-                steamworks.get_leaderboard_range(leaderboard_name.clone(), 1, 2).await?;
-
-            Ok(LevelInfo {
-                name: level_name.to_owned(),
-                mode,
-                leaderboard_name,
-                workshop_response: None,
-                leaderboard_response,
-                timestamp: Utc::now(),
-            })
-        }
-    })
-}
+        LevelTarget { name: level_name.to_owned(), mode, leaderboard_name, workshop_response: None }
+    });
 
-fn get_workshop_levels(
-    steamworks: &Steamworks,
-) -> impl Stream<Item = impl Future<Output = Result<Option<LevelInfo>, Error>> + '_> + '_ {
-    let workshop_levels = steamworks.get_all_workshop_sprint_challenge_stunt_levels();
-    let level_infos = workshop_levels
-        .map(|fut| {
-            fut.map_ok(|workshop_response| {
-                let x = [
-                    LeaderboardGameMode::Sprint,
-                    LeaderboardGameMode::Challenge,
-                    LeaderboardGameMode::Stunt,
-                ]
-                .iter()
-                .filter_map(move |mode| {
-                    if workshop_response.tags.iter().any(|x| x == mode.name()) {
-                        let leaderboard_name = distance_util::create_leaderboard_name_string(
-                            remove_bytes_extension(&workshop_response.file_name),
-                            *mode,
-                            Some(workshop_response.steam_id_owner),
-                        );
-                        leaderboard_name.map(|leaderboard_name| {
-                            Ok((workshop_response.clone(), *mode, leaderboard_name))
-                        })
-                    } else {
-                        None
-                    }
-                });
-
-                stream::iter(x)
-            })
-            .try_flatten_stream()
-        })
-        .flatten();
-
-    level_infos.map(move |x| {
-        future::ready(x)
-            .and_then(move |(workshop_response, mode, leaderboard_name)| async move {
-                Ok(steamworks.get_leaderboard_range(leaderboard_name.clone(), 1, 2).await.ok().map(
-                    |leaderboard_response| {
-                        (workshop_response, mode, leaderboard_name, leaderboard_response)
-                    },
-                ))
-            })
-            .map_ok(|opt| {
-                opt.map(|(workshop_response, mode, leaderboard_name, leaderboard_response)| {
-                    LevelInfo {
+    let workshop_levels = workshop_index.levels.iter().flat_map(|workshop_response| {
+        [LeaderboardGameMode::Sprint, LeaderboardGameMode::Challenge, LeaderboardGameMode::Stunt]
+            .iter()
+            .filter_map(move |mode| {
+                if workshop_response.tags.iter().any(|x| x == mode.name()) {
+                    let leaderboard_name = distance_util::create_leaderboard_name_string(
+                        remove_bytes_extension(&workshop_response.file_name),
+                        *mode,
+                        Some(workshop_response.steam_id_owner),
+                    );
+                    leaderboard_name.map(|leaderboard_name| LevelTarget {
                         name: workshop_response.title.clone(),
-                        mode,
+                        mode: *mode,
                         leaderboard_name,
-                        workshop_response: Some(workshop_response),
-                        leaderboard_response,
-                        timestamp: Utc::now(),
-                    }
-                })
+                        workshop_response: Some(workshop_response.clone()),
+                    })
+                } else {
+                    None
+                }
             })
-    })
+    });
+
+    official_levels.chain(workshop_levels).collect()
 }
 
-fn update_changelist(
-    changelist: &mut Vec<ChangelistEntry>,
-    new: &mut [LevelInfo],
-    old: Vec<LevelInfo>,
-) {
-    new.sort_by_key(|level_info| {
-        level_info.workshop_response.as_ref().map(|x| x.published_file_id).unwrap_or(0)
-    });
-    let old: BTreeMap<_, _> = old
-        .into_iter()
-        .map(|level_info| (level_info.leaderboard_name.clone(), level_info))
-        .collect();
-
-    let entries = new.iter().filter_map(|level_info| {
-        let LevelInfo {
-            name,
-            mode,
-            leaderboard_name,
-            workshop_response,
-            leaderboard_response,
-            timestamp,
-        } = level_info;
-        let first_entry = if let Some(x) = leaderboard_response.entries.get(0) {
-            x.clone()
-        } else {
-            return None;
-        };
-
-        let (old_recordholder, record_old, steam_id_old_recordholder) = if_chain! {
-            if let Some(level_info_old) = old.get(leaderboard_name);
-            if let Some(previous_first_entry) = level_info_old.leaderboard_response.entries.get(0);
-            then {
-                if is_score_better(first_entry.score, previous_first_entry.score, *mode) {
-                    (Some(previous_first_entry.player_name.clone()),
-                        Some(distance_util::format_score(previous_first_entry.score, *mode).unwrap()),
-                        Some(format!("{}", previous_first_entry.steam_id)))
-                } else {
-                    return None;
+fn get_level_infos<'a>(
+    backends: &'a Backends,
+    targets: Vec<LevelTarget>,
+    shutdown: &'a Shutdown,
+) -> impl Stream<Item = Result<LevelInfo, Error>> + 'a {
+    const MAX_BUFFER: usize = 512;
+
+    // No new requests once a shutdown is requested, but the ones in flight may finish
+    let level_infos = stream::iter(targets)
+        .take_until(shutdown.requested())
+        .map(move |target| async move {
+            let result = backends
+                .leaderboards
+                .get_leaderboard_range(target.leaderboard_name.clone(), 1, 2)
+                .await;
+            match result {
+                Ok(leaderboard_response) => {
+                    let fetched_at = backends.fetch_time(&target.leaderboard_name);
+                    Ok(Some(target.into_level_info(leaderboard_response, fetched_at)))
                 }
-            } else {
-                (None, None, None)
+                // Not every workshop level has a leaderboard
+                Err(_) if target.workshop_response.is_some() => Ok(None),
+                Err(e) => Err(e),
             }
-        };
-
-        Some(ChangelistEntry {
-            map_name: name.clone(),
-            map_author: workshop_response.as_ref().map(|x| x.author_name.clone()),
-            map_preview: workshop_response.as_ref().map(|x| x.preview_url.clone()),
-            mode: format!("{}", mode),
-            new_recordholder: first_entry.player_name,
-            old_recordholder,
-            record_new: distance_util::format_score(first_entry.score, *mode).unwrap(),
-            record_old,
-            workshop_item_id: workshop_response
-                .as_ref()
-                .map(|x| format!("{}", x.published_file_id)),
-            steam_id_author: workshop_response
-                .as_ref()
-                .map(|x| format!("{}", x.steam_id_owner)),
-            steam_id_new_recordholder: format!("{}", first_entry.steam_id),
-            steam_id_old_recordholder,
-            fetch_time: timestamp.to_rfc2822(),
         })
-    });
+        .buffer_unordered(MAX_BUFFER)
+        .filter_map(|x| future::ready(x.transpose()));
 
-    let entries: Vec<_> = entries
-        .filter(|new_entry| {
-            changelist
-                .iter()
-                .all(|existing_entry| !new_entry.is_likely_a_duplicate_of(existing_entry))
-        })
-        .rev()
-        .collect();
-
-    changelist.extend(entries);
+    skip_after_timeout(level_infos)
 }
 
-fn is_score_better(score_1: i32, score_2: i32, game_mode: LeaderboardGameMode) -> bool {
-    match game_mode {
-        LeaderboardGameMode::Sprint | LeaderboardGameMode::Challenge => score_1 < score_2,
-        LeaderboardGameMode::Stunt => score_1 > score_2,
-    }
+/// Ends the stream early if an item takes too long to arrive.
+fn skip_after_timeout<T>(stream: impl Stream<Item = T>) -> impl Stream<Item = T> {
+    const TIMEOUT_SECS: u64 = 60;
+
+    async_std::stream::StreamExt::timeout_repeat(stream, Duration::from_secs(TIMEOUT_SECS))
+        .take_while(|timeout_result| {
+            let timed_out = timeout_result.is_err();
+            if timed_out {
+                warn!("Skipping some levels that took too long to fetch");
+            }
+
+            future::ready(!timed_out)
+        })
+        .map(|timeout_result| timeout_result.unwrap())
 }
 
 fn remove_bytes_extension(level: &str) -> &str {
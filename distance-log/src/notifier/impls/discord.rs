@@ -0,0 +1,154 @@
+use crate::{
+    config::DiscordConfig,
+    domain::{steam_profile_url, ChangelistEntry},
+    http,
+    notifier::{self, Notification, Notifier},
+};
+use anyhow::Error;
+use futures::{future::LocalBoxFuture, prelude::*};
+use serde_json::json;
+
+/// Discord accepts at most this many embeds in one message.
+const MAX_EMBEDS_PER_MESSAGE: usize = 10;
+
+/// Posts notifications to a Discord webhook as embeds, several to a message.
+#[derive(Debug)]
+pub struct Discord {
+    config: DiscordConfig,
+    agent: ureq::Agent,
+}
+
+impl Discord {
+    pub fn new(config: &DiscordConfig) -> Self {
+        Discord { config: config.clone(), agent: http::agent() }
+    }
+}
+
+impl Notifier for Discord {
+    fn max_batch_size(&self) -> usize {
+        MAX_EMBEDS_PER_MESSAGE
+    }
+
+    fn notify<'a>(
+        &'a self,
+        notifications: &'a [Notification],
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        async move {
+            let message = message(&self.config, notifications).to_string();
+            notifier::send_json(&self.agent, "POST", &self.config.webhook_url, &[], &message)
+                .await?;
+
+            Ok(())
+        }
+        .boxed_local()
+    }
+}
+
+fn message(config: &DiscordConfig, notifications: &[Notification]) -> serde_json::Value {
+    let embeds: Vec<_> = notifications.iter().map(|x| embed(&x.entry)).collect();
+    let mut message = json!({ "embeds": embeds });
+    if let Some(username) = &config.username {
+        message["username"] = json!(username);
+    }
+
+    message
+}
+
+fn embed(entry: &ChangelistEntry) -> serde_json::Value {
+    let mut fields = vec![json!({
+        "name": "New record",
+        "value": format!(
+            "{} by {}",
+            entry.record_new,
+            profile_link(&entry.steam_id_new_recordholder, &entry.new_recordholder)
+        ),
+        "inline": true,
+    })];
+    if let (Some(record_old), Some(old_recordholder), Some(steam_id)) =
+        (&entry.record_old, &entry.old_recordholder, &entry.steam_id_old_recordholder)
+    {
+        fields.push(json!({
+            "name": "Previous record",
+            "value": format!("{} by {}", record_old, profile_link(steam_id, old_recordholder)),
+            "inline": true,
+        }));
+    }
+    if let Some(improvement) = entry.improvement() {
+        fields.push(json!({ "name": "Improvement", "value": improvement, "inline": true }));
+    }
+
+    let mut embed = json!({
+        "title": format!("[{}] {}", entry.mode, entry.map_name),
+        "fields": fields,
+    });
+    if let Some(url) = entry.workshop_item_url() {
+        embed["url"] = json!(url);
+    }
+    if let (Some(author), Some(steam_id)) = (&entry.map_author, &entry.steam_id_author) {
+        embed["author"] = json!({ "name": author, "url": steam_profile_url(steam_id) });
+    }
+    if let Some(preview) = &entry.map_preview {
+        embed["thumbnail"] = json!({ "url": preview });
+    }
+    if let Ok(fetch_time) = chrono::DateTime::parse_from_rfc2822(&entry.fetch_time) {
+        embed["timestamp"] = json!(fetch_time.to_rfc3339());
+    }
+
+    embed
+}
+
+fn profile_link(steam_id: &str, name: &str) -> String {
+    format!("[{}]({})", escape_markdown(name), steam_profile_url(steam_id))
+}
+
+fn escape_markdown(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "\\*_~`|[]()<>".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+#[test]
+fn test_discord_rate_limits() {
+    use crate::test_util::{changelist_of, level_info, MockResponse, MockServer};
+    use async_std::task;
+    use chrono::Utc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Rate limited once, then accepted with the bucket used up
+    let requests = AtomicUsize::new(0);
+    let server = MockServer::start(move |_| {
+        if requests.fetch_add(1, Ordering::SeqCst) == 0 {
+            MockResponse::from((429, r#"{"retry_after": 0.01}"#.to_owned()))
+        } else {
+            MockResponse::from((204, String::new()))
+                .with_header("X-RateLimit-Remaining", "0")
+                .with_header("X-RateLimit-Reset-After", "0.01")
+        }
+    });
+
+    let level_infos: Vec<_> = (0..MAX_EMBEDS_PER_MESSAGE)
+        .map(|i| {
+            let name = format!("Level {}", i);
+            level_info(&name, &name, Some(("Seeker [1]", 20000)), Utc::now())
+        })
+        .collect();
+    let changelist = changelist_of(&[level_infos]);
+    let notifications: Vec<_> =
+        changelist.into_iter().map(|entry| Notification { id: entry.id(), entry }).collect();
+
+    let discord = Discord::new(&DiscordConfig { webhook_url: server.url(), username: None });
+    task::block_on(discord.notify(&notifications)).unwrap();
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests.iter().all(|x| x.method == "POST"));
+    let message: serde_json::Value = serde_json::from_str(&requests[1].body).unwrap();
+    assert_eq!(message["embeds"].as_array().unwrap().len(), MAX_EMBEDS_PER_MESSAGE);
+    assert!(message["embeds"][0]["fields"][0]["value"].as_str().unwrap().contains(r"\[1\]"));
+}
@@ -0,0 +1,184 @@
+use crate::{
+    config::MastodonConfig,
+    domain::ChangelistEntry,
+    dry_run::describe_entry,
+    http,
+    notifier::{self, Notification, Notifier},
+};
+use anyhow::{format_err, Error};
+use futures::{future::LocalBoxFuture, prelude::*};
+use log::warn;
+use serde_json::json;
+
+/// Posts each notification as a status, with the level's preview image attached.
+#[derive(Debug)]
+pub struct Mastodon {
+    config: MastodonConfig,
+    agent: ureq::Agent,
+}
+
+impl Mastodon {
+    pub fn new(config: &MastodonConfig) -> Self {
+        Mastodon { config: config.clone(), agent: http::agent() }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.config.instance_url.trim_end_matches('/'), path)
+    }
+
+    fn authorization(&self) -> (String, String) {
+        ("Authorization".to_owned(), format!("Bearer {}", self.config.access_token))
+    }
+
+    /// Downloads the preview image and uploads it as a media attachment, returning its ID.
+    async fn upload_preview(
+        &self,
+        notification: &Notification,
+        url: &str,
+    ) -> Result<String, Error> {
+        let image = http::get_bytes(&self.agent, url.to_owned()).await?;
+
+        let boundary = format!("distance-log-{}", notification.id);
+        let mut body = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"description\"\r\n\r\n{}\r\n",
+            boundary, notification.entry.map_name
+        )
+        .into_bytes();
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"preview\"\r\n\
+                 Content-Type: {}\r\n\r\n",
+                boundary,
+                http::image_media_type(url)
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&image);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+        let response = notifier::send(
+            &self.agent,
+            "POST",
+            &self.url("/api/v2/media"),
+            &[self.authorization()],
+            &format!("multipart/form-data; boundary={}", boundary),
+            &body,
+        )
+        .await?;
+        let media: serde_json::Value = serde_json::from_str(&response.body)?;
+
+        media["id"]
+            .as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| format_err!("Mastodon didn't return a media ID: {}", response.body))
+    }
+}
+
+impl Notifier for Mastodon {
+    fn max_batch_size(&self) -> usize {
+        1
+    }
+
+    fn notify<'a>(
+        &'a self,
+        notifications: &'a [Notification],
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        async move {
+            for notification in notifications {
+                let mut media_ids = Vec::new();
+                if let (true, Some(preview)) =
+                    (self.config.attach_preview, &notification.entry.map_preview)
+                {
+                    // A record is still worth posting without its picture
+                    match self.upload_preview(notification, preview).await {
+                        Ok(id) => media_ids.push(id),
+                        Err(e) => warn!("Error attaching the preview of '{}': {:#}", preview, e),
+                    }
+                }
+
+                let body = json!({
+                    "status": status_text(&notification.entry),
+                    "visibility": self.config.visibility,
+                    "media_ids": media_ids,
+                });
+                // Mastodon ignores a status posted again with the same key
+                let headers =
+                    [self.authorization(), ("Idempotency-Key".to_owned(), notification.id.clone())];
+                notifier::send_json(
+                    &self.agent,
+                    "POST",
+                    &self.url("/api/v1/statuses"),
+                    &headers,
+                    &body.to_string(),
+                )
+                .await?;
+            }
+
+            Ok(())
+        }
+        .boxed_local()
+    }
+}
+
+fn status_text(entry: &ChangelistEntry) -> String {
+    match entry.workshop_item_url() {
+        Some(url) => format!("{}\n\n{}", describe_entry(entry), url),
+        None => describe_entry(entry),
+    }
+}
+
+#[test]
+fn test_mastodon_status_with_media() {
+    use crate::{
+        changelist::update_changelist,
+        test_util::{level_info, MockResponse, MockServer},
+    };
+    use async_std::task;
+    use chrono::Utc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Posting is rate limited once, until a moment from now
+    let statuses = AtomicUsize::new(0);
+    let server = MockServer::start(move |request| match request.url.as_str() {
+        "/preview.jpg" => MockResponse::from((200, "JPEG".to_owned())),
+        "/api/v2/media" => MockResponse::from((200, r#"{"id": "42"}"#.to_owned())),
+        _ => {
+            if statuses.fetch_add(1, Ordering::SeqCst) == 0 {
+                let reset = Utc::now() + chrono::Duration::milliseconds(50);
+                MockResponse::from((429, r#"{"error": "Too many requests"}"#.to_owned()))
+                    .with_header("X-RateLimit-Remaining", "0")
+                    .with_header("X-RateLimit-Reset", &reset.to_rfc3339())
+            } else {
+                MockResponse::from((200, r#"{"id": "1"}"#.to_owned()))
+            }
+        }
+    });
+
+    let mut changelist = Vec::new();
+    update_changelist(
+        &mut changelist,
+        &mut [level_info("Broken Symmetry", "a", Some(("Seeker", 20000)), Utc::now())],
+        vec![],
+    );
+    let mut entry = changelist.remove(0);
+    entry.map_preview = Some(format!("{}/preview.jpg", server.url()));
+    let notification = Notification { id: entry.id(), entry };
+
+    let mastodon = Mastodon::new(&MastodonConfig {
+        instance_url: server.url(),
+        access_token: "token".to_owned(),
+        visibility: "unlisted".to_owned(),
+        attach_preview: true,
+    });
+    task::block_on(mastodon.notify(std::slice::from_ref(&notification))).unwrap();
+
+    let requests = server.requests();
+    let urls: Vec<_> = requests.iter().map(|x| x.url.as_str()).collect();
+    assert_eq!(urls, ["/preview.jpg", "/api/v2/media", "/api/v1/statuses", "/api/v1/statuses"]);
+    assert!(requests[1].body.contains("name=\"description\"\r\n\r\nBroken Symmetry\r\n"));
+    assert!(requests[1].body.contains("Content-Type: image/jpeg\r\n\r\nJPEG\r\n"));
+    let body: serde_json::Value = serde_json::from_str(&requests[3].body).unwrap();
+    assert_eq!(body["media_ids"], json!(["42"]));
+    assert_eq!(body["visibility"], "unlisted");
+    assert!(body["status"].as_str().unwrap().contains("Broken Symmetry"));
+}
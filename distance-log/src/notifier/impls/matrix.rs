@@ -0,0 +1,129 @@
+use crate::{
+    config::MatrixConfig,
+    dry_run::describe_entry,
+    feed::entry_html,
+    http,
+    notifier::{self, Notification, Notifier},
+};
+use anyhow::Error;
+use futures::{future::LocalBoxFuture, prelude::*};
+use serde_json::json;
+
+/// Sends each notification to a Matrix room as a notice.
+#[derive(Debug)]
+pub struct Matrix {
+    config: MatrixConfig,
+    agent: ureq::Agent,
+}
+
+impl Matrix {
+    pub fn new(config: &MatrixConfig) -> Self {
+        Matrix { config: config.clone(), agent: http::agent() }
+    }
+
+    /// The notification's ID doubles as the transaction ID, so the homeserver ignores a message
+    /// that is sent again.
+    fn send_url(&self, notification: &Notification) -> String {
+        format!(
+            "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+            self.config.homeserver_url.trim_end_matches('/'),
+            percent_encode(&self.config.room_id),
+            percent_encode(&notification.id)
+        )
+    }
+}
+
+impl Notifier for Matrix {
+    fn max_batch_size(&self) -> usize {
+        1
+    }
+
+    fn notify<'a>(
+        &'a self,
+        notifications: &'a [Notification],
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        async move {
+            let headers =
+                [("Authorization".to_owned(), format!("Bearer {}", self.config.access_token))];
+            for notification in notifications {
+                let body = json!({
+                    "msgtype": "m.notice",
+                    "body": describe_entry(&notification.entry),
+                    "format": "org.matrix.custom.html",
+                    "formatted_body": entry_html(&notification.entry),
+                });
+                let url = self.send_url(notification);
+                notifier::send_json(&self.agent, "PUT", &url, &headers, &body.to_string()).await?;
+            }
+
+            Ok(())
+        }
+        .boxed_local()
+    }
+}
+
+/// Encodes everything but unreserved characters, for use in a URL path segment.
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+#[test]
+fn test_matrix_notice() {
+    use crate::{
+        changelist::update_changelist,
+        test_util::{level_info, MockResponse, MockServer},
+    };
+    use async_std::task;
+    use chrono::Utc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Rate limited once; Matrix gives the wait in milliseconds
+    let requests = AtomicUsize::new(0);
+    let server = MockServer::start(move |_| {
+        if requests.fetch_add(1, Ordering::SeqCst) == 0 {
+            MockResponse::from((429, r#"{"retry_after_ms": 10}"#.to_owned()))
+        } else {
+            MockResponse::from((200, r#"{"event_id": "$1"}"#.to_owned()))
+        }
+    });
+
+    let mut changelist = Vec::new();
+    update_changelist(
+        &mut changelist,
+        &mut [level_info("Broken Symmetry", "a", Some(("Seeker", 20000)), Utc::now())],
+        vec![],
+    );
+    let entry = changelist.remove(0);
+    let notification = Notification { id: entry.id(), entry };
+
+    let matrix = Matrix::new(&MatrixConfig {
+        homeserver_url: server.url(),
+        room_id: "!room:example.org".to_owned(),
+        access_token: "token".to_owned(),
+    });
+    task::block_on(matrix.notify(std::slice::from_ref(&notification))).unwrap();
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].method, "PUT");
+    assert_eq!(
+        requests[1].url,
+        format!(
+            "/_matrix/client/v3/rooms/%21room%3Aexample.org/send/m.room.message/{}",
+            notification.id
+        )
+    );
+    let body: serde_json::Value = serde_json::from_str(&requests[1].body).unwrap();
+    assert_eq!(body["msgtype"], "m.notice");
+    assert!(body["formatted_body"].as_str().unwrap().contains("Broken Symmetry"));
+}
@@ -0,0 +1,4 @@
+pub mod discord;
+pub mod mastodon;
+pub mod matrix;
+pub mod webhook;
@@ -0,0 +1,45 @@
+use crate::{
+    config::WebhookConfig,
+    http,
+    notifier::{self, Notification, Notifier},
+};
+use anyhow::Error;
+use futures::{future::LocalBoxFuture, prelude::*};
+
+/// Posts each notification as JSON, with its ID in the `Idempotency-Key` header so that the
+/// receiver can ignore a notification it already got.
+#[derive(Debug)]
+pub struct Webhook {
+    config: WebhookConfig,
+    agent: ureq::Agent,
+}
+
+impl Webhook {
+    pub fn new(config: &WebhookConfig) -> Self {
+        Webhook { config: config.clone(), agent: http::agent() }
+    }
+}
+
+impl Notifier for Webhook {
+    fn max_batch_size(&self) -> usize {
+        1
+    }
+
+    fn notify<'a>(
+        &'a self,
+        notifications: &'a [Notification],
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        async move {
+            for notification in notifications {
+                let mut headers: Vec<_> =
+                    self.config.headers.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+                headers.push(("Idempotency-Key".to_owned(), notification.id.clone()));
+                let body = serde_json::to_string(notification)?;
+                notifier::send_json(&self.agent, "POST", &self.config.url, &headers, &body).await?;
+            }
+
+            Ok(())
+        }
+        .boxed_local()
+    }
+}
@@ -0,0 +1,423 @@
+pub mod impls;
+
+use crate::{
+    config::{NotificationFilter, NotifierConfig},
+    domain::{ChangelistEntry, LevelInfo},
+    http,
+    notifier::impls::{discord::Discord, mastodon::Mastodon, matrix::Matrix, webhook::Webhook},
+    persistence::{
+        impls::file_json::{load_file, write_file_atomically},
+        LoadError,
+    },
+};
+use anyhow::{bail, Context, Error};
+use async_std::task;
+use chrono::{DateTime, Utc};
+use futures::future::LocalBoxFuture;
+use log::{debug, info, warn};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt::Debug,
+    path::Path,
+    time::Duration,
+};
+
+/// How many runs a notification is attempted in before it's given up on.
+const MAX_ATTEMPTS: u32 = 10;
+
+/// How many times a rate limited request is retried within a run.
+const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+
+/// The longest wait for a rate limit to reset within a run. Notifications that would have to wait
+/// longer are left for a later run.
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+
+/// A new changelist entry to deliver.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notification {
+    /// Stays the same when delivery is retried, so that receivers which support it can ignore a
+    /// notification they already got.
+    pub id: String,
+    pub entry: ChangelistEntry,
+}
+
+/// A destination for notifications about new changelist entries.
+pub trait Notifier: Debug {
+    /// The most notifications delivered in one request.
+    fn max_batch_size(&self) -> usize;
+
+    /// Delivers the notifications; an error means none of them were delivered.
+    fn notify<'a>(
+        &'a self,
+        notifications: &'a [Notification],
+    ) -> LocalBoxFuture<'a, Result<(), Error>>;
+}
+
+/// The configured notifiers, each with the filter that decides which entries it gets.
+#[derive(Debug)]
+pub struct Notifiers {
+    routes: Vec<Route>,
+}
+
+#[derive(Debug)]
+struct Route {
+    name: String,
+    filter: NotificationFilter,
+    notifier: Box<dyn Notifier>,
+}
+
+/// The notifications still waiting to be delivered to each notifier. Saved after every delivery
+/// attempt, so that a crash neither drops notifications nor delivers them again, except for one
+/// that was in flight at the time.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DeliveryState {
+    pending: BTreeMap<String, VecDeque<PendingNotification>>,
+
+    /// Notifications given up on after too many attempts, kept for inspection.
+    #[serde(default)]
+    failed: BTreeMap<String, Vec<Notification>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PendingNotification {
+    notification: Notification,
+    attempts: u32,
+}
+
+impl Notifiers {
+    pub fn from_config(configs: &[NotifierConfig]) -> Result<Self, Error> {
+        let mut routes = Vec::new();
+        for config in configs {
+            let notifier: Box<dyn Notifier> =
+                match (&config.discord, &config.webhook, &config.matrix, &config.mastodon) {
+                    (Some(x), None, None, None) => Box::new(Discord::new(x)),
+                    (None, Some(x), None, None) => Box::new(Webhook::new(x)),
+                    (None, None, Some(x), None) => Box::new(Matrix::new(x)),
+                    (None, None, None, Some(x)) => Box::new(Mastodon::new(x)),
+                    _ => bail!(
+                        "Notifier '{}' must have exactly one of `discord`, `webhook`, `matrix` \
+                         and `mastodon`",
+                        config.name
+                    ),
+                };
+            if routes.iter().any(|x: &Route| x.name == config.name) {
+                bail!("There's more than one notifier named '{}'", config.name);
+            }
+
+            routes.push(Route {
+                name: config.name.clone(),
+                filter: config.filter.clone(),
+                notifier,
+            });
+        }
+
+        Ok(Notifiers { routes })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Queues a notification for each new entry with every notifier whose filter it passes.
+    /// `level_infos` provide the entry counts of the entries' leaderboards.
+    pub fn enqueue(
+        &self,
+        state: &mut DeliveryState,
+        entries: &[ChangelistEntry],
+        level_infos: &[LevelInfo],
+    ) {
+        let entry_counts: HashMap<_, _> = level_infos
+            .iter()
+            .map(|level_info| (level_info.level_key(), level_info.leaderboard_response.entry_count))
+            .collect();
+        for route in &self.routes {
+            let notifications = entries
+                .iter()
+                .filter(|entry| {
+                    let entry_count = entry_counts.get(&entry.level_key()).copied().flatten();
+                    route.filter.matches(entry, entry_count)
+                })
+                .map(|entry| PendingNotification {
+                    notification: Notification { id: entry.id(), entry: entry.clone() },
+                    attempts: 0,
+                });
+            state.pending.entry(route.name.clone()).or_default().extend(notifications);
+        }
+    }
+
+    /// Delivers the pending notifications, oldest first and in batches as large as each notifier
+    /// accepts. A notifier that fails is left alone until the next run.
+    pub async fn deliver(&self, state: &mut DeliveryState, state_path: &Path) -> Result<(), Error> {
+        for route in &self.routes {
+            loop {
+                let pending = state.pending.entry(route.name.clone()).or_default();
+                if pending.is_empty() {
+                    break;
+                }
+
+                // Counted before sending, so a notification that crashes the run every time
+                // is still given up on eventually
+                let batch_len = pending.len().min(route.notifier.max_batch_size().max(1));
+                pending.iter_mut().take(batch_len).for_each(|x| x.attempts += 1);
+                let batch: Vec<_> =
+                    pending.iter().take(batch_len).map(|x| x.notification.clone()).collect();
+                state.save(state_path)?;
+
+                let result = route.notifier.notify(&batch).await;
+                let pending = state.pending.get_mut(&route.name).unwrap();
+                let failed = match result {
+                    Ok(()) => {
+                        pending.drain(..batch_len);
+                        false
+                    }
+                    Err(e) => {
+                        warn!("Error delivering notifications to '{}': {:#}", route.name, e);
+                        let (exhausted, remaining): (VecDeque<_>, _) =
+                            pending.drain(..).partition(|x| x.attempts >= MAX_ATTEMPTS);
+                        *pending = remaining;
+                        if !exhausted.is_empty() {
+                            warn!(
+                                "Giving up on {} notifications to '{}'",
+                                exhausted.len(),
+                                route.name
+                            );
+                            state
+                                .failed
+                                .entry(route.name.clone())
+                                .or_default()
+                                .extend(exhausted.into_iter().map(|x| x.notification));
+                        }
+
+                        true
+                    }
+                };
+                state.save(state_path)?;
+
+                if failed {
+                    break;
+                }
+            }
+        }
+        state.pending.retain(|_, pending| !pending.is_empty());
+        state.save(state_path)?;
+
+        let remaining: usize = state.pending.values().map(VecDeque::len).sum();
+        if remaining > 0 {
+            info!("{} notifications will be retried next run", remaining);
+        }
+
+        Ok(())
+    }
+}
+
+impl DeliveryState {
+    /// Loads the state, treating a missing state file as nothing pending.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match load_file(path) {
+            Ok(x) => Ok(x),
+            Err(LoadError::DoesNotExist) => Ok(DeliveryState::default()),
+            Err(e) => Err(e).with_context(|| {
+                format!("Error loading notification delivery state '{}'", path.display())
+            }),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let serialized = serde_json::to_vec(self)?;
+        write_file_atomically(&serialized, path).with_context(|| {
+            format!("Error saving notification delivery state '{}'", path.display())
+        })
+    }
+}
+
+impl NotificationFilter {
+    /// Whether the entry passes the filter. Entries on leaderboards of unknown size don't pass a
+    /// minimum entry count.
+    pub fn matches(&self, entry: &ChangelistEntry, entry_count: Option<u32>) -> bool {
+        let official = entry.workshop_item_id.is_none();
+        let involves = |steam_id: &String| {
+            entry.steam_id_new_recordholder == *steam_id
+                || entry.steam_id_old_recordholder.as_ref() == Some(steam_id)
+        };
+        let enough_entries = match (self.min_entries, entry_count) {
+            (Some(min_entries), Some(entry_count)) => entry_count >= min_entries,
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+
+        self.official.map(|x| x == official).unwrap_or(true)
+            && (self.modes.is_empty()
+                || self.modes.iter().any(|x| x.eq_ignore_ascii_case(&entry.mode)))
+            && (self.steam_ids.is_empty() || self.steam_ids.iter().any(involves))
+            && enough_entries
+    }
+}
+
+/// Sends a JSON request, waiting and retrying while rate limited, and fails unless the response
+/// is a success. Understands the rate limit responses of Discord, Matrix and Mastodon. After a
+/// success that used up the rate limit, waits for it to reset so the next request isn't rejected,
+/// unless that takes longer than `MAX_RATE_LIMIT_WAIT`.
+pub async fn send_json(
+    agent: &ureq::Agent,
+    method: &'static str,
+    url: &str,
+    headers: &[(String, String)],
+    body: &str,
+) -> Result<http::Response, Error> {
+    send(agent, method, url, headers, "application/json", body.as_bytes()).await
+}
+
+/// Like `send_json`, but with a body of any content type.
+pub async fn send(
+    agent: &ureq::Agent,
+    method: &'static str,
+    url: &str,
+    headers: &[(String, String)],
+    content_type: &str,
+    body: &[u8],
+) -> Result<http::Response, Error> {
+    for _ in 0..=MAX_RATE_LIMIT_RETRIES {
+        let response = http::send(
+            agent,
+            method,
+            url.to_owned(),
+            headers.to_vec(),
+            content_type.to_owned(),
+            body.to_vec(),
+        )
+        .await?;
+        match response.status {
+            200..=299 => {
+                if response.headers.get("x-ratelimit-remaining").map(String::as_str) == Some("0") {
+                    match rate_limit_reset(&response) {
+                        Some(wait) if wait <= MAX_RATE_LIMIT_WAIT => task::sleep(wait).await,
+                        _ => {}
+                    }
+                }
+
+                return Ok(response);
+            }
+            429 => {
+                let wait = retry_after(&response).unwrap_or_else(|| Duration::from_secs(1));
+                if wait > MAX_RATE_LIMIT_WAIT {
+                    bail!("Rate limited for the next {:?}", wait);
+                }
+                debug!("Rate limited; waiting {:?}", wait);
+                task::sleep(wait).await;
+            }
+            status => bail!("Responded with status {}: {}", status, response.body),
+        }
+    }
+
+    bail!("Still rate limited after {} retries", MAX_RATE_LIMIT_RETRIES)
+}
+
+/// How long a rate limited response asks to wait: `retry_after` in seconds from Discord,
+/// `retry_after_ms` from Matrix, the standard header, or when the rate limit resets.
+fn retry_after(response: &http::Response) -> Option<Duration> {
+    let body: Option<serde_json::Value> = serde_json::from_str(&response.body).ok();
+    let from_body = body.and_then(|body| {
+        if let Some(ms) = body["retry_after_ms"].as_u64() {
+            Some(Duration::from_millis(ms))
+        } else {
+            body["retry_after"].as_f64().map(|x| Duration::from_secs_f64(x.max(0.)))
+        }
+    });
+
+    from_body
+        .or_else(|| seconds_header(response, "retry-after"))
+        .or_else(|| rate_limit_reset(response))
+}
+
+/// How long until the rate limit resets: Discord gives the seconds left, Mastodon the time it
+/// happens.
+fn rate_limit_reset(response: &http::Response) -> Option<Duration> {
+    seconds_header(response, "x-ratelimit-reset-after").or_else(|| {
+        let reset = response.headers.get("x-ratelimit-reset")?;
+        let reset = DateTime::parse_from_rfc3339(reset).ok()?.with_timezone(&Utc);
+        Some((reset - Utc::now()).to_std().unwrap_or_default())
+    })
+}
+
+fn seconds_header(response: &http::Response, name: &str) -> Option<Duration> {
+    let seconds: f64 = response.headers.get(name)?.parse().ok()?;
+    Some(Duration::from_secs_f64(seconds.max(0.)))
+}
+
+#[test]
+fn test_routing_and_delivery_state() {
+    use crate::test_util::{changelist_of, level_info};
+    use anyhow::format_err;
+    use futures::prelude::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[derive(Debug)]
+    struct Collect {
+        delivered: Rc<RefCell<Vec<String>>>,
+        down: bool,
+    }
+
+    impl Notifier for Collect {
+        fn max_batch_size(&self) -> usize {
+            2
+        }
+
+        fn notify<'a>(
+            &'a self,
+            notifications: &'a [Notification],
+        ) -> LocalBoxFuture<'a, Result<(), Error>> {
+            let result = if self.down {
+                Err(format_err!("down"))
+            } else {
+                let names = notifications.iter().map(|x| x.entry.map_name.clone());
+                self.delivered.borrow_mut().extend(names);
+                Ok(())
+            };
+            future::ready(result).boxed_local()
+        }
+    }
+
+    let delivered: Vec<_> = (0..3).map(|_| Rc::new(RefCell::new(Vec::new()))).collect();
+    let route = |i: usize, name: &str, filter| Route {
+        name: name.to_owned(),
+        filter,
+        notifier: Box::new(Collect { delivered: delivered[i].clone(), down: i == 2 }),
+    };
+    let notifiers = Notifiers {
+        routes: vec![
+            route(0, "all", NotificationFilter::default()),
+            route(1, "popular", NotificationFilter { min_entries: Some(50), ..Default::default() }),
+            route(
+                2,
+                "watched",
+                NotificationFilter { steam_ids: vec!["2".to_owned()], ..Default::default() },
+            ),
+        ],
+    };
+
+    let mut level_infos: Vec<_> = ["a", "b", "c"]
+        .iter()
+        .map(|name| level_info(name, name, Some(("Seeker", 20000)), Utc::now()))
+        .collect();
+    level_infos[1].leaderboard_response.entry_count = Some(100);
+    level_infos[2].leaderboard_response.entries[0].steam_id = 2;
+    let changelist = changelist_of(&[level_infos.clone()]);
+
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = dir.path().join("notification_state.json");
+    let mut state = DeliveryState::default();
+    notifiers.enqueue(&mut state, &changelist, &level_infos);
+    task::block_on(notifiers.deliver(&mut state, &state_path)).unwrap();
+
+    assert_eq!(delivered[0].borrow().len(), 3);
+    assert_eq!(*delivered[1].borrow(), ["b"]);
+    assert!(delivered[2].borrow().is_empty());
+
+    // The failing notifier keeps its notification for the next run
+    let state = DeliveryState::load(&state_path).unwrap();
+    let pending: Vec<_> = state.pending.keys().collect();
+    assert_eq!(pending, ["watched"]);
+    assert_eq!(state.pending["watched"][0].notification.entry.map_name, "c");
+    assert_eq!(state.pending["watched"][0].attempts, 1);
+}
@@ -0,0 +1,21 @@
+use anyhow::{bail, Error};
+use std::str::FromStr;
+
+/// How a report is printed.
+#[derive(Debug, Copy, Clone)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => bail!("unknown output format '{}'; expected 'text' or 'json'", s),
+        }
+    }
+}
@@ -1,14 +1,15 @@
 use crate::{
+    domain::{ChangelistEntry, LevelInfo, LevelPopularity},
     persistence::{LoadError, Persistence},
-    ChangelistEntry, LevelInfo,
 };
 use anyhow::{Context, Error};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
-    fs::File,
+    fs::{self, File},
     io,
     io::Write,
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 use tempfile::NamedTempFile;
 
@@ -16,16 +17,19 @@ use tempfile::NamedTempFile;
 pub struct FileJson {
     query_results_path: PathBuf,
     changelist_path: PathBuf,
+    popularity_path: PathBuf,
 }
 
 impl FileJson {
     pub fn new(
         query_results_path: impl Into<PathBuf>,
         changelist_path: impl Into<PathBuf>,
+        popularity_path: impl Into<PathBuf>,
     ) -> Self {
         FileJson {
             query_results_path: query_results_path.into(),
             changelist_path: changelist_path.into(),
+            popularity_path: popularity_path.into(),
         }
     }
 }
@@ -46,9 +50,24 @@ impl Persistence for &FileJson {
     fn save_changelist(&self, changelist: &[ChangelistEntry]) -> Result<(), Error> {
         save_file(changelist, &self.changelist_path)
     }
+
+    fn load_popularity(&self) -> Result<Vec<LevelPopularity>, LoadError> {
+        load_file(&self.popularity_path)
+    }
+
+    fn save_popularity(&self, popularity: &[LevelPopularity]) -> Result<(), Error> {
+        save_file(popularity, &self.popularity_path)
+    }
+
+    fn last_modified(&self) -> Option<SystemTime> {
+        [&self.query_results_path, &self.changelist_path]
+            .iter()
+            .filter_map(|path| fs::metadata(path).and_then(|x| x.modified()).ok())
+            .max()
+    }
 }
 
-fn load_file<T>(path: &Path) -> Result<T, LoadError>
+pub fn load_file<T>(path: &Path) -> Result<T, LoadError>
 where
     for<'de> T: Deserialize<'de>,
 {
@@ -66,16 +85,24 @@ where
     }
 }
 
-fn save_file<T: Serialize + DeserializeOwned>(data: &[T], path: &Path) -> Result<(), Error> {
+pub fn save_file<T: Serialize + DeserializeOwned>(data: &[T], path: &Path) -> Result<(), Error> {
     let serialized = serde_json::to_vec(&data)?;
 
     // Make sure the JSON we just generated is valid
     let _: Vec<T> =
         serde_json::from_slice(&serialized).context("the JSON we just generated is not valid")?;
 
-    // Atomically update the file using a temporary file
-    let mut tmp = NamedTempFile::new()?;
-    tmp.write_all(&serialized)?;
+    write_file_atomically(&serialized, path)
+}
+
+pub fn write_file_atomically(data: &[u8], path: &Path) -> Result<(), Error> {
+    // Create the temporary file next to the destination so it can be renamed into place
+    let dir = match path.parent() {
+        Some(x) if !x.as_os_str().is_empty() => x,
+        _ => Path::new("."),
+    };
+    let mut tmp = NamedTempFile::new_in(dir)?;
+    tmp.write_all(data)?;
     #[allow(unused_variables)]
     let file = tmp.persist(path)?;
 
@@ -1,7 +1,8 @@
 pub mod impls;
 
-use crate::{ChangelistEntry, LevelInfo};
+use crate::domain::{ChangelistEntry, LevelInfo, LevelPopularity};
 use anyhow::Error;
+use std::time::SystemTime;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -18,4 +19,10 @@ pub trait Persistence {
     fn save_query_results(&self, query_results: &[LevelInfo]) -> Result<(), Error>;
     fn load_changelist(&self) -> Result<Vec<ChangelistEntry>, LoadError>;
     fn save_changelist(&self, changelist: &[ChangelistEntry]) -> Result<(), Error>;
+    fn load_popularity(&self) -> Result<Vec<LevelPopularity>, LoadError>;
+    fn save_popularity(&self, popularity: &[LevelPopularity]) -> Result<(), Error>;
+
+    /// When the query results or the changelist were last saved, if that can be known. Lets
+    /// long-running readers tell when to load them again.
+    fn last_modified(&self) -> Option<SystemTime>;
 }
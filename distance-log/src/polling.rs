@@ -0,0 +1,162 @@
+use crate::{
+    config::PollingConfig,
+    domain::{ChangelistEntry, LevelInfo, LevelPopularity},
+};
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
+use std::collections::HashMap;
+
+/// Decides how often each leaderboard is fetched, based on how recently it saw activity: a new
+/// world record in the changelist, growth in its entry count, or an update to its workshop level.
+/// Active leaderboards are fetched every run, and the longer a leaderboard has been dormant, the
+/// longer it may go between fetches, up to a ceiling. Leaderboards with no known activity at all
+/// are treated as dormant for good.
+#[derive(Debug)]
+pub struct PollingPolicy<'a> {
+    config: &'a PollingConfig,
+    last_activity: HashMap<String, DateTime<Utc>>,
+}
+
+impl<'a> PollingPolicy<'a> {
+    pub fn new(
+        config: &'a PollingConfig,
+        level_infos: &[LevelInfo],
+        changelist: &[ChangelistEntry],
+        popularity: &[LevelPopularity],
+    ) -> Self {
+        let mut last_activity: HashMap<String, DateTime<Utc>> = HashMap::new();
+        let mut record = |leaderboard_name: &str, time: DateTime<Utc>| {
+            let last = last_activity.entry(leaderboard_name.to_owned()).or_insert(time);
+            *last = (*last).max(time);
+        };
+
+        // Changelist entries don't name their leaderboard, so match them up the same way they
+        // were created
+        let leaderboard_names: HashMap<_, _> = level_infos
+            .iter()
+            .map(|level_info| (level_info.level_key(), level_info.leaderboard_name.as_str()))
+            .collect();
+        for entry in changelist {
+            let fetch_time = DateTime::parse_from_rfc2822(&entry.fetch_time);
+            if let (Some(leaderboard_name), Ok(fetch_time)) =
+                (leaderboard_names.get(&entry.level_key()), fetch_time)
+            {
+                record(leaderboard_name, fetch_time.with_timezone(&Utc));
+            }
+        }
+
+        // Without growth in the entry count, the first sample is the earliest the leaderboard is
+        // known to have been dormant since
+        for level in popularity {
+            let grew = level
+                .samples
+                .windows(2)
+                .rev()
+                .find(|pair| pair[1].entry_count > pair[0].entry_count)
+                .map(|pair| pair[1].date);
+            if let Some(date) = grew.or_else(|| level.samples.first().map(|x| x.date)) {
+                record(
+                    &level.leaderboard_name,
+                    Utc.from_utc_datetime(&date.and_time(NaiveTime::MIN)),
+                );
+            }
+        }
+
+        // Updating a workshop level tends to bring players back to it
+        for level_info in level_infos {
+            let time_updated =
+                level_info.workshop_response.as_ref().and_then(|response| response.time_updated);
+            if let Some(time_updated) = time_updated {
+                record(&level_info.leaderboard_name, time_updated);
+            }
+        }
+
+        PollingPolicy { config, last_activity }
+    }
+
+    /// Whether a leaderboard with these previous results should be fetched this run.
+    pub fn is_due(&self, previous: &LevelInfo, now: DateTime<Utc>) -> bool {
+        let max_interval = Duration::hours(self.config.max_staleness_hours);
+        let interval = match self.last_activity.get(&previous.leaderboard_name) {
+            Some(&last_activity) => {
+                let dormant_for = now - last_activity;
+                if dormant_for <= Duration::days(self.config.active_days) {
+                    return true;
+                }
+
+                let interval = dormant_for.num_seconds() as f64 * self.config.interval_fraction;
+                Duration::seconds(interval as i64).min(max_interval)
+            }
+            None => max_interval,
+        };
+
+        now - previous.last_refreshed() >= interval
+    }
+}
+
+#[test]
+fn test_polling_policy() {
+    use crate::{backend::WorkshopResponse, domain::EntryCountSample, test_util::level_info};
+    use chrono::NaiveDate;
+
+    let config = PollingConfig { active_days: 7, interval_fraction: 0.1, max_staleness_hours: 24 };
+    let now = Utc.with_ymd_and_hms(2020, 6, 1, 0, 0, 0).unwrap();
+    let hours_ago = |hours| now - Duration::hours(hours);
+    let sample = |day, entry_count| EntryCountSample {
+        date: NaiveDate::from_ymd_opt(2020, 5, day).unwrap(),
+        entry_count,
+        new_entries_per_day: None,
+    };
+
+    let mut level_infos = [
+        level_info("Active", "active", None, hours_ago(1)),
+        level_info("Quiet", "quiet", None, hours_ago(5)),
+        level_info("Dormant", "dormant", None, hours_ago(23)),
+        level_info("Unknown", "unknown", None, hours_ago(1)),
+        level_info("Stale", "stale", None, hours_ago(24)),
+        level_info("Updated", "updated", None, hours_ago(1)),
+    ];
+    let mut changelist = Vec::new();
+    crate::changelist::update_changelist(
+        &mut changelist,
+        &mut [level_info("Active", "active", Some(("Seeker", 1)), hours_ago(48))],
+        vec![],
+    );
+    level_infos[5].workshop_response = Some(WorkshopResponse {
+        published_file_id: 1,
+        steam_id_owner: 1,
+        file_name: "updated.bytes".to_owned(),
+        title: "Updated".to_owned(),
+        score: 0.,
+        tags: Box::new([]),
+        author_name: "Author".to_owned(),
+        preview_url: String::new(),
+        time_updated: Some(hours_ago(72)),
+        file_ugc_id: None,
+        source: None,
+    });
+    let popularity = [
+        // Dormant for 11 days, so fetched about once a day
+        LevelPopularity {
+            leaderboard_name: "quiet".to_owned(),
+            samples: vec![sample(1, 10), sample(21, 11), sample(31, 11)],
+        },
+        // Dormant for a month, so fetched no less often than the ceiling
+        LevelPopularity {
+            leaderboard_name: "dormant".to_owned(),
+            samples: vec![sample(1, 10), sample(31, 10)],
+        },
+    ];
+    let policy = PollingPolicy::new(&config, &level_infos, &changelist, &popularity);
+
+    assert!(policy.is_due(&level_infos[0], now));
+    assert!(!policy.is_due(&level_infos[1], now));
+    assert!(!policy.is_due(&level_infos[2], now));
+    assert!(policy.is_due(&level_infos[2], now + Duration::hours(1)));
+
+    // Without any known activity, a level is fetched as often as the most dormant ones
+    assert!(!policy.is_due(&level_infos[3], now));
+    assert!(policy.is_due(&level_infos[4], now));
+
+    // A recent workshop update counts as activity
+    assert!(policy.is_due(&level_infos[5], now));
+}
@@ -0,0 +1,72 @@
+use crate::domain::{EntryCountSample, LevelInfo, LevelPopularity};
+use std::{collections::BTreeMap, mem};
+
+/// Adds the entry counts in `level_infos` to `history`. A level that already has a sample for the
+/// day its info was fetched has that sample replaced rather than getting a second one, and info
+/// older than a level's latest sample (e.g. carried over from a previous run) is ignored.
+pub fn record_entry_counts(history: &mut Vec<LevelPopularity>, level_infos: &[LevelInfo]) {
+    let mut by_name: BTreeMap<_, _> = mem::take(history)
+        .into_iter()
+        .map(|level| (level.leaderboard_name.clone(), level))
+        .collect();
+
+    for level_info in level_infos {
+        let entry_count = match level_info.leaderboard_response.entry_count {
+            Some(x) => x,
+            None => continue,
+        };
+        let date = level_info.timestamp.naive_utc().date();
+
+        let samples = &mut by_name
+            .entry(level_info.leaderboard_name.clone())
+            .or_insert_with(|| LevelPopularity {
+                leaderboard_name: level_info.leaderboard_name.clone(),
+                samples: Vec::new(),
+            })
+            .samples;
+
+        match samples.last() {
+            Some(last) if last.date > date => continue,
+            Some(last) if last.date == date => {
+                samples.pop();
+            }
+            _ => {}
+        }
+
+        let new_entries_per_day = samples.last().map(|previous| {
+            let days = (date - previous.date).num_days() as f64;
+            (f64::from(entry_count) - f64::from(previous.entry_count)) / days
+        });
+
+        samples.push(EntryCountSample { date, entry_count, new_entries_per_day });
+    }
+
+    history.extend(by_name.into_values());
+}
+
+#[cfg(test)]
+fn level_info_with_entry_count(timestamp: &str, entry_count: u32) -> LevelInfo {
+    let mut level_info = crate::test_util::level_info(
+        "Broken Symmetry",
+        "Broken Symmetry_1_stable",
+        None,
+        timestamp.parse().unwrap(),
+    );
+    level_info.leaderboard_response.entry_count = Some(entry_count);
+    level_info
+}
+
+#[test]
+fn test_record_entry_counts() {
+    let mut history = Vec::new();
+    record_entry_counts(&mut history, &[level_info_with_entry_count("2020-06-01T10:00:00Z", 100)]);
+    record_entry_counts(&mut history, &[level_info_with_entry_count("2020-06-03T10:00:00Z", 110)]);
+    record_entry_counts(&mut history, &[level_info_with_entry_count("2020-06-03T20:00:00Z", 120)]);
+
+    assert_eq!(history.len(), 1);
+    let samples = &history[0].samples;
+    assert_eq!(samples.len(), 2);
+    assert_eq!(samples[0].new_entries_per_day, None);
+    assert_eq!(samples[1].entry_count, 120);
+    assert_eq!(samples[1].new_entries_per_day, Some(10.0));
+}
@@ -0,0 +1,123 @@
+use crate::{
+    changelist::{add_missing_entries_from, update_changelist},
+    domain::{ChangelistEntry, LevelInfo},
+    dry_run::describe_entry,
+    output::OutputFormat,
+};
+use anyhow::Error;
+use serde_derive::Serialize;
+use std::fmt::Write;
+
+/// How a regenerated changelist differs from the existing one.
+#[derive(Debug, Serialize)]
+pub struct ReconciliationReport {
+    pub snapshots: usize,
+    pub existing_entries: usize,
+    pub rebuilt_entries: usize,
+
+    /// Entries the rebuild produced that the existing changelist lacks.
+    pub missing_from_existing: Vec<ChangelistEntry>,
+
+    /// Entries in the existing changelist that the rebuild didn't produce.
+    pub missing_from_rebuilt: Vec<ChangelistEntry>,
+}
+
+/// Regenerates a changelist by running each snapshot of query results through the same steps as
+/// an update, oldest first. A snapshot's age is taken from the newest level timestamp in it.
+pub fn rebuild(mut snapshots: Vec<Vec<LevelInfo>>) -> Vec<ChangelistEntry> {
+    snapshots.sort_by_key(|snapshot| snapshot.iter().map(|level_info| level_info.timestamp).max());
+
+    let mut changelist = Vec::new();
+    let mut snapshots = snapshots.into_iter();
+    let mut previous = match snapshots.next() {
+        Some(x) => x,
+        None => return changelist,
+    };
+    for snapshot in snapshots {
+        let (mut current, _) = add_missing_entries_from(snapshot, previous.clone());
+        update_changelist(&mut changelist, &mut current, previous);
+        previous = current;
+    }
+
+    changelist
+}
+
+/// Compares the existing changelist with a rebuilt one, matching entries the same way duplicates
+/// are detected during an update.
+pub fn reconcile(
+    snapshots: usize,
+    existing: &[ChangelistEntry],
+    rebuilt: &[ChangelistEntry],
+) -> ReconciliationReport {
+    let missing_from = |entries: &[ChangelistEntry], others: &[ChangelistEntry]| {
+        entries
+            .iter()
+            .filter(|entry| !others.iter().any(|other| entry.is_likely_a_duplicate_of(other)))
+            .cloned()
+            .collect()
+    };
+
+    ReconciliationReport {
+        snapshots,
+        existing_entries: existing.len(),
+        rebuilt_entries: rebuilt.len(),
+        missing_from_existing: missing_from(rebuilt, existing),
+        missing_from_rebuilt: missing_from(existing, rebuilt),
+    }
+}
+
+impl ReconciliationReport {
+    pub fn render(&self, format: OutputFormat) -> Result<String, Error> {
+        match format {
+            OutputFormat::Text => Ok(self.render_text()),
+            OutputFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+        }
+    }
+
+    fn render_text(&self) -> String {
+        let mut s = String::new();
+
+        writeln!(
+            s,
+            "Rebuilt {} entries from {} snapshots; the existing changelist has {} entries.",
+            self.rebuilt_entries, self.snapshots, self.existing_entries
+        )
+        .unwrap();
+        for (heading, entries) in [
+            ("Missing from the existing changelist", &self.missing_from_existing),
+            ("Missing from the rebuilt changelist", &self.missing_from_rebuilt),
+        ]
+        .iter()
+        {
+            writeln!(s, "{} ({}):", heading, entries.len()).unwrap();
+            for entry in entries.iter() {
+                writeln!(s, "  {} at {}", describe_entry(entry), entry.fetch_time).unwrap();
+            }
+        }
+
+        s
+    }
+}
+
+#[test]
+fn test_rebuild() {
+    use crate::test_util::{day, level_info};
+
+    let snapshot = |record, day| vec![level_info("Broken Symmetry", "a", Some(record), day)];
+
+    // Out of order on purpose. The level is missing from the day 2 snapshot, so it's carried over.
+    let snapshots = vec![
+        snapshot(("Runner", 19000), day(3)),
+        snapshot(("Seeker", 20000), day(1)),
+        vec![level_info("Lost Society", "b", None, day(2))],
+        snapshot(("Seeker", 18000), day(4)),
+    ];
+    let rebuilt = rebuild(snapshots);
+    let holders: Vec<_> = rebuilt.iter().map(|x| x.new_recordholder.as_str()).collect();
+    assert_eq!(holders, ["Runner", "Seeker"]);
+
+    let report = reconcile(4, &rebuilt[1..], &rebuilt);
+    assert_eq!(report.missing_from_existing.len(), 1);
+    assert_eq!(report.missing_from_existing[0].new_recordholder, "Runner");
+    assert!(report.missing_from_rebuilt.is_empty());
+}
@@ -0,0 +1,69 @@
+use crate::{
+    backend::{LeaderboardResponse, WorkshopResponse},
+    domain::LevelInfo,
+};
+use chrono::{DateTime, Utc};
+use distance_util::LeaderboardGameMode;
+use std::collections::HashMap;
+
+/// A leaderboard to fetch.
+#[derive(Debug, Clone)]
+pub struct LevelTarget {
+    pub name: String,
+    pub mode: LeaderboardGameMode,
+    pub leaderboard_name: String,
+    pub workshop_response: Option<WorkshopResponse>,
+}
+
+impl LevelTarget {
+    pub fn into_level_info(
+        self,
+        leaderboard_response: LeaderboardResponse,
+        fetched_at: DateTime<Utc>,
+    ) -> LevelInfo {
+        LevelInfo {
+            name: self.name,
+            mode: self.mode,
+            leaderboard_name: self.leaderboard_name,
+            workshop_response: self.workshop_response,
+            leaderboard_response,
+            timestamp: fetched_at,
+            last_refreshed: Some(fetched_at),
+        }
+    }
+}
+
+/// Orders `targets` so that levels that have never been fetched come first, followed by the rest
+/// from the least recently refreshed. Levels at the end are the ones left out when a run can't
+/// fetch everything, and they'll be near the front next time.
+pub fn order_by_staleness(targets: &mut [LevelTarget], previous: &[LevelInfo]) {
+    let last_refreshed: HashMap<&str, DateTime<Utc>> = previous
+        .iter()
+        .map(|level_info| (level_info.leaderboard_name.as_str(), level_info.last_refreshed()))
+        .collect();
+
+    // Stable, so levels refreshed at the same time keep their order
+    targets.sort_by_key(|target| last_refreshed.get(target.leaderboard_name.as_str()).copied());
+}
+
+#[test]
+fn test_order_by_staleness() {
+    use crate::test_util::{day, level_info};
+
+    let target = |leaderboard_name: &str| LevelTarget {
+        name: String::new(),
+        mode: LeaderboardGameMode::Sprint,
+        leaderboard_name: leaderboard_name.to_owned(),
+        workshop_response: None,
+    };
+
+    let mut stale = level_info("", "stale", None, day(3));
+    stale.last_refreshed = Some(day(1));
+    let previous =
+        [level_info("", "fresh", None, day(3)), stale, level_info("", "older", None, day(2))];
+    let mut targets = [target("fresh"), target("stale"), target("new"), target("older")];
+    order_by_staleness(&mut targets, &previous);
+
+    let order: Vec<_> = targets.iter().map(|x| x.leaderboard_name.as_str()).collect();
+    assert_eq!(order, ["new", "stale", "older", "fresh"]);
+}
@@ -0,0 +1,284 @@
+use crate::{
+    domain::ChangelistEntry,
+    server::{Data, Request, Response},
+};
+use anyhow::{Context, Error};
+use serde_json::json;
+use std::{
+    io::{self, Write},
+    net::TcpStream,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tungstenite::{handshake::derive_accept_key, protocol::Role, Message, WebSocket};
+
+/// How often idle connections are sent something, so that closed ones are noticed.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long a WebSocket connection is read for before checking for events to send, so how late an
+/// event can be sent to it.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A changelist entry pushed to live clients. Its ID is the entry's stable ID, which a client
+/// passes back as the last event ID to catch up on what it missed while disconnected.
+#[derive(Debug)]
+pub struct Event {
+    pub id: String,
+
+    /// `{"id": ..., "entry": ...}`
+    pub json: String,
+}
+
+/// Hands newly committed changelist entries to every connected client.
+#[derive(Debug, Default)]
+pub struct Hub {
+    subscribers: Mutex<Vec<Sender<Arc<Event>>>>,
+}
+
+impl Event {
+    pub fn new(entry: &ChangelistEntry) -> Self {
+        let id = entry.id();
+        let json = json!({ "id": id, "entry": entry }).to_string();
+
+        Event { id, json }
+    }
+}
+
+impl Hub {
+    pub fn subscribe(&self) -> Receiver<Arc<Event>> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+
+        receiver
+    }
+
+    /// Sends the entries to every subscriber, dropping the subscribers that disconnected.
+    pub fn publish(&self, entries: &[ChangelistEntry]) {
+        let events: Vec<_> = entries.iter().map(|x| Arc::new(Event::new(x))).collect();
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| events.iter().all(|event| subscriber.send(event.clone()).is_ok()));
+    }
+}
+
+/// The events after the one with the given ID, oldest first. Nothing is replayed without an ID,
+/// or with one that isn't in the changelist.
+pub fn replay(changelist: &[ChangelistEntry], last_event_id: Option<&str>) -> Vec<Event> {
+    let position =
+        last_event_id.and_then(|id| changelist.iter().rposition(|entry| entry.id() == id));
+    match position {
+        Some(i) => changelist[i + 1..].iter().map(Event::new).collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Streams events as Server-Sent Events until the client disconnects.
+pub fn serve_events(
+    request: Request,
+    mut connection: TcpStream,
+    data: &Data,
+    events: Receiver<Arc<Event>>,
+) -> Result<(), Error> {
+    connection.write_all(
+        b"HTTP/1.1 200 OK\r\n\
+          Content-Type: text/event-stream\r\n\
+          Cache-Control: no-cache\r\n\
+          Access-Control-Allow-Origin: *\r\n\
+          Connection: close\r\n\r\n",
+    )?;
+    connection.flush()?;
+
+    stream(data, events, request.last_event_id().as_deref(), |event| {
+        match event {
+            Some(event) => {
+                write!(connection, "id: {}\nevent: record\ndata: {}\n\n", event.id, event.json)?
+            }
+            None => connection.write_all(b": keepalive\n\n")?,
+        }
+        connection.flush()?;
+
+        Ok(())
+    })
+}
+
+/// Streams events as WebSocket text messages until the client disconnects. Between events the
+/// connection is read for up to `POLL_INTERVAL` at a time, which is what answers the client's pings
+/// and closes.
+pub fn serve_websocket(
+    request: Request,
+    mut connection: TcpStream,
+    data: &Data,
+    events: Receiver<Arc<Event>>,
+) -> Result<(), Error> {
+    let key = match request.header("Sec-WebSocket-Key") {
+        Some(x) => x,
+        None => {
+            return Response::error(400, "Expected a WebSocket handshake")
+                .write_to(&mut connection, true)
+                .context("Error responding")
+        }
+    };
+    write!(
+        connection,
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.as_bytes())
+    )?;
+    connection.set_read_timeout(Some(POLL_INTERVAL))?;
+    let last_event_id = request.last_event_id();
+    let mut socket = WebSocket::from_partially_read(connection, request.rest, Role::Server, None);
+
+    let result = (|| -> Result<(), Error> {
+        for event in replay(&data.changelist, last_event_id.as_deref()) {
+            socket.send(Message::Text(event.json))?;
+        }
+
+        let mut last_sent = Instant::now();
+        loop {
+            match socket.read() {
+                // Replies to pings and closes are sent by tungstenite
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e))
+                    if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            loop {
+                match events.try_recv() {
+                    Ok(event) => socket.send(Message::Text(event.json.clone()))?,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+                last_sent = Instant::now();
+            }
+            if last_sent.elapsed() >= KEEPALIVE_INTERVAL {
+                socket.send(Message::Ping(Vec::new()))?;
+                last_sent = Instant::now();
+            }
+        }
+    })();
+
+    match result {
+        Err(e)
+            if matches!(
+                e.downcast_ref(),
+                Some(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed)
+            ) =>
+        {
+            Ok(())
+        }
+        x => x,
+    }
+}
+
+/// Replays the events missed since `last_event_id`, then passes on new events as they come, or
+/// `None` when it's time for a keepalive. `events` must have been subscribed to when `data` was
+/// current, so that nothing falls between the two. Returns when `send` fails, which is how a
+/// disconnected client shows.
+fn stream(
+    data: &Data,
+    events: Receiver<Arc<Event>>,
+    last_event_id: Option<&str>,
+    mut send: impl FnMut(Option<&Event>) -> Result<(), Error>,
+) -> Result<(), Error> {
+    for event in replay(&data.changelist, last_event_id) {
+        send(Some(&event))?;
+    }
+
+    loop {
+        match events.recv_timeout(KEEPALIVE_INTERVAL) {
+            Ok(event) => send(Some(&event))?,
+            Err(RecvTimeoutError::Timeout) => send(None)?,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_replay_and_push() {
+    use crate::test_util::{changelist_of, level_info};
+    use anyhow::format_err;
+    use chrono::Utc;
+
+    let runs: Vec<_> = ["Broken Symmetry", "Lost Society", "Micro-Brew"]
+        .iter()
+        .map(|name| vec![level_info(name, name, Some(("Seeker", 20000)), Utc::now())])
+        .collect();
+    let changelist = changelist_of(&runs);
+    let ids: Vec<_> = changelist.iter().map(|x| x.id()).collect();
+
+    // The client saw the first entry, the second was committed while it was away, and the third
+    // is committed after it reconnects
+    let hub = Hub::default();
+    let data = Data { changelist: changelist[..2].to_vec(), query_results: Vec::new() };
+    let events = hub.subscribe();
+    hub.publish(&changelist[2..]);
+
+    let mut received = Vec::new();
+    let result = stream(&data, events, Some(&ids[0]), |event| {
+        let event = event.ok_or_else(|| format_err!("Expected an event, not a keepalive"))?;
+        let json: serde_json::Value = serde_json::from_str(&event.json)?;
+        assert_eq!(json["id"], event.id);
+        received.push(event.id.clone());
+        if received.len() == 2 {
+            Err(format_err!("Disconnected"))
+        } else {
+            Ok(())
+        }
+    });
+    assert!(result.is_err());
+    assert_eq!(received, &ids[1..]);
+
+    assert!(replay(&changelist, Some("unknown")).is_empty());
+    assert!(replay(&changelist, None).is_empty());
+    assert!(replay(&changelist, Some(&ids[2])).is_empty());
+}
+
+#[test]
+fn test_websocket() {
+    use crate::test_util::{changelist_of, day, level_info};
+    use std::{net::TcpListener, thread};
+    use tungstenite::stream::MaybeTlsStream;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let hub = Hub::default();
+    let events = hub.subscribe();
+    let handler = thread::spawn(move || {
+        let (mut connection, _) = listener.accept().unwrap();
+        let request = Request::read(&mut connection).unwrap();
+        serve_websocket(request, connection, &Data::default(), events)
+    });
+
+    let (mut client, _) = tungstenite::connect(format!("ws://{}/api/ws", address)).unwrap();
+    if let MaybeTlsStream::Plain(stream) = client.get_ref() {
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    }
+    client.send(Message::Ping(b"ping".to_vec())).unwrap();
+    assert_eq!(client.read().unwrap(), Message::Pong(b"ping".to_vec()));
+
+    // Events still go out while the server is waiting for the client
+    let changelist = changelist_of(&[vec![level_info("a", "a", Some(("Seeker", 20000)), day(1))]]);
+    hub.publish(&changelist);
+    match client.read().unwrap() {
+        Message::Text(json) => assert!(json.contains(&changelist[0].id())),
+        x => panic!("Expected an event, got {:?}", x),
+    }
+
+    // Closing is acknowledged, and ends the connection
+    client.close(None).unwrap();
+    loop {
+        match client.read() {
+            Ok(_) => {}
+            Err(tungstenite::Error::ConnectionClosed) => break,
+            Err(e) => panic!("{}", e),
+        }
+    }
+    handler.join().unwrap().unwrap();
+}
@@ -0,0 +1,456 @@
+pub mod live;
+pub mod query;
+
+use crate::{
+    domain::{ChangelistEntry, LevelInfo},
+    persistence::{LoadError, Persistence},
+    server::{
+        live::Hub,
+        query::{is_level, ChangelistQuery, WorldRecord},
+    },
+};
+use anyhow::{bail, Context, Error};
+use log::{debug, info, warn};
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// How often the persistence layer is checked for new data, so that new changelist entries are
+/// pushed to live clients soon after they're saved.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a client may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Requests with a longer head are turned away.
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// Serves the JSON API on `address` until the process is stopped. The changelist and query results
+/// are loaded again on a thread of their own whenever the persistence layer reports they changed,
+/// and the entries added since are pushed to live clients.
+pub fn serve(persistence: impl Persistence + Send, address: &str) -> Result<(), Error> {
+    let listener =
+        TcpListener::bind(address).with_context(|| format!("Error listening on '{}'", address))?;
+    info!("Serving the API on http://{}", address);
+
+    let mut store = Store::new(persistence)?;
+    let current = Mutex::new(store.data.clone());
+    let hub = Hub::default();
+    thread::scope(|scope| {
+        scope.spawn(|| loop {
+            thread::sleep(REFRESH_INTERVAL);
+            match store.refresh() {
+                Ok(new_entries) => {
+                    // Swapped under the lock live clients subscribe under, so that each of them
+                    // gets every entry exactly once
+                    let mut current = current.lock().unwrap();
+                    *current = store.data.clone();
+                    hub.publish(&new_entries);
+                }
+                Err(e) => warn!("Error loading data; serving the previous data: {:#}", e),
+            }
+        });
+
+        let (current, hub) = (&current, &hub);
+        loop {
+            match listener.accept() {
+                Ok((connection, _)) => {
+                    scope.spawn(move || handle_connection(connection, current, hub));
+                }
+                Err(e) => {
+                    warn!("Error accepting a connection: {}", e);
+                    // Such as running out of file descriptors, which takes a while to clear up
+                    thread::sleep(Duration::from_millis(100));
+                }
+            }
+        }
+    })
+}
+
+/// Answers the one request read from `connection`. Runs on a thread of its own, so that a slow
+/// client doesn't hold up the rest.
+fn handle_connection(mut connection: TcpStream, current: &Mutex<Arc<Data>>, hub: &Hub) {
+    let request = connection
+        .set_read_timeout(Some(REQUEST_TIMEOUT))
+        .map_err(Error::from)
+        .and_then(|()| Request::read(&mut connection));
+    let request = match request {
+        Ok(x) => x,
+        Err(e) => {
+            debug!("Error reading a request: {:#}", e);
+            Response::error(400, "Malformed request").write_to(&mut connection, true).ok();
+            return;
+        }
+    };
+
+    let path = request.url.split('?').next().unwrap_or_default().trim_end_matches('/');
+    let live = match path {
+        "/api/events" => Some(live::serve_events as LiveHandler),
+        "/api/ws" => Some(live::serve_websocket as LiveHandler),
+        _ => None,
+    };
+    match live {
+        Some(handler) => {
+            // Subscribed together with taking the data, so that no entry is missed between the
+            // replay and the live events
+            let (data, events) = {
+                let current = current.lock().unwrap();
+                (current.clone(), hub.subscribe())
+            };
+            if let Err(e) = handler(request, connection, &data, events) {
+                debug!("Live connection ended: {:#}", e);
+            }
+        }
+        None => {
+            let data = current.lock().unwrap().clone();
+            let response =
+                handle(&data, &request.method, &request.url, request.header("If-None-Match"));
+            response.write_to(&mut connection, request.method != "HEAD").ok();
+        }
+    }
+}
+
+type LiveHandler =
+    fn(Request, TcpStream, &Data, std::sync::mpsc::Receiver<Arc<live::Event>>) -> Result<(), Error>;
+
+/// The head of a request. Bodies aren't read, as only GET requests are served.
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+
+    /// The path and query string.
+    pub url: String,
+
+    headers: Vec<(String, String)>,
+
+    /// What the client sent after the head.
+    rest: Vec<u8>,
+}
+
+impl Request {
+    fn read(reader: &mut impl Read) -> Result<Self, Error> {
+        let mut buffer = Vec::new();
+        loop {
+            let mut chunk = [0; 4096];
+            let n = reader.read(&mut chunk)?;
+            if n == 0 {
+                bail!("The connection was closed in the middle of the request");
+            }
+            buffer.extend_from_slice(&chunk[..n]);
+
+            let mut headers = [httparse::EMPTY_HEADER; 64];
+            let mut request = httparse::Request::new(&mut headers);
+            if let httparse::Status::Complete(length) = request.parse(&buffer)? {
+                return Ok(Request {
+                    method: request.method.unwrap_or_default().to_owned(),
+                    url: request.path.unwrap_or_default().to_owned(),
+                    headers: request
+                        .headers
+                        .iter()
+                        .map(|x| (x.name.to_owned(), String::from_utf8_lossy(x.value).into_owned()))
+                        .collect(),
+                    rest: buffer[length..].to_vec(),
+                });
+            }
+            if buffer.len() > MAX_HEAD_SIZE {
+                bail!("The request head is longer than {} bytes", MAX_HEAD_SIZE);
+            }
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(x, _)| x.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// From the `Last-Event-ID` header that reconnecting `EventSource`s send, or the
+    /// `last_event_id` parameter, for clients that can't set headers.
+    pub fn last_event_id(&self) -> Option<String> {
+        self.header("Last-Event-ID").map(str::to_owned).or_else(|| {
+            let (_, query_string) = self.url.split_once('?')?;
+            parse_query_string(query_string)
+                .into_iter()
+                .find(|(name, _)| name == "last_event_id")
+                .map(|(_, value)| value)
+        })
+    }
+}
+
+/// Everything the API serves, as loaded at one point in time.
+#[derive(Debug, Default)]
+pub struct Data {
+    pub changelist: Vec<ChangelistEntry>,
+    pub query_results: Vec<LevelInfo>,
+}
+
+#[derive(Debug)]
+struct Store<P> {
+    persistence: P,
+    data: Arc<Data>,
+    last_modified: Option<SystemTime>,
+}
+
+impl<P: Persistence> Store<P> {
+    fn new(persistence: P) -> Result<Self, Error> {
+        let mut store = Store { persistence, data: Arc::default(), last_modified: None };
+        store.load()?;
+
+        Ok(store)
+    }
+
+    /// Loads the data again if it changed since it was last loaded, or if that can't be told.
+    /// Returns the changelist entries that weren't there before.
+    fn refresh(&mut self) -> Result<Vec<ChangelistEntry>, Error> {
+        match (self.persistence.last_modified(), self.last_modified) {
+            (Some(x), Some(y)) if x == y => return Ok(Vec::new()),
+            _ => {}
+        }
+
+        let previous = self.data.clone();
+        self.load()?;
+        let previous_ids: HashSet<_> = previous.changelist.iter().map(|x| x.id()).collect();
+
+        Ok(self
+            .data
+            .changelist
+            .iter()
+            .filter(|x| !previous_ids.contains(&x.id()))
+            .cloned()
+            .collect())
+    }
+
+    fn load(&mut self) -> Result<(), Error> {
+        // Read the time first, so that a save while loading is picked up by the next refresh. Data
+        // that fails to load isn't tried again until it changes.
+        self.last_modified = self.persistence.last_modified();
+        let changelist =
+            or_empty(self.persistence.load_changelist()).context("Error loading the changelist")?;
+        let query_results = or_empty(self.persistence.load_query_results())
+            .context("Error loading the query results")?;
+
+        self.data = Arc::new(Data { changelist, query_results });
+
+        Ok(())
+    }
+}
+
+fn or_empty<T>(result: Result<Vec<T>, LoadError>) -> Result<Vec<T>, LoadError> {
+    match result {
+        Err(LoadError::DoesNotExist) => Ok(Vec::new()),
+        x => x,
+    }
+}
+
+#[derive(Debug)]
+struct Response {
+    status: u16,
+    body: String,
+    etag: Option<String>,
+}
+
+impl Response {
+    /// A successful response, with an ETag derived from the body.
+    fn json(value: &impl Serialize) -> Self {
+        let body = serde_json::to_string(value).unwrap();
+        let etag = format!("\"{:x}\"", Sha256::digest(body.as_bytes()));
+
+        Response { status: 200, body, etag: Some(etag) }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Response { status, body: json!({ "error": message }).to_string(), etag: None }
+    }
+
+    /// Writes the response. The connection is closed after it, so it isn't kept alive.
+    fn write_to(&self, writer: &mut impl Write, with_body: bool) -> io::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            304 => "Not Modified",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "",
+        };
+        write!(writer, "HTTP/1.1 {} {}\r\n", self.status, reason)?;
+        writer
+            .write_all(b"Content-Type: application/json\r\nAccess-Control-Allow-Origin: *\r\n")?;
+        // Clients may keep responses, but must check they're still current
+        writer.write_all(b"Cache-Control: no-cache\r\n")?;
+        if let Some(etag) = &self.etag {
+            write!(writer, "ETag: {}\r\n", etag)?;
+        }
+        if self.status != 304 {
+            write!(writer, "Content-Length: {}\r\n", self.body.len())?;
+        }
+        writer.write_all(b"Connection: close\r\n\r\n")?;
+        if with_body && self.status != 304 {
+            writer.write_all(self.body.as_bytes())?;
+        }
+
+        writer.flush()
+    }
+}
+
+/// Answers a request. `url` is the path and query string.
+///
+/// - `GET /api/changelist` returns a page of changelist entries, newest first; see
+///   `ChangelistQuery` for the parameters.
+/// - `GET /api/levels/{level}/records` returns the current world records on a level, by name or
+///   workshop item ID, optionally restricted to one `mode`.
+/// - `GET /api/players/{steam_id}/records` returns the world records a player currently holds.
+fn handle(data: &Data, method: &str, url: &str, if_none_match: Option<&str>) -> Response {
+    if method != "GET" && method != "HEAD" {
+        return Response::error(405, "Only GET requests are supported");
+    }
+
+    let (path, query_string) = match url.find('?') {
+        Some(i) => (&url[..i], &url[i + 1..]),
+        None => (url, ""),
+    };
+    let params = parse_query_string(query_string);
+    let segments: Vec<_> =
+        path.trim_matches('/').split('/').map(|x| percent_decode(x, false)).collect();
+    let segments: Vec<_> = segments.iter().map(String::as_str).collect();
+
+    let response = match segments.as_slice() {
+        ["api", "changelist"] => match ChangelistQuery::parse(&params) {
+            Ok(query) => Response::json(&query.run(&data.changelist)),
+            Err(e) => Response::error(400, &format!("{:#}", e)),
+        },
+        ["api", "levels", level, "records"] => {
+            let mode = params.iter().find(|(name, _)| name == "mode").map(|(_, x)| x);
+            let levels: Vec<_> = data.query_results.iter().filter(|x| is_level(x, level)).collect();
+            if levels.is_empty() {
+                Response::error(404, "Unknown level")
+            } else {
+                let records: Vec<_> = levels
+                    .into_iter()
+                    .filter(|x| match mode {
+                        Some(mode) => x.mode.to_string().eq_ignore_ascii_case(mode),
+                        None => true,
+                    })
+                    .filter_map(WorldRecord::of)
+                    .collect();
+                Response::json(&records)
+            }
+        }
+        ["api", "players", steam_id, "records"] => {
+            let records: Vec<_> = data
+                .query_results
+                .iter()
+                .filter_map(WorldRecord::of)
+                .filter(|x| x.steam_id == *steam_id)
+                .collect();
+            Response::json(&records)
+        }
+        _ => Response::error(404, "Not found"),
+    };
+
+    match (&response.etag, if_none_match) {
+        (Some(etag), Some(if_none_match)) if etag_matches(etag, if_none_match) => {
+            Response { status: 304, body: String::new(), etag: response.etag }
+        }
+        _ => response,
+    }
+}
+
+/// Whether an `If-None-Match` header lists the ETag. Weak comparison, as HTTP requires here.
+fn etag_matches(etag: &str, if_none_match: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|x| x.trim())
+        .any(|x| x == "*" || x.trim_start_matches("W/") == etag)
+}
+
+fn parse_query_string(s: &str) -> Vec<(String, String)> {
+    s.split('&')
+        .filter(|x| !x.is_empty())
+        .map(|pair| {
+            let mut split = pair.splitn(2, '=');
+            let name = split.next().unwrap_or_default();
+            let value = split.next().unwrap_or_default();
+            (percent_decode(name, true), percent_decode(value, true))
+        })
+        .collect()
+}
+
+/// Decodes `%XX` escapes, and `+` as a space in query strings. Invalid escapes are kept as is.
+fn percent_decode(s: &str, plus_as_space: bool) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' {
+            s.get(i + 1..i + 3).and_then(|x| u8::from_str_radix(x, 16).ok())
+        } else {
+            None
+        };
+        match (escaped, bytes[i]) {
+            (Some(byte), _) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (None, b'+') if plus_as_space => decoded.push(b' '),
+            (None, byte) => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[test]
+fn test_api() {
+    use crate::test_util::{changelist_of, level_info};
+    use chrono::Utc;
+
+    let mut query_results = vec![
+        level_info("Broken Symmetry", "a", Some(("Seeker", 20000)), Utc::now()),
+        level_info("Lost Society", "b", Some(("Runner", 30000)), Utc::now()),
+        level_info("Empty", "c", None, Utc::now()),
+    ];
+    query_results[1].leaderboard_response.entries[0].steam_id = 2;
+    let changelist = changelist_of(&[query_results.clone()]);
+    let data = Data { changelist, query_results };
+
+    let get = |url: &str| {
+        let response = handle(&data, "GET", url, None);
+        let body = serde_json::from_str::<serde_json::Value>(&response.body).ok();
+        (response.status, body)
+    };
+
+    let (status, body) = get("/api/changelist?level=Lost+Society");
+    assert_eq!(status, 200);
+    assert_eq!(body.unwrap()["entries"][0]["map_name"], "Lost Society");
+    assert_eq!(get("/api/changelist?page=zero").0, 400);
+
+    let (status, body) = get("/api/levels/broken%20symmetry/records?mode=Sprint");
+    assert_eq!(status, 200);
+    assert_eq!(body.unwrap()[0]["player_name"], "Seeker");
+    assert_eq!(get("/api/levels/Empty/records").1.unwrap(), json!([]));
+    assert_eq!(get("/api/levels/Nowhere/records").0, 404);
+
+    let (_, body) = get("/api/players/2/records");
+    let body = body.unwrap();
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["level"], "Lost Society");
+
+    // Unchanged responses aren't sent again
+    let response = handle(&data, "GET", "/api/players/1/records", None);
+    let etag = response.etag.unwrap();
+    let response = handle(&data, "GET", "/api/players/1/records", Some(&format!("W/{}", etag)));
+    assert_eq!(response.status, 304);
+    let response = handle(&data, "GET", "/api/players/2/records", Some(&etag));
+    assert_eq!(response.status, 200);
+}
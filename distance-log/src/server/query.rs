@@ -0,0 +1,249 @@
+use crate::domain::{ChangelistEntry, LevelInfo};
+use anyhow::{bail, format_err, Error};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use serde_derive::Serialize;
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// Which changelist entries a request asks for, and which page of them. Every filter that is set
+/// must match.
+#[derive(Debug)]
+pub struct ChangelistQuery {
+    /// A level name, compared case-insensitively, or a workshop item ID.
+    pub level: Option<String>,
+    pub mode: Option<String>,
+
+    /// Entries where this player set or lost the record.
+    pub steam_id: Option<String>,
+
+    /// Entries fetched at or after `since`, and before `until`.
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+
+    /// Only official levels when `true`, only workshop levels when `false`.
+    pub official: Option<bool>,
+
+    /// Starts at 1.
+    pub page: usize,
+    pub per_page: usize,
+}
+
+/// One page of the entries matching a query, newest first.
+#[derive(Debug, Serialize)]
+pub struct ChangelistPage<'a> {
+    /// How many entries match, on all pages.
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+    pub entries: Vec<&'a ChangelistEntry>,
+}
+
+/// The current world record on a leaderboard.
+#[derive(Debug, Clone, Serialize)]
+pub struct WorldRecord<'a> {
+    pub level: &'a str,
+    pub mode: String,
+    pub leaderboard_name: &'a str,
+    pub workshop_item_id: Option<String>,
+    pub steam_id: String,
+    pub player_name: &'a str,
+    pub score: i32,
+    pub record: String,
+    pub entry_count: Option<u32>,
+    pub last_refreshed: DateTime<Utc>,
+}
+
+impl ChangelistQuery {
+    /// Reads the query from query string parameters, ignoring unknown ones.
+    pub fn parse(params: &[(String, String)]) -> Result<Self, Error> {
+        let mut query = ChangelistQuery {
+            level: None,
+            mode: None,
+            steam_id: None,
+            since: None,
+            until: None,
+            official: None,
+            page: 1,
+            per_page: DEFAULT_PAGE_SIZE,
+        };
+        for (name, value) in params {
+            match name.as_str() {
+                "level" => query.level = Some(value.clone()),
+                "mode" => query.mode = Some(value.clone()),
+                "steam_id" => query.steam_id = Some(value.clone()),
+                "since" => query.since = Some(parse_time(value)?),
+                "until" => query.until = Some(parse_time(value)?),
+                "official" => {
+                    query.official = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format_err!("Invalid `official`: '{}'", value))?,
+                    )
+                }
+                "page" => query.page = parse_number(name, value)?,
+                "per_page" => query.per_page = parse_number(name, value)?,
+                _ => {}
+            }
+        }
+        if query.page == 0 {
+            bail!("`page` starts at 1");
+        }
+        if query.per_page == 0 || query.per_page > MAX_PAGE_SIZE {
+            bail!("`per_page` must be between 1 and {}", MAX_PAGE_SIZE);
+        }
+
+        Ok(query)
+    }
+
+    pub fn matches(&self, entry: &ChangelistEntry) -> bool {
+        let fetch_time =
+            || DateTime::parse_from_rfc2822(&entry.fetch_time).ok().map(|x| x.with_timezone(&Utc));
+        let level = match &self.level {
+            Some(level) => {
+                entry.map_name.eq_ignore_ascii_case(level)
+                    || entry.workshop_item_id.as_ref() == Some(level)
+            }
+            None => true,
+        };
+        let mode = match &self.mode {
+            Some(mode) => entry.mode.eq_ignore_ascii_case(mode),
+            None => true,
+        };
+        let steam_id = match &self.steam_id {
+            Some(steam_id) => {
+                entry.steam_id_new_recordholder == *steam_id
+                    || entry.steam_id_old_recordholder.as_ref() == Some(steam_id)
+            }
+            None => true,
+        };
+        let since = match (self.since, fetch_time()) {
+            (Some(since), Some(fetch_time)) => fetch_time >= since,
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        let until = match (self.until, fetch_time()) {
+            (Some(until), Some(fetch_time)) => fetch_time < until,
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        let official = match self.official {
+            Some(official) => entry.workshop_item_id.is_none() == official,
+            None => true,
+        };
+
+        level && mode && steam_id && since && until && official
+    }
+
+    pub fn run<'a>(&self, changelist: &'a [ChangelistEntry]) -> ChangelistPage<'a> {
+        // New entries are appended to the changelist
+        let matching: Vec<_> = changelist.iter().rev().filter(|x| self.matches(x)).collect();
+        let entries = matching
+            .iter()
+            .skip((self.page - 1) * self.per_page)
+            .take(self.per_page)
+            .copied()
+            .collect();
+
+        ChangelistPage { total: matching.len(), page: self.page, per_page: self.per_page, entries }
+    }
+}
+
+impl<'a> WorldRecord<'a> {
+    /// `None` for empty leaderboards.
+    pub fn of(level_info: &'a LevelInfo) -> Option<Self> {
+        let first = level_info.leaderboard_response.entries.first()?;
+
+        Some(WorldRecord {
+            level: &level_info.name,
+            mode: level_info.mode.to_string(),
+            leaderboard_name: &level_info.leaderboard_name,
+            workshop_item_id: level_info
+                .workshop_response
+                .as_ref()
+                .map(|x| x.published_file_id.to_string()),
+            steam_id: first.steam_id.to_string(),
+            player_name: &first.player_name,
+            score: first.score,
+            record: distance_util::format_score(first.score, level_info.mode).unwrap(),
+            entry_count: level_info.leaderboard_response.entry_count,
+            last_refreshed: level_info.last_refreshed(),
+        })
+    }
+}
+
+/// Whether the level is the one named, by name compared case-insensitively or by workshop item
+/// ID.
+pub fn is_level(level_info: &LevelInfo, level: &str) -> bool {
+    level_info.name.eq_ignore_ascii_case(level)
+        || level_info
+            .workshop_response
+            .as_ref()
+            .map(|x| x.published_file_id.to_string() == level)
+            .unwrap_or(false)
+}
+
+/// Accepts RFC 3339 times and dates, which stand for midnight UTC.
+pub fn parse_time(s: &str) -> Result<DateTime<Utc>, Error> {
+    if let Ok(x) = DateTime::parse_from_rfc3339(s) {
+        return Ok(x.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| format_err!("Invalid time '{}'; expected RFC 3339 or YYYY-MM-DD", s))?;
+
+    Ok(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()))
+}
+
+fn parse_number(name: &str, value: &str) -> Result<usize, Error> {
+    value.parse().map_err(|_| format_err!("Invalid `{}`: '{}'", name, value))
+}
+
+#[test]
+fn test_changelist_query() {
+    use crate::test_util::{changelist_of, day, level_info};
+    use chrono::Duration;
+
+    let runs: Vec<_> = ["Broken Symmetry", "Lost Society", "Micro-Brew", "Lost Society"]
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let leaderboard_name = format!("{}{}", name, i);
+            let noon = day(i as u32 + 1) + Duration::hours(12);
+            // Whole seconds apart, so the formatted records differ too
+            let score = 20000 + 1000 * i as i32;
+            vec![level_info(name, &leaderboard_name, Some(("Seeker", score)), noon)]
+        })
+        .collect();
+    let mut changelist = changelist_of(&runs);
+    changelist[2].workshop_item_id = Some("123".to_owned());
+    changelist[3].steam_id_new_recordholder = "76561198000000000".to_owned();
+
+    let query = |params: &[(&str, &str)]| {
+        let params: Vec<_> =
+            params.iter().map(|(k, v)| ((*k).to_owned(), (*v).to_owned())).collect();
+        ChangelistQuery::parse(&params)
+    };
+    let names = |page: ChangelistPage<'_>| -> Vec<String> {
+        page.entries.iter().map(|x| x.map_name.clone()).collect()
+    };
+
+    let page = query(&[("per_page", "2"), ("page", "2")]).unwrap().run(&changelist);
+    assert_eq!(page.total, 4);
+    assert_eq!(names(page), ["Lost Society", "Broken Symmetry"]);
+
+    let page = query(&[("level", "lost society")]).unwrap().run(&changelist);
+    assert_eq!(page.total, 2);
+    assert_eq!(query(&[("level", "123")]).unwrap().run(&changelist).total, 1);
+    assert_eq!(query(&[("official", "false")]).unwrap().run(&changelist).total, 1);
+    assert_eq!(query(&[("mode", "stunt")]).unwrap().run(&changelist).total, 0);
+    assert_eq!(query(&[("steam_id", "76561198000000000")]).unwrap().run(&changelist).total, 1);
+
+    let page = query(&[("since", "2020-05-02"), ("until", "2020-05-03T12:00:00Z")])
+        .unwrap()
+        .run(&changelist);
+    assert_eq!(names(page), ["Lost Society"]);
+
+    assert!(query(&[("page", "0")]).is_err());
+    assert!(query(&[("since", "yesterday")]).is_err());
+    assert!(query(&[("per_page", "100000")]).is_err());
+}
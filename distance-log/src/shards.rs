@@ -0,0 +1,285 @@
+use crate::{
+    config::ShardsConfig,
+    domain::{ChangelistEntry, LevelInfo},
+    feed::fetch_time,
+    persistence::{
+        impls::file_json::{load_file, write_file_atomically},
+        LoadError,
+    },
+    server::query::WorldRecord,
+};
+use anyhow::{Context, Error};
+use chrono::{DateTime, Utc};
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    fs, io,
+    path::Path,
+};
+
+const MANIFEST_FILENAME: &str = "manifest.json";
+
+/// Every shard written, with a hash of its contents, so that clients can fetch only the shards
+/// that changed since they last looked.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub generated: Option<DateTime<Utc>>,
+
+    /// By path, relative to the manifest.
+    pub files: BTreeMap<String, ManifestFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// Hex-encoded SHA-256 of the contents.
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// One leaderboard's current world record and the changelist entries for it, oldest first.
+#[derive(Debug, Serialize)]
+struct LeaderboardShard<'a> {
+    leaderboard_name: &'a str,
+    level: &'a str,
+    mode: String,
+    workshop_item_id: Option<String>,
+    record: Option<WorldRecord<'a>>,
+    history: Vec<&'a ChangelistEntry>,
+}
+
+/// The world records a player holds, and the changelist entries where they set or lost one, oldest
+/// first.
+#[derive(Debug, Default, Serialize)]
+struct PlayerShard<'a> {
+    steam_id: String,
+    name: &'a str,
+    records: Vec<WorldRecord<'a>>,
+    history: Vec<&'a ChangelistEntry>,
+}
+
+/// Writes the changelist split into shards: `latest.json` with the newest entries,
+/// `months/YYYY-MM.json` with the entries fetched in each month, `leaderboards/{name}.json` for
+/// every leaderboard in the query results and `players/{steam_id}.json` for every player who holds
+/// or held a record, followed by `manifest.json`. Shards whose contents didn't change aren't
+/// rewritten, and shards that are no longer produced are removed once the manifest stops listing
+/// them.
+pub fn write_shards(
+    config: &ShardsConfig,
+    changelist: &[ChangelistEntry],
+    level_infos: &[LevelInfo],
+) -> Result<(), Error> {
+    let shards = render_shards(config, changelist, level_infos)?;
+
+    let directory = &config.directory;
+    let manifest_path = directory.join(MANIFEST_FILENAME);
+    let old_manifest = Manifest::load(&manifest_path)?;
+    let mut manifest = Manifest { generated: Some(Utc::now()), files: BTreeMap::new() };
+    let mut written = 0;
+    for (path, contents) in &shards {
+        let file = ManifestFile {
+            sha256: format!("{:x}", Sha256::digest(contents)),
+            size: contents.len() as u64,
+        };
+        let full_path = directory.join(path);
+        if old_manifest.files.get(path) != Some(&file) || !full_path.exists() {
+            if let Some(parent) = full_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            write_file_atomically(contents, &full_path)
+                .with_context(|| format!("Error writing shard '{}'", full_path.display()))?;
+            written += 1;
+        }
+        manifest.files.insert(path.clone(), file);
+    }
+    manifest.save(&manifest_path)?;
+
+    for path in old_manifest.files.keys().filter(|x| !manifest.files.contains_key(*x)) {
+        let full_path = directory.join(path);
+        match fs::remove_file(&full_path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Error removing old shard '{}'", full_path.display()))
+            }
+        }
+    }
+    info!("Wrote {} of {} shards", written, manifest.files.len());
+
+    Ok(())
+}
+
+/// The contents of every shard, by path.
+fn render_shards(
+    config: &ShardsConfig,
+    changelist: &[ChangelistEntry],
+    level_infos: &[LevelInfo],
+) -> Result<BTreeMap<String, Vec<u8>>, Error> {
+    let mut shards = BTreeMap::new();
+
+    let start = changelist.len().saturating_sub(config.latest_entries);
+    shards.insert("latest.json".to_owned(), serde_json::to_vec(&changelist[start..])?);
+
+    let mut months: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for entry in changelist {
+        let month = match fetch_time(entry) {
+            Some(x) => x.format("%Y-%m").to_string(),
+            None => "undated".to_owned(),
+        };
+        months.entry(month).or_default().push(entry);
+    }
+    for (month, entries) in months {
+        shards.insert(format!("months/{}.json", month), serde_json::to_vec(&entries)?);
+    }
+
+    // Changelist entries don't name their leaderboard, so they're matched to one by level key.
+    // Entries for leaderboards that are no longer in the query results only appear in the other
+    // shards.
+    let mut histories: HashMap<_, Vec<_>> = HashMap::new();
+    for entry in changelist {
+        histories.entry(entry.level_key()).or_default().push(entry);
+    }
+    for level_info in level_infos {
+        let shard = LeaderboardShard {
+            leaderboard_name: &level_info.leaderboard_name,
+            level: &level_info.name,
+            mode: level_info.mode.to_string(),
+            workshop_item_id: level_info
+                .workshop_response
+                .as_ref()
+                .map(|x| x.published_file_id.to_string()),
+            record: WorldRecord::of(level_info),
+            history: histories.remove(&level_info.level_key()).unwrap_or_default(),
+        };
+        shards.insert(
+            format!("leaderboards/{}.json", file_name(&level_info.leaderboard_name)),
+            serde_json::to_vec(&shard)?,
+        );
+    }
+
+    let mut players: BTreeMap<_, PlayerShard<'_>> = BTreeMap::new();
+    // Oldest first, so that the latest names seen win
+    for entry in changelist {
+        let new_recordholder = players.entry(entry.steam_id_new_recordholder.clone()).or_default();
+        new_recordholder.name = &entry.new_recordholder;
+        new_recordholder.history.push(entry);
+        if let (Some(steam_id), Some(name)) =
+            (&entry.steam_id_old_recordholder, &entry.old_recordholder)
+        {
+            if *steam_id != entry.steam_id_new_recordholder {
+                let old_recordholder = players.entry(steam_id.clone()).or_default();
+                old_recordholder.name = name;
+                old_recordholder.history.push(entry);
+            }
+        }
+    }
+    for level_info in level_infos {
+        if let Some(record) = WorldRecord::of(level_info) {
+            let player = players.entry(record.steam_id.clone()).or_default();
+            player.name = record.player_name;
+            player.records.push(record);
+        }
+    }
+    for (steam_id, mut shard) in players {
+        shard.steam_id = steam_id.clone();
+        shard.records.sort_by(|a, b| (a.level, &a.mode).cmp(&(b.level, &b.mode)));
+        shards
+            .insert(format!("players/{}.json", file_name(&steam_id)), serde_json::to_vec(&shard)?);
+    }
+
+    Ok(shards)
+}
+
+/// Leaves ASCII letters, digits, `.`, `_` and `-` as they are, and writes every other byte as `~`
+/// followed by its value in hex, which needs no further escaping in URLs.
+fn file_name(s: &str) -> String {
+    let mut file_name = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"._-".contains(&b) {
+            file_name.push(b as char);
+        } else {
+            write!(file_name, "~{:02X}", b).unwrap();
+        }
+    }
+
+    file_name
+}
+
+impl Manifest {
+    /// Loads the manifest, treating a missing file as no shards having been written.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match load_file(path) {
+            Ok(x) => Ok(x),
+            Err(LoadError::DoesNotExist) => Ok(Manifest::default()),
+            Err(e) => {
+                Err(e).with_context(|| format!("Error loading shard manifest '{}'", path.display()))
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let serialized = serde_json::to_vec(self)?;
+        write_file_atomically(&serialized, path)
+            .with_context(|| format!("Error saving shard manifest '{}'", path.display()))
+    }
+}
+
+#[test]
+fn test_shards() {
+    use crate::test_util::{changelist_of, day, level_info, read_json};
+    use chrono::Duration;
+
+    let june = day(31) + Duration::days(1);
+    let broken_symmetry =
+        level_info("Broken Symmetry", "Broken Symmetry", Some(("Seeker", 20000)), day(1));
+    let lost_society = level_info("Lost Society", "Lost Society", Some(("Seeker", 30000)), day(2));
+    let mut beaten = level_info("Broken Symmetry", "Broken Symmetry", Some(("Other", 19000)), june);
+    beaten.leaderboard_response.entries[0].steam_id = 2;
+
+    let changelist = changelist_of(&[
+        vec![broken_symmetry.clone()],
+        vec![broken_symmetry, lost_society.clone()],
+        vec![beaten.clone(), lost_society.clone()],
+    ]);
+    assert_eq!(changelist.len(), 3);
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = ShardsConfig { directory: dir.path().to_owned(), latest_entries: 2 };
+    write_shards(&config, &changelist, &[beaten.clone(), lost_society]).unwrap();
+
+    let read = |path: &str| read_json(dir.path().join(path));
+    assert_eq!(read("latest.json").as_array().unwrap().len(), 2);
+    assert_eq!(read("latest.json")[1]["new_recordholder"], "Other");
+    assert_eq!(read("months/2020-05.json").as_array().unwrap().len(), 2);
+    assert_eq!(read("months/2020-06.json").as_array().unwrap().len(), 1);
+
+    let leaderboard = read("leaderboards/Broken~20Symmetry.json");
+    assert_eq!(leaderboard["record"]["player_name"], "Other");
+    assert_eq!(leaderboard["history"].as_array().unwrap().len(), 2);
+
+    // Seeker set two records and lost one of them
+    let seeker = read("players/1.json");
+    assert_eq!(seeker["name"], "Seeker");
+    assert_eq!(seeker["history"].as_array().unwrap().len(), 3);
+    assert_eq!(seeker["records"].as_array().unwrap().len(), 1);
+    assert_eq!(read("players/2.json")["records"][0]["level"], "Broken Symmetry");
+
+    let manifest = Manifest::load(&dir.path().join(MANIFEST_FILENAME)).unwrap();
+    assert_eq!(manifest.files.len(), 7);
+    for (path, file) in &manifest.files {
+        let contents = fs::read(dir.path().join(path)).unwrap();
+        assert_eq!(file.sha256, format!("{:x}", Sha256::digest(&contents)));
+        assert_eq!(file.size, contents.len() as u64);
+    }
+
+    // Shards that are no longer produced are removed
+    write_shards(&config, &changelist, &[beaten]).unwrap();
+    let manifest = Manifest::load(&dir.path().join(MANIFEST_FILENAME)).unwrap();
+    assert!(!manifest.files.contains_key("leaderboards/Lost~20Society.json"));
+    assert!(!dir.path().join("leaderboards/Lost~20Society.json").exists());
+
+    assert_eq!(file_name("a/b c"), "a~2Fb~20c");
+}
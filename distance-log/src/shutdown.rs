@@ -0,0 +1,48 @@
+use anyhow::{Context, Error};
+use async_std::task;
+use log::warn;
+use std::{
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// How often to check whether a shutdown was requested.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Set once SIGINT or SIGTERM is received, so the run can stop early and keep what it has fetched.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    /// Installs the signal handler. A second signal exits immediately.
+    pub fn install() -> Result<Self, Error> {
+        let shutdown = Shutdown::default();
+        let requested = shutdown.requested.clone();
+        ctrlc::set_handler(move || {
+            if requested.swap(true, Ordering::SeqCst) {
+                process::exit(130);
+            }
+            warn!("Shutting down; send the signal again to exit immediately");
+        })
+        .context("Error installing the signal handler")?;
+
+        Ok(shutdown)
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Completes once a shutdown is requested.
+    pub async fn requested(&self) {
+        while !self.is_requested() {
+            task::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
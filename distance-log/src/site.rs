@@ -0,0 +1,544 @@
+use crate::{
+    config::SiteConfig,
+    domain::{steam_profile_url, workshop_item_url, ChangelistEntry, LevelInfo},
+    feed::{escape, fetch_time, profile_link},
+    persistence::impls::file_json::write_file_atomically,
+    server::query::WorldRecord,
+};
+use anyhow::{Context, Error};
+use chrono::{DateTime, Utc};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Write,
+    fs,
+};
+
+/// How many of the newest changelist entries the index lists.
+const RECENT_ENTRIES: usize = 50;
+
+const STYLE: &str = "body{font-family:sans-serif;max-width:60em;margin:0 auto;padding:0 1em}\
+                     table{border-collapse:collapse}\
+                     th,td{text-align:left;padding:.2em .8em .2em 0}\
+                     img{max-width:100%}";
+
+/// Writes an index of the recent records and of every level, a page per level with the history of
+/// its world record in each mode, a page per player with the records they hold and have held, and
+/// a sitemap of them all. Levels that dropped out of the query results keep their pages, built
+/// from the changelist alone.
+pub fn write_site(
+    config: &SiteConfig,
+    changelist: &[ChangelistEntry],
+    level_infos: &[LevelInfo],
+) -> Result<(), Error> {
+    let site = Site::new(changelist, level_infos);
+
+    let directory = &config.directory;
+    fs::create_dir_all(directory.join("levels"))?;
+    fs::create_dir_all(directory.join("players"))?;
+    let mut pages = vec![("index.html".to_owned(), site.index(config))];
+    for (path, level) in &site.levels {
+        pages.push((path.clone(), site.level_page(config, path, level)));
+    }
+    for (steam_id, player) in &site.players {
+        let path = player_path(steam_id);
+        let page = site.player_page(config, &path, steam_id, player);
+        pages.push((path, page));
+    }
+    pages.push(("sitemap.xml".to_owned(), site.sitemap(config)));
+
+    for (path, page) in pages {
+        let path = directory.join(path);
+        write_file_atomically(page.as_bytes(), &path)
+            .with_context(|| format!("Error writing '{}'", path.display()))?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct Site<'a> {
+    /// By page path.
+    levels: BTreeMap<String, Level<'a>>,
+
+    /// By Steam ID.
+    players: BTreeMap<String, Player<'a>>,
+
+    /// Newest first.
+    changelist: Vec<&'a ChangelistEntry>,
+
+    /// The entry that beat each entry's record, by the beaten entry's ID.
+    beaten_by: HashMap<String, &'a ChangelistEntry>,
+}
+
+#[derive(Debug, Default)]
+struct Level<'a> {
+    name: &'a str,
+    workshop_item_id: Option<String>,
+
+    /// The author's Steam ID and name.
+    author: Option<(String, &'a str)>,
+    preview: Option<&'a str>,
+
+    /// The current world records, by mode.
+    records: BTreeMap<String, WorldRecord<'a>>,
+
+    /// By mode, newest first.
+    history: BTreeMap<&'a str, Vec<&'a ChangelistEntry>>,
+}
+
+#[derive(Debug, Default)]
+struct Player<'a> {
+    name: &'a str,
+    current: Vec<WorldRecord<'a>>,
+
+    /// The records the player set, newest first.
+    history: Vec<&'a ChangelistEntry>,
+}
+
+impl<'a> Site<'a> {
+    fn new(changelist: &'a [ChangelistEntry], level_infos: &'a [LevelInfo]) -> Self {
+        let mut site = Site::default();
+
+        let mut latest = HashMap::new();
+        for entry in changelist {
+            if let Some(previous) = latest.insert(entry.level_key(), entry) {
+                site.beaten_by.insert(previous.id(), entry);
+            }
+        }
+
+        // Newest first, so that levels and players get the latest names seen
+        for entry in changelist.iter().rev() {
+            site.changelist.push(entry);
+
+            let path = level_path(entry.workshop_item_id.as_deref(), &entry.map_name);
+            let level = site.levels.entry(path).or_default();
+            if level.name.is_empty() {
+                level.name = &entry.map_name;
+                level.workshop_item_id = entry.workshop_item_id.clone();
+                level.author = entry.steam_id_author.clone().zip(entry.map_author.as_deref());
+                level.preview = entry.map_preview.as_deref();
+            }
+            level.history.entry(&entry.mode).or_default().push(entry);
+
+            let player = site.players.entry(entry.steam_id_new_recordholder.clone()).or_default();
+            if player.name.is_empty() {
+                player.name = &entry.new_recordholder;
+            }
+            player.history.push(entry);
+        }
+
+        // The query results are newer than any changelist entry
+        for level_info in level_infos {
+            let workshop_item_id =
+                level_info.workshop_response.as_ref().map(|x| x.published_file_id.to_string());
+            let path = level_path(workshop_item_id.as_deref(), &level_info.name);
+            let level = site.levels.entry(path).or_default();
+            level.name = &level_info.name;
+            level.workshop_item_id = workshop_item_id;
+            if let Some(workshop) = &level_info.workshop_response {
+                level.author = Some((workshop.steam_id_owner.to_string(), &workshop.author_name));
+                if !workshop.preview_url.is_empty() {
+                    level.preview = Some(&workshop.preview_url);
+                }
+            }
+
+            if let Some(record) = WorldRecord::of(level_info) {
+                let player = site.players.entry(record.steam_id.clone()).or_default();
+                player.name = record.player_name;
+                player.current.push(record.clone());
+                level.records.insert(record.mode.clone(), record);
+            }
+        }
+        for player in site.players.values_mut() {
+            player.current.sort_by(|a, b| (a.level, &a.mode).cmp(&(b.level, &b.mode)));
+        }
+
+        site
+    }
+
+    fn index(&self, config: &SiteConfig) -> String {
+        let root = "";
+        let mut body = String::new();
+
+        writeln!(body, "<h1>{}</h1>", escape(&config.title)).unwrap();
+        body.push_str("<h2>Recent records</h2>\n");
+        let rows = self
+            .changelist
+            .iter()
+            .take(RECENT_ENTRIES)
+            .map(|entry| {
+                vec![
+                    date(entry),
+                    self.level_link(root, entry.workshop_item_id.as_deref(), &entry.map_name),
+                    escape(&entry.mode),
+                    escape(&entry.record_new),
+                    self.player_link(
+                        root,
+                        &entry.steam_id_new_recordholder,
+                        &entry.new_recordholder,
+                    ),
+                ]
+            })
+            .collect();
+        body.push_str(&table(&["Date", "Level", "Mode", "Record", "Player"], rows));
+
+        let mut levels: Vec<_> = self.levels.iter().collect();
+        levels.sort_by_key(|(_, level)| level.name.to_lowercase());
+        for &(heading, official) in &[("Official levels", true), ("Workshop levels", false)] {
+            writeln!(body, "<h2>{}</h2>\n<ul>", heading).unwrap();
+            for (path, level) in &levels {
+                if level.workshop_item_id.is_none() == official {
+                    writeln!(
+                        body,
+                        "<li><a href=\"{}{}\">{}</a></li>",
+                        root,
+                        escape(path),
+                        escape(level.name)
+                    )
+                    .unwrap();
+                }
+            }
+            body.push_str("</ul>\n");
+        }
+
+        let description = "World records and record history of every level in Distance.";
+        page(config, "index.html", &config.title, description, &body)
+    }
+
+    fn level_page(&self, config: &SiteConfig, path: &str, level: &Level<'_>) -> String {
+        let root = "../";
+        let mut body = String::new();
+
+        writeln!(body, "<h1>{}</h1>", escape(level.name)).unwrap();
+        let mut about = Vec::new();
+        if let Some((steam_id, name)) = &level.author {
+            about.push(format!("By {}.", self.player_link(root, steam_id, name)));
+        }
+        if let Some(workshop_item_id) = &level.workshop_item_id {
+            about.push(format!(
+                "<a href=\"{}\">Steam Workshop page</a>",
+                escape(&workshop_item_url(workshop_item_id))
+            ));
+        }
+        if !about.is_empty() {
+            writeln!(body, "<p>{}</p>", about.join(" ")).unwrap();
+        }
+        if let Some(preview) = level.preview {
+            writeln!(body, "<img src=\"{}\" alt=\"{}\">", escape(preview), escape(level.name))
+                .unwrap();
+        }
+
+        let modes: BTreeSet<&str> =
+            level.records.keys().map(|x| x.as_str()).chain(level.history.keys().copied()).collect();
+        for mode in modes {
+            writeln!(body, "<h2>{}</h2>", escape(mode)).unwrap();
+            if let Some(record) = level.records.get(mode) {
+                write!(
+                    body,
+                    "<p>The world record is {} by {}",
+                    escape(&record.record),
+                    self.player_link(root, &record.steam_id, record.player_name)
+                )
+                .unwrap();
+                if let Some(entry_count) = record.entry_count {
+                    write!(body, ", out of {} entries", entry_count).unwrap();
+                }
+                body.push_str(".</p>\n");
+            }
+            let rows: Vec<_> = level
+                .history
+                .get(mode)
+                .into_iter()
+                .flatten()
+                .map(|entry| {
+                    vec![
+                        date(entry),
+                        escape(&entry.record_new),
+                        self.player_link(
+                            root,
+                            &entry.steam_id_new_recordholder,
+                            &entry.new_recordholder,
+                        ),
+                        entry.improvement().map(|x| escape(&x)).unwrap_or_default(),
+                    ]
+                })
+                .collect();
+            if rows.is_empty() {
+                body.push_str("<p>No new records since tracking began.</p>\n");
+            } else {
+                body.push_str(&table(&["Date", "Record", "Player", "Improvement"], rows));
+            }
+        }
+
+        let description =
+            format!("World records and record history of {} in Distance.", level.name);
+        page(config, path, level.name, &description, &body)
+    }
+
+    fn player_page(
+        &self,
+        config: &SiteConfig,
+        path: &str,
+        steam_id: &str,
+        player: &Player<'_>,
+    ) -> String {
+        let root = "../";
+        let mut body = String::new();
+
+        writeln!(body, "<h1>{}</h1>", escape(player.name)).unwrap();
+        writeln!(
+            body,
+            "<p><a href=\"{}\">Steam profile</a></p>",
+            escape(&steam_profile_url(steam_id))
+        )
+        .unwrap();
+
+        body.push_str("<h2>Current records</h2>\n");
+        let rows: Vec<_> = player
+            .current
+            .iter()
+            .map(|record| {
+                vec![
+                    self.level_link(root, record.workshop_item_id.as_deref(), record.level),
+                    escape(&record.mode),
+                    escape(&record.record),
+                    record.entry_count.map(|x| x.to_string()).unwrap_or_default(),
+                ]
+            })
+            .collect();
+        if rows.is_empty() {
+            body.push_str("<p>None.</p>\n");
+        } else {
+            body.push_str(&table(&["Level", "Mode", "Record", "Entries"], rows));
+        }
+
+        body.push_str("<h2>Record history</h2>\n");
+        let rows: Vec<_> = player
+            .history
+            .iter()
+            .map(|entry| {
+                let status = match self.beaten_by.get(&entry.id()) {
+                    Some(next) => format!(
+                        "Beaten by {} on {}",
+                        self.player_link(
+                            root,
+                            &next.steam_id_new_recordholder,
+                            &next.new_recordholder
+                        ),
+                        date(next)
+                    ),
+                    None => "Standing".to_owned(),
+                };
+                vec![
+                    date(entry),
+                    self.level_link(root, entry.workshop_item_id.as_deref(), &entry.map_name),
+                    escape(&entry.mode),
+                    escape(&entry.record_new),
+                    status,
+                ]
+            })
+            .collect();
+        if rows.is_empty() {
+            body.push_str("<p>No new records since tracking began.</p>\n");
+        } else {
+            body.push_str(&table(&["Date", "Level", "Mode", "Record", "Status"], rows));
+        }
+
+        let description = format!("Distance world records held and set by {}.", player.name);
+        page(config, path, player.name, &description, &body)
+    }
+
+    fn sitemap(&self, config: &SiteConfig) -> String {
+        let mut s = String::new();
+        let mut add = |path: &str, entries: &mut dyn Iterator<Item = &&ChangelistEntry>| {
+            writeln!(s, "  <url>\n    <loc>{}</loc>", escape(&url(config, path))).unwrap();
+            if let Some(last_modified) = entries.filter_map(|x| fetch_time(x)).max() {
+                writeln!(s, "    <lastmod>{}</lastmod>", last_modified.format("%Y-%m-%d")).unwrap();
+            }
+            s.push_str("  </url>\n");
+        };
+
+        add("index.html", &mut self.changelist.iter());
+        for (path, level) in &self.levels {
+            add(path, &mut level.history.values().flatten());
+        }
+        for (steam_id, player) in &self.players {
+            add(&player_path(steam_id), &mut player.history.iter());
+        }
+
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n{}</urlset>\n",
+            s
+        )
+    }
+
+    fn level_link(&self, root: &str, workshop_item_id: Option<&str>, name: &str) -> String {
+        let path = level_path(workshop_item_id, name);
+        format!("<a href=\"{}{}\">{}</a>", root, escape(&path), escape(name))
+    }
+
+    /// Links to the player's page, or to their Steam profile if they have none, which is the case
+    /// for players who only lost records.
+    fn player_link(&self, root: &str, steam_id: &str, name: &str) -> String {
+        if self.players.contains_key(steam_id) {
+            format!("<a href=\"{}{}\">{}</a>", root, escape(&player_path(steam_id)), escape(name))
+        } else {
+            profile_link(steam_id, name)
+        }
+    }
+}
+
+/// Workshop levels are identified by their workshop item ID, official levels by their name.
+fn level_path(workshop_item_id: Option<&str>, name: &str) -> String {
+    format!("levels/{}.html", slugify(workshop_item_id.unwrap_or(name)))
+}
+
+fn player_path(steam_id: &str) -> String {
+    format!("players/{}.html", slugify(steam_id))
+}
+
+/// Lowercase ASCII letters and digits, with every other run of characters replaced by a dash.
+/// Falls back to hex for text without any letters or digits.
+fn slugify(s: &str) -> String {
+    let mut slug = String::new();
+    for c in s.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+
+    if slug.is_empty() {
+        s.bytes().map(|x| format!("{:02x}", x)).collect()
+    } else {
+        slug.to_owned()
+    }
+}
+
+fn url(config: &SiteConfig, path: &str) -> String {
+    let path = if path == "index.html" { "" } else { path };
+    format!("{}/{}", config.base_url.trim_end_matches('/'), path)
+}
+
+fn date(entry: &ChangelistEntry) -> String {
+    match fetch_time(entry) {
+        Some(x) => format_date(x),
+        None => escape(&entry.fetch_time),
+    }
+}
+
+fn format_date(time: DateTime<Utc>) -> String {
+    format!("<time datetime=\"{}\">{}</time>", time.to_rfc3339(), time.format("%Y-%m-%d"))
+}
+
+/// The cells are HTML.
+fn table(headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut s = String::new();
+
+    s.push_str("<table>\n<thead><tr>");
+    for header in headers {
+        write!(s, "<th>{}</th>", header).unwrap();
+    }
+    s.push_str("</tr></thead>\n<tbody>\n");
+    for row in rows {
+        s.push_str("<tr>");
+        for cell in row {
+            write!(s, "<td>{}</td>", cell).unwrap();
+        }
+        s.push_str("</tr>\n");
+    }
+    s.push_str("</tbody>\n</table>\n");
+
+    s
+}
+
+/// `body` is HTML. `path` is relative to the root of the site.
+fn page(config: &SiteConfig, path: &str, title: &str, description: &str, body: &str) -> String {
+    let root = "../".repeat(path.matches('/').count());
+    let title = if title == config.title {
+        escape(title)
+    } else {
+        format!("{} - {}", escape(title), escape(&config.title))
+    };
+
+    format!(
+        "<!DOCTYPE html>\n\
+         <html lang=\"en\">\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{title}</title>\n\
+         <meta name=\"description\" content=\"{description}\">\n\
+         <link rel=\"canonical\" href=\"{canonical}\">\n\
+         <style>{style}</style>\n\
+         </head>\n\
+         <body>\n\
+         <nav><a href=\"{root}index.html\">{site_title}</a></nav>\n\
+         <main>\n\
+         {body}\
+         </main>\n\
+         </body>\n\
+         </html>\n",
+        title = title,
+        description = escape(description),
+        canonical = escape(&url(config, path)),
+        style = STYLE,
+        root = root,
+        site_title = escape(&config.title),
+        body = body,
+    )
+}
+
+#[test]
+fn test_site() {
+    use crate::test_util::{changelist_of, day, level_info};
+    use distance_util::LeaderboardGameMode;
+
+    let record = distance_util::format_score(19000, LeaderboardGameMode::Sprint).unwrap();
+    let first = level_info("Broken Symmetry", "Broken Symmetry", Some(("Seeker", 20000)), day(1));
+    let mut second =
+        level_info("Broken Symmetry", "Broken Symmetry", Some(("Other", 19000)), day(2));
+    second.leaderboard_response.entries[0].steam_id = 2;
+    let changelist = changelist_of(&[vec![first], vec![second.clone()]]);
+    assert_eq!(changelist.len(), 2);
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = SiteConfig {
+        base_url: "https://example.org/records/".to_owned(),
+        title: "Distance world records".to_owned(),
+        directory: dir.path().to_owned(),
+    };
+    write_site(&config, &changelist, &[second]).unwrap();
+
+    let read = |path: &str| fs::read_to_string(dir.path().join(path)).unwrap();
+    let index = read("index.html");
+    assert!(index.contains("<a href=\"levels/broken-symmetry.html\">Broken Symmetry</a>"));
+    assert!(index.contains("<link rel=\"canonical\" href=\"https://example.org/records/\">"));
+
+    // The level's history, newest first
+    let level = read("levels/broken-symmetry.html");
+    assert!(level.find("Other").unwrap() < level.find("Seeker").unwrap());
+    assert!(level.contains(&format!(
+        "The world record is {} by <a href=\"../players/2.html\">Other</a>",
+        record
+    )));
+
+    // Seeker lost their record to Other, who still holds theirs
+    let seeker = read("players/1.html");
+    assert!(seeker.contains("<h2>Current records</h2>\n<p>None.</p>"));
+    assert!(seeker.contains("Beaten by <a href=\"../players/2.html\">Other</a>"));
+    let other = read("players/2.html");
+    assert!(other.contains("Standing"));
+    assert!(other.contains(&format!("<td>{}</td>", record)));
+
+    let sitemap = read("sitemap.xml");
+    assert!(sitemap.contains("<loc>https://example.org/records/players/2.html</loc>"));
+    assert!(sitemap.contains("<lastmod>2020-05-02</lastmod>"));
+
+    assert_eq!(slugify("Micro-Brew"), "micro-brew");
+    assert_eq!(slugify("Le Teleporter"), "le-teleporter");
+    assert_eq!(slugify("???"), "3f3f3f");
+}
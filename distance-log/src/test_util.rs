@@ -0,0 +1,356 @@
+use crate::{
+    backend::{
+        Backend, LeaderboardEntry, LeaderboardResponse, NotFound, Unsupported, WorkshopResponse,
+    },
+    changelist::update_changelist,
+    domain::{ChangelistEntry, LevelInfo},
+};
+use anyhow::{format_err, Error};
+use chrono::{DateTime, TimeZone, Utc};
+use distance_util::LeaderboardGameMode;
+use futures::{future::LocalBoxFuture, prelude::*, stream::LocalBoxStream};
+use serde_json::Value;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: String,
+    pub url: String,
+    pub body: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub body: String,
+    pub headers: Vec<(String, String)>,
+}
+
+/// An HTTP server on a local port that answers every request with whatever `handler` returns, and
+/// remembers the requests it received.
+#[derive(Debug)]
+pub struct MockServer {
+    url: String,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+}
+
+impl MockServer {
+    pub fn start<F, R>(handler: F) -> Self
+    where
+        F: Fn(&MockRequest) -> R + Send + 'static,
+        R: Into<MockResponse>,
+    {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let requests_ = requests.clone();
+        thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).ok();
+                let mock_request = MockRequest {
+                    method: request.method().to_string(),
+                    url: request.url().to_owned(),
+                    body,
+                };
+
+                let mock_response = handler(&mock_request).into();
+                requests_.lock().unwrap().push(mock_request);
+
+                let response = mock_response.headers.iter().fold(
+                    tiny_http::Response::from_string(mock_response.body)
+                        .with_status_code(mock_response.status),
+                    |response, (name, value)| {
+                        let header =
+                            tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes())
+                                .unwrap();
+                        response.with_header(header)
+                    },
+                );
+                request.respond(response).ok();
+            }
+        });
+
+        MockServer { url, requests }
+    }
+
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl MockResponse {
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+}
+
+impl From<(u16, String)> for MockResponse {
+    fn from((status, body): (u16, String)) -> Self {
+        MockResponse { status, body, headers: Vec::new() }
+    }
+}
+
+/// A message received by `MockSmtpServer`.
+#[derive(Debug, Clone, Default)]
+pub struct MockEmail {
+    /// Whether the client authenticated before sending the message.
+    pub authenticated: bool,
+    pub from: String,
+    pub to: Vec<String>,
+
+    /// The message as sent, headers included, with CRLF line endings.
+    pub data: String,
+}
+
+/// An SMTP server on a local port that accepts every message in plain text and remembers it.
+#[derive(Debug)]
+pub struct MockSmtpServer {
+    port: u16,
+    messages: Arc<Mutex<Vec<MockEmail>>>,
+}
+
+impl MockSmtpServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let messages = Arc::new(Mutex::new(Vec::new()));
+
+        let messages_ = messages.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(x) => x,
+                    Err(_) => continue,
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut message = MockEmail::default();
+                let mut reply = |line: &str| stream.write_all(format!("{}\r\n", line).as_bytes());
+
+                reply("220 localhost ESMTP").ok();
+                let mut line = String::new();
+                while let Ok(n) = reader.read_line(&mut line) {
+                    if n == 0 {
+                        break;
+                    }
+                    let command = line.trim_end().to_owned();
+                    line.clear();
+                    let verb = command.split(' ').next().unwrap_or_default().to_uppercase();
+                    let argument = |prefix: &str| {
+                        command[prefix.len()..].trim_matches(|c| c == '<' || c == '>').to_owned()
+                    };
+                    let result = match verb.as_str() {
+                        "EHLO" | "HELO" => {
+                            reply("250-localhost\r\n250-AUTH PLAIN LOGIN\r\n250 8BITMIME")
+                        }
+                        "AUTH" => {
+                            message.authenticated = true;
+                            reply("235 2.7.0 Authentication successful")
+                        }
+                        "MAIL" => {
+                            message.from = argument("MAIL FROM:");
+                            reply("250 OK")
+                        }
+                        "RCPT" => {
+                            message.to.push(argument("RCPT TO:"));
+                            reply("250 OK")
+                        }
+                        "DATA" => {
+                            reply("354 End data with <CR><LF>.<CR><LF>").ok();
+                            while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                                if line == ".\r\n" {
+                                    break;
+                                }
+                                message.data.push_str(&line);
+                                line.clear();
+                            }
+                            line.clear();
+                            messages_.lock().unwrap().push(message.clone());
+                            reply("250 OK")
+                        }
+                        "RSET" | "NOOP" => reply("250 OK"),
+                        "QUIT" => {
+                            reply("221 Bye").ok();
+                            break;
+                        }
+                        _ => reply("502 Command not implemented"),
+                    };
+                    if result.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        MockSmtpServer { port, messages }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn messages(&self) -> Vec<MockEmail> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+/// A backend serving the leaderboards and UGC files it's given, and remembering which files were
+/// downloaded. Leaderboards it wasn't given don't exist; while it's down, every leaderboard request
+/// fails. It can't enumerate workshop levels.
+#[derive(Debug)]
+pub struct MockBackend {
+    name: &'static str,
+    down: bool,
+    leaderboards: HashMap<String, Vec<LeaderboardEntry>>,
+    ugc: HashMap<u64, Vec<u8>>,
+    downloads: RefCell<Vec<u64>>,
+}
+
+impl MockBackend {
+    pub fn new(name: &'static str) -> Self {
+        MockBackend {
+            name,
+            down: false,
+            leaderboards: HashMap::new(),
+            ugc: HashMap::new(),
+            downloads: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn down(mut self) -> Self {
+        self.down = true;
+        self
+    }
+
+    pub fn with_leaderboard(
+        mut self,
+        leaderboard_name: &str,
+        entries: Vec<LeaderboardEntry>,
+    ) -> Self {
+        self.leaderboards.insert(leaderboard_name.to_owned(), entries);
+        self
+    }
+
+    pub fn with_ugc(mut self, ugc_id: u64, data: &[u8]) -> Self {
+        self.ugc.insert(ugc_id, data.to_vec());
+        self
+    }
+
+    /// The UGC IDs of every download, in the order they were requested.
+    pub fn downloads(&self) -> Vec<u64> {
+        self.downloads.borrow().clone()
+    }
+}
+
+impl Backend for MockBackend {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn get_leaderboard_range(
+        &self,
+        leaderboard_name: String,
+        _start: u32,
+        _end: u32,
+    ) -> LocalBoxFuture<'_, Result<LeaderboardResponse, Error>> {
+        let result = match self.leaderboards.get(&leaderboard_name) {
+            _ if self.down => Err(format_err!("{} is down", self.name)),
+            Some(entries) => Ok(LeaderboardResponse {
+                entries: entries.clone().into_boxed_slice(),
+                entry_count: Some(entries.len() as u32),
+                source: Some(self.name.to_owned()),
+            }),
+            None => Err(NotFound { leaderboard_name }.into()),
+        };
+        future::ready(result).boxed_local()
+    }
+
+    fn get_all_workshop_sprint_challenge_stunt_levels(
+        &self,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>> {
+        let error = Unsupported { backend: self.name, operation: "Workshop enumeration" };
+        stream::once(future::ready(future::err(error.into()).boxed_local())).boxed_local()
+    }
+
+    fn get_recently_updated_workshop_levels(
+        &self,
+        _since: DateTime<Utc>,
+    ) -> LocalBoxStream<'_, LocalBoxFuture<'_, Result<WorkshopResponse, Error>>> {
+        self.get_all_workshop_sprint_challenge_stunt_levels()
+    }
+
+    fn download_ugc(&self, ugc_id: u64) -> LocalBoxFuture<'_, Result<Vec<u8>, Error>> {
+        self.downloads.borrow_mut().push(ugc_id);
+        let result = self.ugc.get(&ugc_id).cloned().ok_or_else(|| format_err!("No UGC {}", ugc_id));
+        future::ready(result).boxed_local()
+    }
+}
+
+/// A sprint level whose leaderboard holds just the world record, if any, given as the player name
+/// and score.
+pub fn level_info(
+    name: &str,
+    leaderboard_name: &str,
+    record: Option<(&str, i32)>,
+    timestamp: DateTime<Utc>,
+) -> LevelInfo {
+    LevelInfo {
+        name: name.to_owned(),
+        mode: LeaderboardGameMode::Sprint,
+        leaderboard_name: leaderboard_name.to_owned(),
+        workshop_response: None,
+        leaderboard_response: LeaderboardResponse {
+            entries: record
+                .into_iter()
+                .map(|(player_name, score)| LeaderboardEntry {
+                    steam_id: 1,
+                    global_rank: 1,
+                    score,
+                    player_name: player_name.to_owned(),
+                    ugc_id: None,
+                })
+                .collect(),
+            entry_count: None,
+            source: None,
+        },
+        timestamp,
+        last_refreshed: None,
+    }
+}
+
+/// Midnight UTC on the given day of May 2020.
+pub fn day(day: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2020, 5, day, 0, 0, 0).unwrap()
+}
+
+/// The changelist built up by a run fetching each of `runs` in turn, each compared to the run
+/// before it.
+pub fn changelist_of(runs: &[Vec<LevelInfo>]) -> Vec<ChangelistEntry> {
+    let mut changelist = Vec::new();
+    let mut previous = Vec::new();
+    for run in runs {
+        update_changelist(&mut changelist, &mut run.clone(), previous);
+        previous = run.clone();
+    }
+
+    changelist
+}
+
+pub fn read_json(path: impl AsRef<Path>) -> Value {
+    serde_json::from_slice(&fs::read(path).unwrap()).unwrap()
+}
@@ -0,0 +1,196 @@
+use crate::{
+    archive::{self, ContentStore},
+    backend::{Backend, Unsupported, WorkshopResponse},
+    config::WorkshopArchiveConfig,
+    domain::LevelInfo,
+    persistence::impls::file_json::save_file,
+};
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use futures::prelude::*;
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+};
+
+const MANIFEST_FILENAME: &str = "manifest.json";
+const OBJECTS_DIRECTORY: &str = "objects";
+
+/// One archived version of a workshop level. The level file itself is stored in the archive's
+/// content store under `sha256`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedWorkshopLevel {
+    pub published_file_id: u64,
+    pub file_name: String,
+    pub title: String,
+    pub time_updated: DateTime<Utc>,
+    pub sha256: String,
+    pub archived_at: DateTime<Utc>,
+}
+
+/// Downloads the level file of every workshop level in `level_infos` whose current version isn't
+/// archived yet.
+pub async fn archive_workshop_levels(
+    backend: &dyn Backend,
+    config: &WorkshopArchiveConfig,
+    level_infos: &[LevelInfo],
+) -> Result<(), Error> {
+    const MAX_BUFFER: usize = 16;
+
+    fs::create_dir_all(&config.directory)?;
+    let store = ContentStore::new(config.directory.join(OBJECTS_DIRECTORY));
+    let manifest_path = config.directory.join(MANIFEST_FILENAME);
+    let mut manifest: Vec<ArchivedWorkshopLevel> = archive::load_index(&manifest_path)?;
+
+    let archived: HashSet<_> =
+        manifest.iter().map(|level| (level.published_file_id, level.time_updated)).collect();
+
+    // A level with several game modes appears once per mode
+    let workshop_responses: BTreeMap<_, _> = level_infos
+        .iter()
+        .filter_map(|level_info| level_info.workshop_response.as_ref())
+        .map(|workshop_response| (workshop_response.published_file_id, workshop_response))
+        .collect();
+    // Backends that don't report when a level was updated or which file it has can't archive it
+    let mut unsupported = BTreeMap::<_, usize>::new();
+    let missing: Vec<_> = workshop_responses
+        .into_iter()
+        .filter_map(|(published_file_id, response)| {
+            let (time_updated, file_ugc_id) = match (response.time_updated, response.file_ugc_id) {
+                (Some(time_updated), Some(file_ugc_id)) => (time_updated, file_ugc_id),
+                _ => {
+                    *unsupported
+                        .entry(response.source.as_deref().unwrap_or("unknown"))
+                        .or_default() += 1;
+                    return None;
+                }
+            };
+            if archived.contains(&(published_file_id, time_updated)) {
+                None
+            } else {
+                Some((response, time_updated, file_ugc_id))
+            }
+        })
+        .collect();
+    for (backend, count) in unsupported {
+        warn!(
+            "Skipping {} workshop levels from {}: archive unsupported by backend (no update time or \
+             file UGC ID)",
+            count, backend
+        );
+    }
+
+    let downloads: Vec<_> = stream::iter(missing)
+        .map(|(response, time_updated, file_ugc_id)| async move {
+            (response, time_updated, backend.download_ugc(file_ugc_id).await)
+        })
+        .buffer_unordered(MAX_BUFFER)
+        .collect()
+        .await;
+
+    let mut archived_count = 0;
+    let mut unsupported_download = None;
+    for (response, time_updated, download) in downloads {
+        let WorkshopResponse { published_file_id, file_name, title, .. } = response;
+        let data = match download {
+            Ok(x) => x,
+            Err(e) if e.is::<Unsupported>() => {
+                unsupported_download = Some(e);
+                continue;
+            }
+            Err(e) => {
+                warn!(
+                    "Couldn't download the level file of workshop item {}: {}",
+                    published_file_id, e
+                );
+                continue;
+            }
+        };
+
+        manifest.push(ArchivedWorkshopLevel {
+            published_file_id: *published_file_id,
+            file_name: file_name.clone(),
+            title: title.clone(),
+            time_updated,
+            sha256: store.insert(&data)?,
+            archived_at: Utc::now(),
+        });
+        archived_count += 1;
+    }
+
+    if let Some(e) = unsupported_download {
+        warn!("Couldn't download workshop level files: {}", e);
+    }
+    info!("Archived {} new or updated workshop levels", archived_count);
+    save_file(&manifest, &manifest_path)
+}
+
+#[test]
+fn test_archive_workshop_levels() {
+    use crate::test_util::{day, level_info, MockBackend};
+    use async_std::task;
+    use std::path::PathBuf;
+
+    // UGC 0 fails to download
+    let new_backend = || {
+        MockBackend::new("mock")
+            .with_ugc(10, b"file 10")
+            .with_ugc(11, b"file 11")
+            .with_ugc(30, b"file 30")
+    };
+    let workshop_level = |published_file_id, time_updated, file_ugc_id| {
+        let mut level_info = level_info("Level", "Level", None, day(10));
+        level_info.workshop_response = Some(WorkshopResponse {
+            published_file_id,
+            steam_id_owner: 1,
+            file_name: format!("level {}.bytes", published_file_id),
+            title: format!("Level {}", published_file_id),
+            score: 0.,
+            tags: Box::new([]),
+            author_name: "Author".to_owned(),
+            preview_url: String::new(),
+            time_updated: Some(day(time_updated)),
+            file_ugc_id: Some(file_ugc_id),
+            source: None,
+        });
+        level_info
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = WorkshopArchiveConfig { directory: dir.path().to_owned() };
+    let manifest = || -> Vec<ArchivedWorkshopLevel> {
+        archive::load_index(&dir.path().join(MANIFEST_FILENAME)).unwrap()
+    };
+    let object_path = |sha256: &str| -> PathBuf {
+        dir.path().join(OBJECTS_DIRECTORY).join(&sha256[..2]).join(sha256)
+    };
+
+    // The same level in two modes is only downloaded once, and failed downloads aren't recorded
+    let backend = new_backend();
+    let level_infos = [workshop_level(1, 1, 10), workshop_level(1, 1, 10), workshop_level(2, 1, 0)];
+    task::block_on(archive_workshop_levels(&backend, &config, &level_infos)).unwrap();
+    assert_eq!(backend.downloads(), [10, 0]);
+    let archived = manifest();
+    assert_eq!(archived.len(), 1);
+    assert_eq!(archived[0].published_file_id, 1);
+    assert_eq!(archived[0].file_name, "level 1.bytes");
+    assert_eq!(archived[0].time_updated, day(1));
+    assert_eq!(fs::read(object_path(&archived[0].sha256)).unwrap(), b"file 10");
+
+    // Levels whose `time_updated` didn't change are skipped; updated ones are archived again
+    let backend = new_backend();
+    let level_infos = [workshop_level(1, 1, 10), workshop_level(3, 2, 30)];
+    task::block_on(archive_workshop_levels(&backend, &config, &level_infos)).unwrap();
+    assert_eq!(backend.downloads(), [30]);
+
+    let backend = new_backend();
+    task::block_on(archive_workshop_levels(&backend, &config, &[workshop_level(1, 3, 11)]))
+        .unwrap();
+    assert_eq!(backend.downloads(), [11]);
+    let archived = manifest();
+    assert_eq!(archived.len(), 3);
+    assert_eq!(archived[2].time_updated, day(3));
+    assert_eq!(fs::read(object_path(&archived[2].sha256)).unwrap(), b"file 11");
+}
@@ -0,0 +1,109 @@
+use crate::{
+    backend::WorkshopResponse,
+    persistence::{
+        impls::file_json::{load_file, write_file_atomically},
+        LoadError,
+    },
+};
+use anyhow::{Context, Error};
+use chrono::{DateTime, Duration, Utc};
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path};
+
+/// Every workshop level known from previous enumerations, so that a run only needs to ask for the
+/// levels created or updated since the last one.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WorkshopIndex {
+    /// When the last complete enumeration of every workshop level started.
+    pub last_full_enumeration: Option<DateTime<Utc>>,
+
+    /// When the last enumeration of any kind started.
+    pub last_enumeration: Option<DateTime<Utc>>,
+
+    pub levels: Vec<WorkshopResponse>,
+}
+
+impl WorkshopIndex {
+    /// Loads the index, treating a missing index as an empty one.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match load_file(path) {
+            Ok(x) => Ok(x),
+            Err(LoadError::DoesNotExist) => Ok(WorkshopIndex::default()),
+            Err(e) => {
+                Err(e).with_context(|| format!("Error loading workshop index '{}'", path.display()))
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let serialized = serde_json::to_vec(self)?;
+        write_file_atomically(&serialized, path)
+            .with_context(|| format!("Error saving workshop index '{}'", path.display()))
+    }
+
+    pub fn needs_full_enumeration(&self, now: DateTime<Utc>, interval: Duration) -> bool {
+        match self.last_full_enumeration {
+            Some(x) => now - x >= interval,
+            None => true,
+        }
+    }
+
+    /// Adds new levels and replaces the known versions of updated ones.
+    pub fn merge(&mut self, levels: Vec<WorkshopResponse>) {
+        let mut positions: HashMap<_, _> =
+            self.levels.iter().enumerate().map(|(i, level)| (level.published_file_id, i)).collect();
+        for level in levels {
+            match positions.get(&level.published_file_id) {
+                Some(&i) => self.levels[i] = level,
+                None => {
+                    positions.insert(level.published_file_id, self.levels.len());
+                    self.levels.push(level);
+                }
+            }
+        }
+    }
+
+    /// Replaces the known levels with the result of a complete enumeration that started at
+    /// `started`, dropping levels that have since been removed from the workshop.
+    pub fn replace(&mut self, levels: Vec<WorkshopResponse>, started: DateTime<Utc>) {
+        self.levels.clear();
+        self.merge(levels);
+        self.last_full_enumeration = Some(started);
+    }
+}
+
+#[test]
+fn test_workshop_index() {
+    use crate::test_util::day;
+
+    let level = |published_file_id, title: &str| WorkshopResponse {
+        published_file_id,
+        steam_id_owner: 1,
+        file_name: format!("{}.bytes", title),
+        title: title.to_owned(),
+        score: 0.,
+        tags: Box::new(["Sprint".to_owned()]),
+        author_name: String::new(),
+        preview_url: String::new(),
+        time_updated: None,
+        file_ugc_id: None,
+        source: None,
+    };
+    let titles = |index: &WorkshopIndex| -> Vec<String> {
+        index.levels.iter().map(|x| x.title.clone()).collect()
+    };
+
+    let mut index = WorkshopIndex::default();
+    assert!(index.needs_full_enumeration(day(1), Duration::hours(24)));
+
+    index.replace(vec![level(1, "a"), level(2, "b")], day(1));
+    index.merge(vec![level(2, "b2"), level(3, "c")]);
+    assert_eq!(titles(&index), ["a", "b2", "c"]);
+    assert!(!index.needs_full_enumeration(day(1) + Duration::hours(23), Duration::hours(24)));
+    assert!(index.needs_full_enumeration(day(2), Duration::hours(24)));
+
+    // Levels missing from a full enumeration are dropped
+    index.replace(vec![level(3, "c")], day(2));
+    assert_eq!(titles(&index), ["c"]);
+    assert_eq!(index.last_full_enumeration, Some(day(2)));
+}